@@ -0,0 +1,125 @@
+use crate::taps::Taps;
+use dnstap::{MessageType, SocketProtocol};
+use parser::packet::DnsPacket;
+use std::net::SocketAddr;
+
+// Responses are truncated past this size even if the requester advertises a larger payload
+pub const MAX_RESPONSE_SIZE: usize = 4096;
+
+// Answers a message received from a client over either transport with the response `respond`
+// builds for the request, None leaving the message unanswered
+pub fn answer(
+    taps: &Taps,
+    protocol: SocketProtocol,
+    message: &[u8],
+    src: SocketAddr,
+    local: SocketAddr,
+    verbose: bool,
+    respond: impl FnOnce(&DnsPacket) -> DnsPacket,
+) -> Option<Vec<u8>> {
+    taps.query(protocol, MessageType::ClientQuery, src, local, message);
+
+    let request = match DnsPacket::parse(message) {
+        Ok(packet) => packet,
+        Err(e) => {
+            println!("Error parsing packet: {:?}", e);
+            return None;
+        }
+    };
+    if verbose {
+        println!("Received DNS request:\n{}", &request);
+    }
+
+    let response = respond(&request);
+
+    // Only UDP responses are bound by the payload size advertised by the requester
+    let (mut bytes, limit) = match protocol {
+        SocketProtocol::Tcp => (vec![0; tcp::MAX_MESSAGE_SIZE], tcp::MAX_MESSAGE_SIZE),
+        _ => (vec![0; MAX_RESPONSE_SIZE], request.max_payload_size()),
+    };
+    let size = match response.write_with_limit(&mut bytes, limit) {
+        Ok(size) => size,
+        Err(e) => {
+            println!("Error serializing packet: {}", e);
+            return None;
+        }
+    };
+    bytes.truncate(size);
+
+    taps.response(protocol, MessageType::ClientResponse, src, local, &bytes);
+    if verbose {
+        println!("Sent DNS response:\n{}", &response);
+    }
+    Some(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parser::packet::DEFAULT_UDP_PAYLOAD_SIZE;
+    use parser::resources::name::DnsName;
+    use parser::resources::query::DnsQuery;
+    use parser::resources::record::DnsRecord;
+    use parser::resources::{DnsClass, DnsRecordType};
+    use std::net::Ipv4Addr;
+
+    fn answer_with_records(protocol: SocketProtocol, message: &[u8]) -> Option<DnsPacket> {
+        let taps = Taps {
+            capture: None,
+            dnstap: None,
+        };
+        let address = SocketAddr::from(([127, 0, 0, 1], 53));
+        let bytes = answer(
+            &taps,
+            protocol,
+            message,
+            address,
+            address,
+            false,
+            |request| {
+                let mut response = request.reply();
+                response.header.flags.response = true;
+                response.add_answers(
+                    (0..100)
+                        .map(|i| DnsRecord::A {
+                            name: DnsName::from("example.com"),
+                            class: DnsClass::IN,
+                            ttl: 300,
+                            address: Ipv4Addr::new(10, 0, 0, i),
+                        })
+                        .collect(),
+                );
+                response
+            },
+        )?;
+        Some(DnsPacket::parse(&bytes).unwrap())
+    }
+
+    #[test]
+    fn test_answer_limits_udp_responses() {
+        let mut request = DnsPacket::new();
+        request.add_query(DnsQuery {
+            name: DnsName::from("example.com"),
+            record_type: DnsRecordType::A.into(),
+            class: DnsClass::IN.into(),
+        });
+        let message = request.serialize().unwrap();
+
+        let response = answer_with_records(SocketProtocol::Udp, &message).unwrap();
+        assert!(response.header.flags.truncated);
+        assert!(response.wire_len() <= DEFAULT_UDP_PAYLOAD_SIZE);
+
+        let response = answer_with_records(SocketProtocol::Tcp, &message).unwrap();
+        assert!(!response.header.flags.truncated);
+        assert_eq!(100, response.answers().len());
+        assert_eq!(
+            request.header.transaction_id,
+            response.header.transaction_id
+        );
+    }
+
+    #[test]
+    fn test_answer_ignores_malformed_messages() {
+        assert!(answer_with_records(SocketProtocol::Udp, b"garbage").is_none());
+    }
+}
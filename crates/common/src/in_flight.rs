@@ -0,0 +1,43 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// A slot among the queries being resolved or forwarded, freed when dropped
+pub struct InFlight<'a>(&'a AtomicUsize);
+
+impl<'a> InFlight<'a> {
+    pub fn acquire(counter: &'a AtomicUsize, max: usize) -> Option<Self> {
+        counter
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| {
+                if n < max {
+                    Some(n + 1)
+                } else {
+                    None
+                }
+            })
+            .ok()
+            .map(|_| Self(counter))
+    }
+}
+
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_in_flight() {
+        let counter = AtomicUsize::new(0);
+        let first = InFlight::acquire(&counter, 2);
+        let second = InFlight::acquire(&counter, 2);
+        assert!(first.is_some() && second.is_some());
+        assert!(InFlight::acquire(&counter, 2).is_none());
+
+        drop(first);
+        assert!(InFlight::acquire(&counter, 2).is_some());
+        assert_eq!(1, counter.load(Ordering::SeqCst));
+    }
+}
@@ -0,0 +1,9 @@
+mod answer;
+mod in_flight;
+mod taps;
+mod upstream;
+
+pub use answer::{answer, MAX_RESPONSE_SIZE};
+pub use in_flight::InFlight;
+pub use taps::Taps;
+pub use upstream::{attempt_timeout, receive_response, MAX_ATTEMPT_TIMEOUT};
@@ -0,0 +1,88 @@
+use dnstap::{Logger, Message, MessageType, SocketProtocol};
+use pcap::Capture;
+use std::io::Result;
+use std::net::SocketAddr;
+use std::path::Path;
+
+// Where the traffic is recorded with --capture and --dnstap
+pub struct Taps {
+    pub capture: Option<Capture>,
+    pub dnstap: Option<Logger>,
+}
+
+impl Taps {
+    // The dnstap messages go to the collector listening on `dnstap_socket` rather than to the
+    // `dnstap` file when both are given
+    pub fn open(
+        capture: Option<&Path>,
+        dnstap: Option<&Path>,
+        dnstap_socket: Option<&Path>,
+    ) -> Result<Self> {
+        Ok(Self {
+            capture: capture.map(Capture::create).transpose()?,
+            dnstap: match dnstap_socket {
+                Some(path) => Some(Logger::connect(path)?),
+                None => dnstap.map(Logger::create).transpose()?,
+            },
+        })
+    }
+
+    // The query address is the side that sent the query
+    pub fn query(
+        &self,
+        protocol: SocketProtocol,
+        message_type: MessageType,
+        query_address: SocketAddr,
+        response_address: SocketAddr,
+        bytes: &[u8],
+    ) {
+        self.record(protocol, query_address, response_address, bytes);
+        self.log(Message {
+            socket_protocol: Some(protocol),
+            ..Message::query(message_type, query_address, response_address, bytes)
+        });
+    }
+
+    pub fn response(
+        &self,
+        protocol: SocketProtocol,
+        message_type: MessageType,
+        query_address: SocketAddr,
+        response_address: SocketAddr,
+        bytes: &[u8],
+    ) {
+        self.record(protocol, response_address, query_address, bytes);
+        self.log(Message {
+            socket_protocol: Some(protocol),
+            ..Message::response(message_type, query_address, response_address, bytes)
+        });
+    }
+
+    fn record(
+        &self,
+        protocol: SocketProtocol,
+        source: SocketAddr,
+        destination: SocketAddr,
+        bytes: &[u8],
+    ) {
+        let capture = match &self.capture {
+            Some(capture) => capture,
+            None => return,
+        };
+        let result = match protocol {
+            SocketProtocol::Tcp => capture.record_tcp(source, destination, bytes),
+            _ => capture.record(source, destination, bytes),
+        };
+        if let Err(e) = result {
+            println!("Error recording packet: {}", e);
+        }
+    }
+
+    fn log(&self, message: Message) {
+        if let Some(dnstap) = &self.dnstap {
+            if let Err(e) = dnstap.log(message) {
+                println!("Error logging dnstap message: {}", e);
+            }
+        }
+    }
+}
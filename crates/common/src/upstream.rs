@@ -0,0 +1,119 @@
+use parser::packet::DnsPacket;
+use std::io::{Error, ErrorKind, Result};
+use std::net::{SocketAddr, UdpSocket};
+use std::time::{Duration, Instant};
+
+// Attempts are given at most this long however many rounds they went through, so that the slot
+// of the query is given back
+pub const MAX_ATTEMPT_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Waits for the response to `request`, dropping the datagrams from other addresses and the ones
+// that don't echo the ID and the question, so that they can't be used to poison a cache
+// https://datatracker.ietf.org/doc/html/rfc5452#section-9.1
+pub fn receive_response(
+    socket: &UdpSocket,
+    server: SocketAddr,
+    request: &DnsPacket,
+    timeout: Duration,
+    mut tap: impl FnMut(&[u8]),
+) -> Result<DnsPacket> {
+    let deadline = Instant::now() + timeout;
+    let mut buffer = [0; 512];
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err(Error::new(ErrorKind::TimedOut, "No response received"));
+        }
+        socket.set_read_timeout(Some(remaining))?;
+
+        let (size, src) = socket.recv_from(&mut buffer)?;
+        if src != server {
+            continue;
+        }
+        tap(&buffer[..size]);
+        match DnsPacket::parse(&buffer[..size]) {
+            Ok(response) if response.is_response_to(request) => return Ok(response),
+            // The server answered with a message that can't be read, which waiting for another
+            // one won't fix
+            Err(_) if buffer[..size].starts_with(&request.header.transaction_id.to_be_bytes()) => {
+                return Err(Error::new(ErrorKind::InvalidData, "Malformed response"));
+            }
+            _ => continue,
+        }
+    }
+}
+
+// Timeout of an attempt, doubled after each round over the servers
+pub fn attempt_timeout(base: Duration, round: u32) -> Duration {
+    base.saturating_mul(1 << round.min(16))
+        .min(MAX_ATTEMPT_TIMEOUT)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parser::resources::name::DnsName;
+    use parser::resources::query::DnsQuery;
+    use parser::resources::{DnsClass, DnsRecordType};
+
+    #[test]
+    fn test_attempt_timeout() {
+        let base = Duration::from_millis(1500);
+        assert_eq!(base, attempt_timeout(base, 0));
+        assert_eq!(Duration::from_secs(3), attempt_timeout(base, 1));
+        assert_eq!(Duration::from_secs(6), attempt_timeout(base, 2));
+        assert_eq!(MAX_ATTEMPT_TIMEOUT, attempt_timeout(base, 3));
+        assert_eq!(MAX_ATTEMPT_TIMEOUT, attempt_timeout(base, u32::MAX));
+    }
+
+    #[test]
+    fn test_receive_response() {
+        let socket = UdpSocket::bind(("127.0.0.1", 0)).unwrap();
+        let server = UdpSocket::bind(("127.0.0.1", 0)).unwrap();
+        let spoofer = UdpSocket::bind(("127.0.0.1", 0)).unwrap();
+        let local = socket.local_addr().unwrap();
+
+        let mut request = DnsPacket::new();
+        request.add_query(DnsQuery {
+            name: DnsName::from("example.com"),
+            record_type: DnsRecordType::A.into(),
+            class: DnsClass::IN.into(),
+        });
+        let mut response = request.reply();
+        response.header.flags.response = true;
+        let mut wrong_id = response.clone();
+        wrong_id.header.transaction_id = request.header.transaction_id.wrapping_add(1);
+
+        let send = |from: &UdpSocket, packet: &DnsPacket| {
+            let mut bytes = [0; 512];
+            let size = packet.write_to(&mut bytes).unwrap();
+            from.send_to(&bytes[..size], local).unwrap();
+        };
+        send(&spoofer, &response);
+        send(&server, &wrong_id);
+        server.send_to(b"garbage", local).unwrap();
+        send(&server, &response);
+
+        let mut tapped = 0;
+        let server = server.local_addr().unwrap();
+        let timeout = Duration::from_secs(1);
+        let received =
+            receive_response(&socket, server, &request, timeout, |_| tapped += 1).unwrap();
+        assert_eq!(response, received);
+        assert_eq!(3, tapped);
+
+        let timeout = Duration::from_millis(10);
+        assert!(receive_response(&socket, server, &request, timeout, |_| {}).is_err());
+
+        // A malformed response echoing the ID fails right away
+        let server = UdpSocket::bind(("127.0.0.1", 0)).unwrap();
+        let mut malformed = request.header.transaction_id.to_be_bytes().to_vec();
+        malformed.extend_from_slice(b"garbage");
+        server.send_to(&malformed, local).unwrap();
+        send(&server, &response);
+        let server = server.local_addr().unwrap();
+        let timeout = Duration::from_secs(1);
+        let error = receive_response(&socket, server, &request, timeout, |_| {}).unwrap_err();
+        assert_eq!(ErrorKind::InvalidData, error.kind());
+    }
+}
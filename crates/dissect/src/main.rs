@@ -0,0 +1,55 @@
+use std::io::{stdin, Read, Result};
+use std::process::exit;
+
+// Accepts plain hex dumps as well as byte arrays pasted from the tests,
+// e.g. "0xD0, 0xAE, // Transaction ID"
+fn parse_hex(input: &str) -> std::result::Result<Vec<u8>, String> {
+    let mut bytes = Vec::new();
+
+    for line in input.lines() {
+        let line = line.split("//").next().unwrap_or_default();
+        for token in line
+            .split(|c: char| c.is_whitespace() || c == ',')
+            .filter(|t| !t.is_empty())
+        {
+            let token = token
+                .strip_prefix("0x")
+                .or_else(|| token.strip_prefix("0X"))
+                .unwrap_or(token);
+            if token.len() % 2 != 0 {
+                return Err(format!("Odd number of hex digits in {:?}", token));
+            }
+
+            for i in (0..token.len()).step_by(2) {
+                let byte = u8::from_str_radix(&token[i..i + 2], 16)
+                    .map_err(|_| format!("Invalid hex in {:?}", token))?;
+                bytes.push(byte);
+            }
+        }
+    }
+
+    Ok(bytes)
+}
+
+fn main() -> Result<()> {
+    let mut input = String::new();
+    stdin().read_to_string(&mut input)?;
+
+    let bytes = match parse_hex(&input) {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            eprintln!("Error reading hex from stdin: {}", e);
+            exit(1);
+        }
+    };
+
+    let fields = parser::dissect(&bytes);
+    for field in &fields {
+        print!("{}", field);
+    }
+
+    if fields.iter().any(|f| f.is_malformed()) {
+        exit(2);
+    }
+    Ok(())
+}
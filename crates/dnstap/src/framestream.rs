@@ -0,0 +1,210 @@
+use super::invalid_data;
+use std::io::{Read, Result, Write};
+
+// https://farsightsec.github.io/fstrm/ (Frame Streams protocol)
+const CONTROL_ACCEPT: u32 = 0x01;
+const CONTROL_START: u32 = 0x02;
+const CONTROL_STOP: u32 = 0x03;
+const CONTROL_READY: u32 = 0x04;
+const CONTROL_FINISH: u32 = 0x05;
+const FIELD_CONTENT_TYPE: u32 = 0x01;
+const MAX_CONTROL_FRAME_SIZE: usize = 512;
+// A dnstap message holds at most a query and its response, so longer frames are rejected
+// rather than allocated. This is the default of the fstrm library
+pub const MAX_DATA_FRAME_SIZE: usize = 1 << 20;
+
+struct ControlFrame {
+    control_type: u32,
+    content_types: Vec<Vec<u8>>,
+}
+
+fn write_control(
+    out: &mut impl Write,
+    control_type: u32,
+    content_type: Option<&[u8]>,
+) -> Result<()> {
+    let mut frame = control_type.to_be_bytes().to_vec();
+    if let Some(content_type) = content_type {
+        frame.extend_from_slice(&FIELD_CONTENT_TYPE.to_be_bytes());
+        frame.extend_from_slice(&(content_type.len() as u32).to_be_bytes());
+        frame.extend_from_slice(content_type);
+    }
+
+    // A zero length escapes the control frame from data frames
+    out.write_all(&0u32.to_be_bytes())?;
+    out.write_all(&(frame.len() as u32).to_be_bytes())?;
+    out.write_all(&frame)?;
+    out.flush()
+}
+
+fn read_u32(input: &mut impl Read) -> Result<u32> {
+    let mut bytes = [0; 4];
+    input.read_exact(&mut bytes)?;
+    Ok(u32::from_be_bytes(bytes))
+}
+
+// Reads a control frame once its escape sequence has been consumed
+fn read_control_body(input: &mut impl Read) -> Result<ControlFrame> {
+    let length = read_u32(input)? as usize;
+    if !(4..=MAX_CONTROL_FRAME_SIZE).contains(&length) {
+        return Err(invalid_data("Invalid control frame length"));
+    }
+    let mut frame = vec![0; length];
+    input.read_exact(&mut frame)?;
+
+    let mut fields = &frame[4..];
+    let mut content_types = Vec::new();
+    while fields.len() >= 8 {
+        let field_type = u32::from_be_bytes([fields[0], fields[1], fields[2], fields[3]]);
+        let field_length =
+            u32::from_be_bytes([fields[4], fields[5], fields[6], fields[7]]) as usize;
+        let value = fields
+            .get(8..8 + field_length)
+            .ok_or_else(|| invalid_data("Truncated control frame field"))?;
+        if field_type == FIELD_CONTENT_TYPE {
+            content_types.push(value.to_vec());
+        }
+        fields = &fields[8 + field_length..];
+    }
+
+    Ok(ControlFrame {
+        control_type: u32::from_be_bytes([frame[0], frame[1], frame[2], frame[3]]),
+        content_types,
+    })
+}
+
+fn read_control(input: &mut impl Read, expected: u32) -> Result<ControlFrame> {
+    if read_u32(input)? != 0 {
+        return Err(invalid_data("Expected a control frame"));
+    }
+    let frame = read_control_body(input)?;
+    if frame.control_type != expected {
+        return Err(invalid_data("Unexpected control frame"));
+    }
+    Ok(frame)
+}
+
+fn check_content_type(frame: &ControlFrame, content_type: &[u8]) -> Result<()> {
+    // The content type is optional, but must match when given
+    if frame.content_types.is_empty() || frame.content_types.iter().any(|c| c == content_type) {
+        Ok(())
+    } else {
+        Err(invalid_data("Unsupported content type"))
+    }
+}
+
+// Writes data frames of a single content type, to a file or to a reader over a socket
+pub struct FrameWriter<W: Write> {
+    out: W,
+}
+
+impl<W: Write> FrameWriter<W> {
+    // Unidirectional stream, e.g. a file
+    pub fn new(mut out: W, content_type: &[u8]) -> Result<Self> {
+        write_control(&mut out, CONTROL_START, Some(content_type))?;
+        Ok(Self { out })
+    }
+
+    pub fn write_frame(&mut self, frame: &[u8]) -> Result<()> {
+        if frame.is_empty() || frame.len() > MAX_DATA_FRAME_SIZE {
+            return Err(invalid_data("Invalid data frame length"));
+        }
+        self.out.write_all(&(frame.len() as u32).to_be_bytes())?;
+        self.out.write_all(frame)
+    }
+
+    pub fn flush(&mut self) -> Result<()> {
+        self.out.flush()
+    }
+
+    pub fn finish(mut self) -> Result<W> {
+        write_control(&mut self.out, CONTROL_STOP, None)?;
+        Ok(self.out)
+    }
+}
+
+// Offers the content type to the reader of a bidirectional stream, before starting it
+pub(crate) fn handshake(stream: &mut (impl Read + Write), content_type: &[u8]) -> Result<()> {
+    write_control(stream, CONTROL_READY, Some(content_type))?;
+    let accept = read_control(stream, CONTROL_ACCEPT)?;
+    check_content_type(&accept, content_type)
+}
+
+impl<W: Read + Write> FrameWriter<W> {
+    // Bidirectional stream, where the reader has to accept the content type first
+    pub fn handshake(mut stream: W, content_type: &[u8]) -> Result<Self> {
+        handshake(&mut stream, content_type)?;
+        Self::new(stream, content_type)
+    }
+
+    pub fn close(self) -> Result<()> {
+        let mut stream = self.finish()?;
+        read_control(&mut stream, CONTROL_FINISH).map(|_| ())
+    }
+}
+
+pub struct FrameReader<R: Read> {
+    input: R,
+    stopped: bool,
+}
+
+impl<R: Read> FrameReader<R> {
+    // Unidirectional stream, e.g. a file
+    pub fn new(mut input: R, content_type: &[u8]) -> Result<Self> {
+        let start = read_control(&mut input, CONTROL_START)?;
+        check_content_type(&start, content_type)?;
+        Ok(Self {
+            input,
+            stopped: false,
+        })
+    }
+
+    // Returns None once the writer stopped, or at the end of a file written by a writer
+    // that never got to stop
+    pub fn read_frame(&mut self) -> Result<Option<Vec<u8>>> {
+        if self.stopped {
+            return Ok(None);
+        }
+
+        let mut length = [0; 4];
+        match self.input.read(&mut length[..1])? {
+            0 => {
+                self.stopped = true;
+                return Ok(None);
+            }
+            _ => self.input.read_exact(&mut length[1..])?,
+        }
+
+        match u32::from_be_bytes(length) as usize {
+            0 => {
+                let control = read_control_body(&mut self.input)?;
+                if control.control_type != CONTROL_STOP {
+                    return Err(invalid_data("Unexpected control frame"));
+                }
+                self.stopped = true;
+                Ok(None)
+            }
+            length if length > MAX_DATA_FRAME_SIZE => Err(invalid_data("Data frame too large")),
+            length => {
+                let mut frame = vec![0; length];
+                self.input.read_exact(&mut frame)?;
+                Ok(Some(frame))
+            }
+        }
+    }
+}
+
+impl<R: Read + Write> FrameReader<R> {
+    // Bidirectional stream, accepting the content type offered by the writer
+    pub fn accept(mut stream: R, content_type: &[u8]) -> Result<Self> {
+        let ready = read_control(&mut stream, CONTROL_READY)?;
+        check_content_type(&ready, content_type)?;
+        write_control(&mut stream, CONTROL_ACCEPT, Some(content_type))?;
+        Self::new(stream, content_type)
+    }
+
+    // Acknowledges the writer's stop
+    pub fn finish(mut self) -> Result<()> {
+        write_control(&mut self.input, CONTROL_FINISH, None)
+    }
+}
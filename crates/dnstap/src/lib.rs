@@ -0,0 +1,17 @@
+mod framestream;
+mod logger;
+mod message;
+mod protobuf;
+
+pub use framestream::{FrameReader, FrameWriter, MAX_DATA_FRAME_SIZE};
+pub use logger::{read_dnstap, Logger};
+pub use message::{Dnstap, Message, MessageType, SocketProtocol};
+
+use std::io::{Error, ErrorKind};
+
+// Frame Streams content type of dnstap payloads
+pub const CONTENT_TYPE: &[u8] = b"protobuf:dnstap.Dnstap";
+
+fn invalid_data(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
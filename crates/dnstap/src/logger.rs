@@ -0,0 +1,59 @@
+use super::framestream::{handshake, FrameReader, FrameWriter};
+use super::message::{Dnstap, Message};
+use super::CONTENT_TYPE;
+
+use std::fs::File;
+use std::io::{BufWriter, Read, Result, Write};
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::sync::Mutex;
+
+const VERSION: &str = concat!("dns-rs ", env!("CARGO_PKG_VERSION"));
+
+// Logs the messages of a running server as dnstap frames, flushed after every message
+pub struct Logger {
+    writer: Mutex<FrameWriter<Box<dyn Write + Send>>>,
+}
+
+impl Logger {
+    pub fn create(path: impl AsRef<Path>) -> Result<Self> {
+        let file = BufWriter::new(File::create(path)?);
+        Self::new(Box::new(file))
+    }
+
+    // Connects to a collector listening on a unix socket
+    pub fn connect(path: impl AsRef<Path>) -> Result<Self> {
+        let mut stream = UnixStream::connect(path)?;
+        handshake(&mut stream, CONTENT_TYPE)?;
+        Self::new(Box::new(stream))
+    }
+
+    fn new(out: Box<dyn Write + Send>) -> Result<Self> {
+        Ok(Self {
+            writer: Mutex::new(FrameWriter::new(out, CONTENT_TYPE)?),
+        })
+    }
+
+    pub fn log(&self, message: Message) -> Result<()> {
+        let frame = Dnstap {
+            identity: None,
+            version: Some(VERSION.as_bytes().to_vec()),
+            message,
+        }
+        .encode();
+
+        let mut writer = self.writer.lock().unwrap();
+        writer.write_frame(&frame)?;
+        writer.flush()
+    }
+}
+
+// Reads every dnstap frame of a file
+pub fn read_dnstap(input: impl Read) -> Result<Vec<Dnstap>> {
+    let mut reader = FrameReader::new(input, CONTENT_TYPE)?;
+    let mut frames = Vec::new();
+    while let Some(frame) = reader.read_frame()? {
+        frames.push(Dnstap::decode(&frame)?);
+    }
+    Ok(frames)
+}
@@ -0,0 +1,119 @@
+use clap::{AppSettings, Clap};
+use dnstap::{Dnstap, FrameReader, Message, SocketProtocol, CONTENT_TYPE};
+use parser::packet::DnsPacket;
+use std::fs::{self, File};
+use std::io::{BufReader, Read, Result, Write};
+use std::os::unix::net::UnixListener;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Clap)]
+#[clap(version = "1.0", author = "Jonathan Bouchard <dev.drakota@gmail.com>")]
+#[clap(setting = AppSettings::ColoredHelp)]
+struct Opts {
+    /// dnstap file to read
+    #[clap(required_unless_present = "socket")]
+    file: Option<PathBuf>,
+
+    /// Unix socket to listen on for dnstap writers, e.g. the servers' --dnstap-socket
+    #[clap(short, long)]
+    socket: Option<PathBuf>,
+
+    /// Print the DNS messages instead of a line per message
+    #[clap(short, long)]
+    verbose: bool,
+}
+
+fn format_time(time: Option<SystemTime>) -> String {
+    match time.map(|t| t.duration_since(UNIX_EPOCH).unwrap_or_default()) {
+        Some(since_epoch) => format!(
+            "{}.{:06}",
+            since_epoch.as_secs(),
+            since_epoch.subsec_micros()
+        ),
+        None => "-".to_owned(),
+    }
+}
+
+fn format_address(address: Option<std::net::SocketAddr>) -> String {
+    address.map_or_else(|| "-".to_owned(), |a| a.to_string())
+}
+
+fn print_message(opts: &Opts, dnstap: &Dnstap) {
+    let Message {
+        message_type,
+        socket_protocol,
+        query_address,
+        response_address,
+        query_time,
+        query_message,
+        response_time,
+        response_message,
+    } = &dnstap.message;
+
+    println!(
+        "{} {} {} {} -> {}",
+        format_time(response_time.or(*query_time)),
+        message_type,
+        match socket_protocol {
+            Some(SocketProtocol::Udp) => "UDP",
+            Some(SocketProtocol::Tcp) => "TCP",
+            None => "-",
+        },
+        format_address(*query_address),
+        format_address(*response_address)
+    );
+
+    if opts.verbose {
+        for bytes in query_message.iter().chain(response_message) {
+            match DnsPacket::parse(bytes) {
+                Ok(packet) => println!("{}", packet),
+                Err(e) => println!("Error parsing packet: {:?}", e),
+            }
+        }
+    }
+}
+
+fn read_frames<R: Read>(opts: &Opts, reader: &mut FrameReader<R>) -> Result<()> {
+    while let Some(frame) = reader.read_frame()? {
+        match Dnstap::decode(&frame) {
+            Ok(dnstap) => print_message(opts, &dnstap),
+            Err(e) => println!("Error decoding frame: {}", e),
+        }
+    }
+    Ok(())
+}
+
+fn main() -> Result<()> {
+    let opts = Opts::parse();
+
+    let path = match &opts.socket {
+        Some(path) => path,
+        None => {
+            let file = BufReader::new(File::open(opts.file.as_ref().unwrap())?);
+            return read_frames(&opts, &mut FrameReader::new(file, CONTENT_TYPE)?);
+        }
+    };
+
+    // A socket left behind by a previous run would make the bind fail
+    if path.exists() {
+        fs::remove_file(path)?;
+    }
+    let listener = UnixListener::bind(path)?;
+    println!("Listening for dnstap writers on {}", path.display());
+
+    // Writers are served one at a time
+    for stream in listener.incoming() {
+        let result = stream.and_then(|stream| {
+            let mut reader = FrameReader::accept(stream, CONTENT_TYPE)?;
+            read_frames(&opts, &mut reader)?;
+            // Writers that went away without stopping can't be answered
+            reader.finish().or(Ok(()))
+        });
+        if let Err(e) = result {
+            println!("Error reading dnstap stream: {}", e);
+        }
+        std::io::stdout().flush()?;
+    }
+    Ok(())
+}
@@ -0,0 +1,299 @@
+use super::invalid_data;
+use super::protobuf::{
+    read_fields, write_bytes_field, write_fixed32_field, write_varint_field, Value,
+};
+
+use std::convert::{TryFrom, TryInto};
+use std::fmt;
+use std::io::{Error, Result};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// https://github.com/dnstap/dnstap.pb/blob/master/dnstap.proto
+const DNSTAP_TYPE_MESSAGE: u64 = 1;
+
+const SOCKET_FAMILY_INET: u64 = 1;
+const SOCKET_FAMILY_INET6: u64 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MessageType {
+    AuthQuery = 1,
+    AuthResponse = 2,
+    ResolverQuery = 3,
+    ResolverResponse = 4,
+    ClientQuery = 5,
+    ClientResponse = 6,
+    ForwarderQuery = 7,
+    ForwarderResponse = 8,
+    StubQuery = 9,
+    StubResponse = 10,
+    ToolQuery = 11,
+    ToolResponse = 12,
+}
+
+impl TryFrom<u64> for MessageType {
+    type Error = Error;
+
+    fn try_from(value: u64) -> Result<Self> {
+        use MessageType::*;
+        Ok(match value {
+            1 => AuthQuery,
+            2 => AuthResponse,
+            3 => ResolverQuery,
+            4 => ResolverResponse,
+            5 => ClientQuery,
+            6 => ClientResponse,
+            7 => ForwarderQuery,
+            8 => ForwarderResponse,
+            9 => StubQuery,
+            10 => StubResponse,
+            11 => ToolQuery,
+            12 => ToolResponse,
+            _ => return Err(invalid_data("Unknown dnstap message type")),
+        })
+    }
+}
+
+impl fmt::Display for MessageType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use MessageType::*;
+        let name = match self {
+            AuthQuery => "AUTH_QUERY",
+            AuthResponse => "AUTH_RESPONSE",
+            ResolverQuery => "RESOLVER_QUERY",
+            ResolverResponse => "RESOLVER_RESPONSE",
+            ClientQuery => "CLIENT_QUERY",
+            ClientResponse => "CLIENT_RESPONSE",
+            ForwarderQuery => "FORWARDER_QUERY",
+            ForwarderResponse => "FORWARDER_RESPONSE",
+            StubQuery => "STUB_QUERY",
+            StubResponse => "STUB_RESPONSE",
+            ToolQuery => "TOOL_QUERY",
+            ToolResponse => "TOOL_RESPONSE",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SocketProtocol {
+    Udp = 1,
+    Tcp = 2,
+}
+
+impl TryFrom<u64> for SocketProtocol {
+    type Error = Error;
+
+    fn try_from(value: u64) -> Result<Self> {
+        match value {
+            1 => Ok(SocketProtocol::Udp),
+            2 => Ok(SocketProtocol::Tcp),
+            _ => Err(invalid_data("Unsupported dnstap socket protocol")),
+        }
+    }
+}
+
+// A DNS message seen by the server, with the wire bytes it was sent or received as
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Message {
+    pub message_type: MessageType,
+    pub socket_protocol: Option<SocketProtocol>,
+    pub query_address: Option<SocketAddr>,
+    pub response_address: Option<SocketAddr>,
+    pub query_time: Option<SystemTime>,
+    pub query_message: Option<Vec<u8>>,
+    pub response_time: Option<SystemTime>,
+    pub response_message: Option<Vec<u8>>,
+}
+
+impl Message {
+    // The query address is the side that sent the query, whichever way this message went
+    pub fn query(
+        message_type: MessageType,
+        query_address: SocketAddr,
+        response_address: SocketAddr,
+        bytes: &[u8],
+    ) -> Self {
+        Self {
+            message_type,
+            socket_protocol: Some(SocketProtocol::Udp),
+            query_address: Some(query_address),
+            response_address: Some(response_address),
+            query_time: Some(SystemTime::now()),
+            query_message: Some(bytes.to_vec()),
+            response_time: None,
+            response_message: None,
+        }
+    }
+
+    pub fn response(
+        message_type: MessageType,
+        query_address: SocketAddr,
+        response_address: SocketAddr,
+        bytes: &[u8],
+    ) -> Self {
+        Self {
+            message_type,
+            socket_protocol: Some(SocketProtocol::Udp),
+            query_address: Some(query_address),
+            response_address: Some(response_address),
+            query_time: None,
+            query_message: None,
+            response_time: Some(SystemTime::now()),
+            response_message: Some(bytes.to_vec()),
+        }
+    }
+
+    fn encode(&self, out: &mut Vec<u8>) {
+        write_varint_field(out, 1, self.message_type as u64);
+
+        let family = self.query_address.or(self.response_address).map(|a| a.ip());
+        match family {
+            Some(IpAddr::V4(_)) => write_varint_field(out, 2, SOCKET_FAMILY_INET),
+            Some(IpAddr::V6(_)) => write_varint_field(out, 2, SOCKET_FAMILY_INET6),
+            None => {}
+        }
+        if let Some(protocol) = self.socket_protocol {
+            write_varint_field(out, 3, protocol as u64);
+        }
+
+        if let Some(address) = self.query_address {
+            write_bytes_field(out, 4, &ip_octets(address.ip()));
+        }
+        if let Some(address) = self.response_address {
+            write_bytes_field(out, 5, &ip_octets(address.ip()));
+        }
+        if let Some(address) = self.query_address {
+            write_varint_field(out, 6, address.port() as u64);
+        }
+        if let Some(address) = self.response_address {
+            write_varint_field(out, 7, address.port() as u64);
+        }
+
+        if let Some(time) = self.query_time {
+            let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+            write_varint_field(out, 8, since_epoch.as_secs());
+            write_fixed32_field(out, 9, since_epoch.subsec_nanos());
+        }
+        if let Some(message) = &self.query_message {
+            write_bytes_field(out, 10, message);
+        }
+        if let Some(time) = self.response_time {
+            let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+            write_varint_field(out, 12, since_epoch.as_secs());
+            write_fixed32_field(out, 13, since_epoch.subsec_nanos());
+        }
+        if let Some(message) = &self.response_message {
+            write_bytes_field(out, 14, message);
+        }
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self> {
+        let mut message_type = None;
+        let mut socket_protocol = None;
+        let (mut query_ip, mut response_ip) = (None, None);
+        let (mut query_port, mut response_port) = (0, 0);
+        let (mut query_seconds, mut query_nanos) = (None, 0);
+        let (mut response_seconds, mut response_nanos) = (None, 0);
+        let (mut query_message, mut response_message) = (None, None);
+
+        // Fields of the wrong wire type are ignored like unknown fields
+        for (field, value) in read_fields(bytes)? {
+            match (field, value) {
+                (1, Value::Varint(value)) => message_type = Some(MessageType::try_from(value)?),
+                (3, Value::Varint(value)) => socket_protocol = SocketProtocol::try_from(value).ok(),
+                (4, Value::Bytes(address)) => query_ip = Some(parse_ip(address)?),
+                (5, Value::Bytes(address)) => response_ip = Some(parse_ip(address)?),
+                (6, Value::Varint(port)) => query_port = port as u16,
+                (7, Value::Varint(port)) => response_port = port as u16,
+                (8, Value::Varint(seconds)) => query_seconds = Some(seconds),
+                (9, Value::Fixed32(nanos)) => query_nanos = nanos,
+                (10, Value::Bytes(message)) => query_message = Some(message.to_vec()),
+                (12, Value::Varint(seconds)) => response_seconds = Some(seconds),
+                (13, Value::Fixed32(nanos)) => response_nanos = nanos,
+                (14, Value::Bytes(message)) => response_message = Some(message.to_vec()),
+                _ => {}
+            }
+        }
+
+        let time = |seconds: Option<u64>, nanos: u32| {
+            seconds.map(|s| UNIX_EPOCH + Duration::new(s, 0) + Duration::from_nanos(nanos as u64))
+        };
+        Ok(Self {
+            message_type: message_type
+                .ok_or_else(|| invalid_data("Missing dnstap message type"))?,
+            socket_protocol,
+            query_address: query_ip.map(|ip| SocketAddr::new(ip, query_port)),
+            response_address: response_ip.map(|ip| SocketAddr::new(ip, response_port)),
+            query_time: time(query_seconds, query_nanos),
+            query_message,
+            response_time: time(response_seconds, response_nanos),
+            response_message,
+        })
+    }
+}
+
+fn ip_octets(ip: IpAddr) -> Vec<u8> {
+    match ip {
+        IpAddr::V4(ip) => ip.octets().to_vec(),
+        IpAddr::V6(ip) => ip.octets().to_vec(),
+    }
+}
+
+fn parse_ip(bytes: &[u8]) -> Result<IpAddr> {
+    if let Ok(octets) = <[u8; 4]>::try_from(bytes) {
+        return Ok(IpAddr::V4(Ipv4Addr::from(octets)));
+    }
+    let octets: [u8; 16] = bytes
+        .try_into()
+        .map_err(|_| invalid_data("Invalid dnstap address"))?;
+    Ok(IpAddr::V6(Ipv6Addr::from(octets)))
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Dnstap {
+    pub identity: Option<Vec<u8>>,
+    pub version: Option<Vec<u8>>,
+    pub message: Message,
+}
+
+impl Dnstap {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut message = Vec::new();
+        self.message.encode(&mut message);
+
+        let mut out = Vec::new();
+        if let Some(identity) = &self.identity {
+            write_bytes_field(&mut out, 1, identity);
+        }
+        if let Some(version) = &self.version {
+            write_bytes_field(&mut out, 2, version);
+        }
+        write_bytes_field(&mut out, 14, &message);
+        write_varint_field(&mut out, 15, DNSTAP_TYPE_MESSAGE);
+        out
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self> {
+        let mut identity = None;
+        let mut version = None;
+        let mut message = None;
+
+        for (field, value) in read_fields(bytes)? {
+            match (field, value) {
+                (1, Value::Bytes(value)) => identity = Some(value.to_vec()),
+                (2, Value::Bytes(value)) => version = Some(value.to_vec()),
+                (14, Value::Bytes(value)) => message = Some(Message::decode(value)?),
+                (15, Value::Varint(DNSTAP_TYPE_MESSAGE)) => {}
+                (15, _) => return Err(invalid_data("Unsupported dnstap type")),
+                _ => {}
+            }
+        }
+
+        Ok(Self {
+            identity,
+            version,
+            message: message.ok_or_else(|| invalid_data("Missing dnstap message"))?,
+        })
+    }
+}
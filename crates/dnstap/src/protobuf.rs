@@ -0,0 +1,89 @@
+use super::invalid_data;
+use std::convert::TryInto;
+use std::io::Result;
+
+// https://developers.google.com/protocol-buffers/docs/encoding
+const WIRE_VARINT: u64 = 0;
+const WIRE_FIXED64: u64 = 1;
+const WIRE_BYTES: u64 = 2;
+const WIRE_FIXED32: u64 = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Value<'a> {
+    Varint(u64),
+    Fixed64(u64),
+    Bytes(&'a [u8]),
+    Fixed32(u32),
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+pub(crate) fn write_varint_field(out: &mut Vec<u8>, field: u32, value: u64) {
+    write_varint(out, (field as u64) << 3 | WIRE_VARINT);
+    write_varint(out, value);
+}
+
+pub(crate) fn write_fixed32_field(out: &mut Vec<u8>, field: u32, value: u32) {
+    write_varint(out, (field as u64) << 3 | WIRE_FIXED32);
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+pub(crate) fn write_bytes_field(out: &mut Vec<u8>, field: u32, value: &[u8]) {
+    write_varint(out, (field as u64) << 3 | WIRE_BYTES);
+    write_varint(out, value.len() as u64);
+    out.extend_from_slice(value);
+}
+
+fn read_varint(input: &mut &[u8]) -> Result<u64> {
+    let mut value = 0;
+    for shift in (0..64).step_by(7) {
+        let (byte, rest) = input
+            .split_first()
+            .ok_or_else(|| invalid_data("Truncated varint"))?;
+        *input = rest;
+        value |= ((byte & 0x7F) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(invalid_data("Varint too long"))
+}
+
+fn take<'a>(input: &mut &'a [u8], length: usize) -> Result<&'a [u8]> {
+    if input.len() < length {
+        return Err(invalid_data("Truncated field"));
+    }
+    let (value, rest) = input.split_at(length);
+    *input = rest;
+    Ok(value)
+}
+
+// Splits a message into its fields, in the order they were encoded
+pub(crate) fn read_fields(mut input: &[u8]) -> Result<Vec<(u32, Value<'_>)>> {
+    let mut fields = Vec::new();
+    while !input.is_empty() {
+        let key = read_varint(&mut input)?;
+        let value = match key & 0x07 {
+            WIRE_VARINT => Value::Varint(read_varint(&mut input)?),
+            WIRE_FIXED64 => {
+                Value::Fixed64(u64::from_le_bytes(take(&mut input, 8)?.try_into().unwrap()))
+            }
+            WIRE_BYTES => {
+                let length = read_varint(&mut input)? as usize;
+                Value::Bytes(take(&mut input, length)?)
+            }
+            WIRE_FIXED32 => {
+                Value::Fixed32(u32::from_le_bytes(take(&mut input, 4)?.try_into().unwrap()))
+            }
+            _ => return Err(invalid_data("Unsupported protobuf wire type")),
+        };
+        fields.push(((key >> 3) as u32, value));
+    }
+    Ok(fields)
+}
@@ -0,0 +1,114 @@
+use dnstap::{
+    read_dnstap, Dnstap, FrameReader, FrameWriter, Logger, Message, MessageType, SocketProtocol,
+    CONTENT_TYPE, MAX_DATA_FRAME_SIZE,
+};
+
+use std::{
+    fs::File,
+    io::ErrorKind,
+    net::SocketAddr,
+    os::unix::net::UnixStream,
+    thread,
+    time::{Duration, UNIX_EPOCH},
+};
+
+fn client_query() -> Message {
+    Message {
+        message_type: MessageType::ClientQuery,
+        socket_protocol: Some(SocketProtocol::Udp),
+        query_address: Some("192.0.2.1:40000".parse().unwrap()),
+        response_address: Some("192.0.2.53:53".parse().unwrap()),
+        query_time: Some(UNIX_EPOCH + Duration::new(1_600_000_000, 123_456_789)),
+        query_message: Some(vec![0xD0, 0xAE, 0x01, 0x00]),
+        response_time: None,
+        response_message: None,
+    }
+}
+
+#[test]
+fn test_decode_dnstap() {
+    #[rustfmt::skip]
+    let bytes: Vec<u8> = vec![
+        0x0A, 0x04, b't', b'e', b's', b't', // Identity: test
+        0x72, 0x29,                         // Message, 41 bytes
+            0x08, 0x05,                     // Type: CLIENT_QUERY
+            0x10, 0x01,                     // Socket family: INET
+            0x18, 0x01,                     // Socket protocol: UDP
+            0x22, 0x04, 192, 0, 2, 1,       // Query address
+            0x2A, 0x04, 192, 0, 2, 53,      // Response address
+            0x30, 0xC0, 0xB8, 0x02,         // Query port: 40000
+            0x38, 0x35,                     // Response port: 53
+            0x40, 0x80, 0xA0, 0xF8, 0xFA, 0x05, // Query time: 1600000000s
+            0x4D, 0x15, 0xCD, 0x5B, 0x07,   // Query time: 123456789ns
+            0x52, 0x04, 0xD0, 0xAE, 0x01, 0x00, // Query message
+        0x78, 0x01,                         // Type: MESSAGE
+    ];
+
+    let dnstap = Dnstap::decode(&bytes).unwrap();
+    assert_eq!(Some(b"test".to_vec()), dnstap.identity);
+    assert_eq!(None, dnstap.version);
+    assert_eq!(client_query(), dnstap.message);
+    assert_eq!(bytes, dnstap.encode());
+}
+
+#[test]
+fn test_logger_file_round_trip() {
+    let path = std::env::temp_dir().join(format!("dnstap-test-{}.fstrm", std::process::id()));
+    let logger = Logger::create(&path).unwrap();
+    logger.log(client_query()).unwrap();
+
+    let server: SocketAddr = "[2001:db8::53]:53".parse().unwrap();
+    let local: SocketAddr = "[2001:db8::1]:40001".parse().unwrap();
+    let response = Message::response(MessageType::ResolverResponse, local, server, &[0; 12]);
+    logger.log(response.clone()).unwrap();
+
+    let frames = read_dnstap(File::open(&path).unwrap()).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(2, frames.len());
+    assert_eq!(client_query(), frames[0].message);
+    assert_eq!(response, frames[1].message);
+    assert!(frames[0].version.is_some());
+}
+
+#[test]
+fn test_bidirectional_stream() {
+    let (writer_stream, reader_stream) = UnixStream::pair().unwrap();
+
+    let writer = thread::spawn(move || {
+        let mut writer = FrameWriter::handshake(writer_stream, CONTENT_TYPE).unwrap();
+        writer.write_frame(b"first").unwrap();
+        writer.write_frame(b"second").unwrap();
+        writer.close().unwrap();
+    });
+
+    let mut reader = FrameReader::accept(reader_stream, CONTENT_TYPE).unwrap();
+    assert_eq!(Some(b"first".to_vec()), reader.read_frame().unwrap());
+    assert_eq!(Some(b"second".to_vec()), reader.read_frame().unwrap());
+    assert_eq!(None, reader.read_frame().unwrap());
+    reader.finish().unwrap();
+
+    writer.join().unwrap();
+}
+
+#[test]
+fn test_unsupported_content_type() {
+    let mut bytes = Vec::new();
+    FrameWriter::new(&mut bytes, b"protobuf:other").unwrap();
+    assert!(FrameReader::new(&bytes[..], CONTENT_TYPE).is_err());
+}
+
+#[test]
+fn test_oversized_data_frame() {
+    let mut bytes = Vec::new();
+    let mut writer = FrameWriter::new(&mut bytes, CONTENT_TYPE).unwrap();
+    assert!(writer
+        .write_frame(&vec![0; MAX_DATA_FRAME_SIZE + 1])
+        .is_err());
+    bytes.extend_from_slice(&u32::MAX.to_be_bytes());
+
+    // The declared length is rejected before anything is allocated for it
+    let mut reader = FrameReader::new(&bytes[..], CONTENT_TYPE).unwrap();
+    let error = reader.read_frame().unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::InvalidData);
+}
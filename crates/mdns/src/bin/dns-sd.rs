@@ -0,0 +1,145 @@
+use clap::{AppSettings, Clap};
+use mdns::dnssd::{browse, resolve, MulticastTransport, Transport, TxtRecord, UnicastTransport};
+use mdns::responder::{Config, Responder, Service};
+use mdns::server;
+use parser::resources::name::{DnsLabel, DnsName};
+use std::io::{Error, ErrorKind, Result};
+use std::net::{IpAddr, SocketAddr};
+use std::time::{Duration, Instant};
+
+#[derive(Clap)]
+#[clap(version = "1.0", author = "Jonathan Bouchard <dev.drakota@gmail.com>")]
+#[clap(setting = AppSettings::ColoredHelp)]
+struct Opts {
+    /// DNS server to query instead of multicasting on the local link
+    #[clap(short, long)]
+    server: Option<SocketAddr>,
+
+    /// Time to wait for answers in milliseconds
+    #[clap(short, long, default_value = "1000")]
+    timeout: u64,
+
+    #[clap(subcommand)]
+    command: Command,
+}
+
+#[derive(Clap)]
+enum Command {
+    /// Lists and resolves the instances of a service type, e.g. _http._tcp.local
+    Browse { service_type: String },
+    /// Resolves a single instance of a service type
+    Resolve {
+        instance: String,
+        service_type: String,
+    },
+    /// Advertises an instance of a service type on the local link until interrupted
+    Register(Register),
+}
+
+#[derive(Clap)]
+struct Register {
+    instance: String,
+    service_type: String,
+    port: u16,
+    /// TXT entries of the instance as <key>[=<value>]
+    txt: Vec<String>,
+
+    /// Host name to claim in the .local domain for the instance
+    #[clap(long, default_value = "dns-rs")]
+    hostname: String,
+
+    /// Address answered for the host name, can be repeated
+    #[clap(short, long, required = true)]
+    address: Vec<IpAddr>,
+
+    #[clap(short, long)]
+    verbose: bool,
+}
+
+// Runs a responder advertising the instance, which is probed and renamed on conflicts first
+fn register(opts: &Register) -> Result<()> {
+    let txt = opts
+        .txt
+        .join(",")
+        .parse::<TxtRecord>()
+        .map_err(|e| Error::new(ErrorKind::InvalidInput, e))?;
+    let service = Service {
+        instance: opts.instance.clone(),
+        // Service types are kept without their domain, which is always .local
+        service_type: opts
+            .service_type
+            .strip_suffix(".local")
+            .unwrap_or(&opts.service_type)
+            .to_owned(),
+        port: opts.port,
+        txt,
+    };
+    println!("Registering {:?}", service.instance_name());
+
+    let responder = Responder::new(
+        Config {
+            hostname: opts.hostname.clone(),
+            addresses: opts.address.clone(),
+            services: vec![service],
+        },
+        Instant::now(),
+    );
+    server::serve(responder, opts.verbose)
+}
+
+fn print_instance(transport: &impl Transport, instance: &DnsName) -> Result<()> {
+    let resolved = match resolve(transport, instance)? {
+        Some(resolved) => resolved,
+        None => {
+            println!("{}\tunresolved", instance);
+            return Ok(());
+        }
+    };
+
+    let addresses: Vec<String> = resolved.addresses.iter().map(|a| a.to_string()).collect();
+    println!(
+        "{}\t{}:{}\t{}",
+        resolved.instance(),
+        resolved.host,
+        resolved.port,
+        addresses.join(",")
+    );
+    for (key, value) in resolved.txt.entries() {
+        match value {
+            Some(value) => println!("\t{}={}", key, String::from_utf8_lossy(value)),
+            None => println!("\t{}", key),
+        }
+    }
+    Ok(())
+}
+
+fn run(opts: &Opts, transport: &impl Transport) -> Result<()> {
+    match &opts.command {
+        Command::Browse { service_type } => {
+            for instance in browse(transport, service_type)? {
+                print_instance(transport, &instance)?;
+            }
+        }
+        Command::Resolve {
+            instance,
+            service_type,
+        } => {
+            // The instance label may contain dots, so it is kept as a single label
+            let mut labels = vec![DnsLabel::new(instance.as_bytes())];
+            labels.extend_from_slice(DnsName::from(service_type.as_str()).labels());
+            print_instance(transport, &DnsName::from(labels))?;
+        }
+        Command::Register(register_opts) => register(register_opts)?,
+    }
+    Ok(())
+}
+
+fn main() -> Result<()> {
+    let opts = Opts::parse();
+    let timeout = Duration::from_millis(opts.timeout);
+
+    match opts.server {
+        Some(server) => run(&opts, &UnicastTransport { server, timeout }),
+        None => run(&opts, &MulticastTransport { timeout }),
+    }
+}
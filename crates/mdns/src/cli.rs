@@ -0,0 +1,23 @@
+use clap::{AppSettings, Clap};
+use mdns::responder::Service;
+use std::net::IpAddr;
+
+#[derive(Clap)]
+#[clap(version = "1.0", author = "Jonathan Bouchard <dev.drakota@gmail.com>")]
+#[clap(setting = AppSettings::ColoredHelp)]
+pub struct Opts {
+    /// Host name to claim in the .local domain
+    #[clap(long, default_value = "dns-rs")]
+    pub hostname: String,
+
+    /// Address answered for the host name, can be repeated
+    #[clap(short, long, required = true)]
+    pub address: Vec<IpAddr>,
+
+    /// Service to advertise as <instance>,<service type>,<port>[,<key>[=<value>]...]
+    #[clap(short, long)]
+    pub service: Vec<Service>,
+
+    #[clap(short, long)]
+    pub verbose: bool,
+}
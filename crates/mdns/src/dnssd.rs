@@ -0,0 +1,451 @@
+use crate::responder::same_name;
+use crate::socket;
+use parser::header::flags::DnsHeaderFlags;
+use parser::mdns::{MdnsPacket, MdnsQuery};
+use parser::packet::DnsPacket;
+use parser::resources::name::DnsName;
+use parser::resources::query::DnsQuery;
+use parser::resources::record::DnsRecord;
+use parser::resources::{DnsClass, DnsRecordType};
+use std::io::{ErrorKind, Result};
+use std::net::{IpAddr, SocketAddr, UdpSocket};
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+// Key/value attributes of a service instance
+// https://datatracker.ietf.org/doc/html/rfc6763#section-6
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TxtRecord {
+    entries: Vec<(String, Option<Vec<u8>>)>,
+}
+
+impl TxtRecord {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // A key without a value is a boolean attribute, which is different from an empty value
+    // https://datatracker.ietf.org/doc/html/rfc6763#section-6.4
+    pub fn insert(&mut self, key: &str, value: Option<&[u8]>) -> std::result::Result<(), String> {
+        if key.is_empty() || !key.bytes().all(|b| (0x20..=0x7E).contains(&b) && b != b'=') {
+            return Err(format!("Invalid TXT key {:?}", key));
+        }
+        if key.len() + value.map_or(0, |v| v.len() + 1) > u8::MAX as usize {
+            return Err(format!("TXT entry {:?} is longer than 255 bytes", key));
+        }
+
+        // Keys are case insensitive
+        self.entries.retain(|(k, _)| !k.eq_ignore_ascii_case(key));
+        self.entries
+            .push((key.to_owned(), value.map(|v| v.to_vec())));
+        Ok(())
+    }
+
+    // Returns None when the key is absent and Some(None) for a boolean attribute
+    pub fn get(&self, key: &str) -> Option<Option<&[u8]>> {
+        self.entries
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(key))
+            .map(|(_, v)| v.as_deref())
+    }
+
+    pub fn entries(&self) -> &[(String, Option<Vec<u8>>)] {
+        &self.entries
+    }
+
+    // A TXT record must hold at least one string, even if there are no attributes
+    // https://datatracker.ietf.org/doc/html/rfc6763#section-6.1
+    pub fn encode(&self) -> Vec<Vec<u8>> {
+        if self.entries.is_empty() {
+            return vec![Vec::new()];
+        }
+
+        self.entries
+            .iter()
+            .map(|(key, value)| {
+                let mut string = key.as_bytes().to_vec();
+                if let Some(value) = value {
+                    string.push(b'=');
+                    string.extend_from_slice(value);
+                }
+                string
+            })
+            .collect()
+    }
+
+    // https://datatracker.ietf.org/doc/html/rfc6763#section-6.4
+    pub fn decode(strings: &[Vec<u8>]) -> Self {
+        let mut txt = Self::new();
+        for string in strings {
+            let (key, value) = match string.iter().position(|&b| b == b'=') {
+                Some(index) => (&string[..index], Some(&string[index + 1..])),
+                None => (&string[..], None),
+            };
+            // Strings without a key are silently ignored
+            let key = match std::str::from_utf8(key) {
+                Ok(key) if !key.is_empty() => key,
+                _ => continue,
+            };
+            // Only the first occurrence of a key counts
+            if txt.get(key).is_none() {
+                let _ = txt.insert(key, value);
+            }
+        }
+        txt
+    }
+}
+
+// Parses comma separated "key=value" or "key" entries
+impl FromStr for TxtRecord {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let mut txt = Self::new();
+        for entry in s.split(',').filter(|e| !e.is_empty()) {
+            match entry.split_once('=') {
+                Some((key, value)) => txt.insert(key, Some(value.as_bytes()))?,
+                None => txt.insert(entry, None)?,
+            }
+        }
+        Ok(txt)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServiceInstance {
+    pub name: DnsName,
+    pub host: DnsName,
+    pub port: u16,
+    pub addresses: Vec<IpAddr>,
+    pub txt: TxtRecord,
+}
+
+impl ServiceInstance {
+    // The user-friendly part of the name, e.g. "Office Printer"
+    pub fn instance(&self) -> String {
+        self.name
+            .labels()
+            .first()
+            .map(|label| String::from_utf8_lossy(&label.data).into_owned())
+            .unwrap_or_default()
+    }
+}
+
+// Sends a single question and returns every record received in reply
+pub trait Transport {
+    fn query(&self, query: &DnsQuery) -> Result<Vec<DnsRecord>>;
+}
+
+// Queries a regular DNS server, for services published in unicast DNS
+pub struct UnicastTransport {
+    pub server: SocketAddr,
+    pub timeout: Duration,
+}
+
+impl Transport for UnicastTransport {
+    fn query(&self, query: &DnsQuery) -> Result<Vec<DnsRecord>> {
+        let socket = UdpSocket::bind(unspecified_address(self.server))?;
+        socket.set_read_timeout(Some(self.timeout))?;
+
+        let mut request = DnsPacket::new();
+        request.header.transaction_id = rand::random();
+        request.header.set_flags(DnsHeaderFlags {
+            recdesired: true,
+            ..Default::default()
+        });
+        request.add_query(query.clone());
+        let bytes = request
+            .serialize()
+            .map_err(|e| std::io::Error::new(ErrorKind::InvalidInput, e.to_string()))?;
+        socket.send_to(&bytes[..], self.server)?;
+
+        let mut buffer = [0; 4096];
+        loop {
+            let (size, src) = socket.recv_from(&mut buffer)?;
+            let response = match DnsPacket::parse(&buffer[..size]) {
+                Ok(response) if src == self.server => response,
+                _ => continue,
+            };
+            if response.header.transaction_id != request.header.transaction_id {
+                continue;
+            }
+
+            return Ok(response
+                .answers()
+                .iter()
+                .chain(response.additional_records())
+                .cloned()
+                .collect());
+        }
+    }
+}
+
+// Sends one-shot mDNS queries, which responders answer directly to our port
+// https://datatracker.ietf.org/doc/html/rfc6762#section-5.1
+pub struct MulticastTransport {
+    pub timeout: Duration,
+}
+
+impl Transport for MulticastTransport {
+    fn query(&self, query: &DnsQuery) -> Result<Vec<DnsRecord>> {
+        let group = socket::ipv4_group();
+        let socket = UdpSocket::bind(unspecified_address(group))?;
+
+        let mut request = MdnsPacket::new();
+        request.header.transaction_id = rand::random();
+        request.queries.push(MdnsQuery {
+            query: query.clone(),
+            unicast_response: false,
+        });
+        let bytes = request
+            .serialize()
+            .map_err(|e| std::io::Error::new(ErrorKind::InvalidInput, e.to_string()))?;
+        socket.send_to(&bytes[..], group)?;
+
+        // Many responders may answer, so we keep listening until the timeout
+        let deadline = Instant::now() + self.timeout;
+        let mut records: Vec<DnsRecord> = Vec::new();
+        let mut buffer = [0; 9000];
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining == Duration::from_secs(0) {
+                return Ok(records);
+            }
+            socket.set_read_timeout(Some(remaining))?;
+
+            let size = match socket.recv_from(&mut buffer) {
+                Ok((size, _)) => size,
+                Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                    return Ok(records)
+                }
+                Err(e) => return Err(e),
+            };
+            let response = match MdnsPacket::parse(&buffer[..size]) {
+                Ok(response) if response.header.flags.response => response,
+                _ => continue,
+            };
+
+            for record in response
+                .answers
+                .into_iter()
+                .chain(response.additional_records)
+                .map(|r| r.record)
+            {
+                if !records.contains(&record) {
+                    records.push(record);
+                }
+            }
+        }
+    }
+}
+
+fn unspecified_address(target: SocketAddr) -> SocketAddr {
+    match target {
+        SocketAddr::V4(_) => SocketAddr::from(([0, 0, 0, 0], 0)),
+        SocketAddr::V6(_) => SocketAddr::from(([0u16; 8], 0)),
+    }
+}
+
+fn question(name: &DnsName, record_type: DnsRecordType) -> DnsQuery {
+    DnsQuery {
+        name: name.clone(),
+        record_type: record_type.into(),
+        class: DnsClass::IN.into(),
+    }
+}
+
+// Lists the instances of a service type, e.g. "_http._tcp.local"
+// https://datatracker.ietf.org/doc/html/rfc6763#section-4
+pub fn browse(transport: &impl Transport, service_type: &str) -> Result<Vec<DnsName>> {
+    let service_type = DnsName::from(service_type);
+    let mut instances: Vec<DnsName> = Vec::new();
+
+    for record in transport.query(&question(&service_type, DnsRecordType::PTR))? {
+        if let DnsRecord::PTR { name, pointer, .. } = record {
+            if same_name(&name, &service_type) && !instances.iter().any(|i| same_name(i, &pointer))
+            {
+                instances.push(pointer);
+            }
+        }
+    }
+
+    Ok(instances)
+}
+
+// https://datatracker.ietf.org/doc/html/rfc6763#section-5
+pub fn resolve(transport: &impl Transport, instance: &DnsName) -> Result<Option<ServiceInstance>> {
+    let mut records = transport.query(&question(instance, DnsRecordType::SRV))?;
+    let (host, port) = match records.iter().find_map(|r| match r {
+        DnsRecord::SRV {
+            name, target, port, ..
+        } if same_name(name, instance) => Some((target.clone(), *port)),
+        _ => None,
+    }) {
+        Some(found) => found,
+        None => return Ok(None),
+    };
+
+    // Responders usually send the TXT and address records along with the SRV record
+    let find_txt = |records: &[DnsRecord]| {
+        records.iter().find_map(|r| match r {
+            DnsRecord::TXT { name, data, .. } if same_name(name, instance) => {
+                Some(TxtRecord::decode(data))
+            }
+            _ => None,
+        })
+    };
+    let txt = match find_txt(&records) {
+        Some(txt) => txt,
+        None => {
+            find_txt(&transport.query(&question(instance, DnsRecordType::TXT))?).unwrap_or_default()
+        }
+    };
+
+    let find_addresses = |records: &[DnsRecord]| -> Vec<IpAddr> {
+        records
+            .iter()
+            .filter(|r| same_name(r.get_name(), &host))
+            .filter_map(DnsRecord::get_address)
+            .collect()
+    };
+    let mut addresses = find_addresses(&records);
+    if addresses.is_empty() {
+        records = transport.query(&question(&host, DnsRecordType::A))?;
+        records.extend(transport.query(&question(&host, DnsRecordType::AAAA))?);
+        addresses = find_addresses(&records);
+    }
+
+    Ok(Some(ServiceInstance {
+        name: instance.clone(),
+        host,
+        port,
+        addresses,
+        txt,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::responder::{Config, Responder, Service, State};
+    use std::cell::RefCell;
+    use std::net::Ipv4Addr;
+
+    // Answers queries straight from a responder, as a legacy unicast querier would see it
+    struct ResponderTransport(RefCell<Responder>);
+
+    impl Transport for ResponderTransport {
+        fn query(&self, query: &DnsQuery) -> Result<Vec<DnsRecord>> {
+            let mut request = MdnsPacket::new();
+            request.queries.push(MdnsQuery {
+                query: query.clone(),
+                unicast_response: false,
+            });
+            let source = SocketAddr::from(([192, 168, 1, 20], 50000));
+
+            Ok(
+                match self.0.borrow_mut().handle(&request, source, Instant::now()) {
+                    Some((response, _)) => response
+                        .answers
+                        .into_iter()
+                        .chain(response.additional_records)
+                        .map(|r| r.record)
+                        .collect(),
+                    None => Vec::new(),
+                },
+            )
+        }
+    }
+
+    fn transport() -> ResponderTransport {
+        let mut txt = TxtRecord::new();
+        txt.insert("path", Some(b"/admin")).unwrap();
+        txt.insert("tls", None).unwrap();
+
+        let mut now = Instant::now();
+        let mut responder = Responder::new(
+            Config {
+                hostname: "nas".to_owned(),
+                addresses: vec![IpAddr::V4(Ipv4Addr::new(192, 168, 1, 5))],
+                services: vec![Service {
+                    instance: "Web UI. v2".to_owned(),
+                    service_type: "_http._tcp".to_owned(),
+                    port: 8080,
+                    txt,
+                }],
+            },
+            now,
+        );
+        while responder.state() != State::Running {
+            now += Duration::from_secs(1);
+            responder.tick(now);
+        }
+        ResponderTransport(RefCell::new(responder))
+    }
+
+    #[test]
+    fn test_browse_and_resolve() {
+        let transport = transport();
+
+        let instances = browse(&transport, "_http._tcp.local").unwrap();
+        assert_eq!(instances.len(), 1);
+        assert!(browse(&transport, "_ipp._tcp.local").unwrap().is_empty());
+
+        let instance = resolve(&transport, &instances[0]).unwrap().unwrap();
+        assert_eq!(instance.instance(), "Web UI. v2");
+        assert_eq!(instance.host, DnsName::from("nas.local"));
+        assert_eq!(instance.port, 8080);
+        assert_eq!(
+            instance.addresses,
+            vec![IpAddr::V4(Ipv4Addr::new(192, 168, 1, 5))]
+        );
+        assert_eq!(instance.txt.get("PATH"), Some(Some(&b"/admin"[..])));
+        assert_eq!(instance.txt.get("tls"), Some(None));
+        assert_eq!(instance.txt.get("missing"), None);
+    }
+
+    #[test]
+    fn test_txt_encoding() {
+        let mut txt = TxtRecord::new();
+        assert_eq!(txt.encode(), vec![Vec::<u8>::new()]);
+
+        txt.insert("paper", Some(b"A4")).unwrap();
+        txt.insert("color", None).unwrap();
+        txt.insert("note", Some(b"")).unwrap();
+        txt.insert("PAPER", Some(b"Letter")).unwrap();
+        assert!(txt.insert("a=b", None).is_err());
+        assert!(txt.insert("", None).is_err());
+        assert!(txt.insert("long", Some(&[0; 255])).is_err());
+
+        assert_eq!(
+            txt.encode(),
+            vec![
+                b"color".to_vec(),
+                b"note=".to_vec(),
+                b"PAPER=Letter".to_vec()
+            ]
+        );
+        assert_eq!(TxtRecord::decode(&txt.encode()), txt);
+        assert_eq!(
+            "color,note=,PAPER=Letter".parse::<TxtRecord>().unwrap(),
+            txt
+        );
+    }
+
+    #[test]
+    fn test_txt_decoding() {
+        let txt = TxtRecord::decode(&[
+            b"".to_vec(),
+            b"=ignored".to_vec(),
+            b"v=1".to_vec(),
+            b"V=2".to_vec(),
+            b"flag".to_vec(),
+            b"eq=a=b".to_vec(),
+        ]);
+
+        assert_eq!(txt.get("v"), Some(Some(&b"1"[..])));
+        assert_eq!(txt.get("flag"), Some(None));
+        assert_eq!(txt.get("eq"), Some(Some(&b"a=b"[..])));
+        assert_eq!(txt.entries().len(), 3);
+    }
+}
@@ -0,0 +1,4 @@
+pub mod dnssd;
+pub mod responder;
+pub mod server;
+pub mod socket;
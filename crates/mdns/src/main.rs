@@ -0,0 +1,28 @@
+mod cli;
+
+use clap::Clap;
+use cli::Opts;
+use mdns::responder::{Config, Responder};
+use mdns::server;
+use std::io::Result;
+use std::time::Instant;
+
+fn main() -> Result<()> {
+    let opts = Opts::parse();
+
+    let responder = Responder::new(
+        Config {
+            hostname: opts.hostname.clone(),
+            addresses: opts.address.clone(),
+            services: opts.service.clone(),
+        },
+        Instant::now(),
+    );
+    println!(
+        "Responder listening on port {} for {}",
+        parser::mdns::MDNS_PORT,
+        responder.host_name()
+    );
+
+    server::serve(responder, opts.verbose)
+}
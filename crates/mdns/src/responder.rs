@@ -0,0 +1,997 @@
+use crate::dnssd::TxtRecord;
+use parser::header::flags::DnsHeaderFlags;
+use parser::mdns::{MdnsPacket, MdnsQuery, MdnsRecord, MDNS_PORT};
+use parser::resources::name::{DnsLabel, DnsName};
+use parser::resources::query::DnsQuery;
+use parser::resources::record::DnsRecord;
+use parser::resources::{DnsClass, QueryClass, QueryType};
+use rand::Rng;
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+// https://datatracker.ietf.org/doc/html/rfc6762#section-10
+pub const HOST_RECORD_TTL: u32 = 120;
+pub const OTHER_RECORD_TTL: u32 = 4500;
+// https://datatracker.ietf.org/doc/html/rfc6762#section-6.7
+const LEGACY_UNICAST_TTL: u32 = 10;
+
+// https://datatracker.ietf.org/doc/html/rfc6762#section-8
+const PROBE_COUNT: u8 = 3;
+const PROBE_INTERVAL: Duration = Duration::from_millis(250);
+const ANNOUNCE_COUNT: u8 = 2;
+const ANNOUNCE_INTERVAL: Duration = Duration::from_secs(1);
+const CONFLICT_DELAY: Duration = Duration::from_secs(1);
+
+// Multicast responses holding shared records are delayed, so that the ones of the other
+// responders can be suppressed by the known answers of the next queries
+// https://datatracker.ietf.org/doc/html/rfc6762#section-6
+const MIN_SHARED_DELAY: Duration = Duration::from_millis(20);
+const MAX_SHARED_DELAY: Duration = Duration::from_millis(120);
+// A record is multicast at most this often, or a bit more often to defend it against a probe
+// https://datatracker.ietf.org/doc/html/rfc6762#section-6.2
+const MULTICAST_INTERVAL: Duration = Duration::from_secs(1);
+const PROBE_DEFENSE_INTERVAL: Duration = Duration::from_millis(250);
+
+// https://datatracker.ietf.org/doc/html/rfc6763#section-9
+const SERVICES_ENUMERATION_NAME: &str = "_services._dns-sd._udp.local";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Service {
+    pub instance: String,
+    // Service type and transport, e.g. "_http._tcp"
+    pub service_type: String,
+    pub port: u16,
+    pub txt: TxtRecord,
+}
+
+// Parses "<instance>,<service type>,<port>[,<key>[=<value>]...]"
+impl FromStr for Service {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split(',');
+        let instance = parts.next().filter(|p| !p.is_empty());
+        let service_type = parts.next().filter(|p| !p.is_empty());
+        let port = parts.next().map(str::parse::<u16>);
+
+        match (instance, service_type, port) {
+            (Some(instance), Some(service_type), Some(Ok(port))) => Ok(Self {
+                instance: instance.to_owned(),
+                service_type: service_type.to_owned(),
+                port,
+                txt: parts
+                    .collect::<Vec<_>>()
+                    .join(",")
+                    .parse::<TxtRecord>()?,
+            }),
+            _ => Err(format!(
+                "Invalid service {:?}, expected <instance>,<service type>,<port>[,<key>[=<value>]...]",
+                s
+            )),
+        }
+    }
+}
+
+impl Service {
+    pub fn type_name(&self) -> DnsName {
+        DnsName::from(format!("{}.local", self.service_type).as_str())
+    }
+
+    // The instance label may contain dots, so it can't go through DnsName::from(&str)
+    pub fn instance_name(&self) -> DnsName {
+        let mut labels = vec![DnsLabel::new(self.instance.as_bytes())];
+        labels.extend_from_slice(self.type_name().labels());
+        DnsName::from(labels)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub hostname: String,
+    pub addresses: Vec<IpAddr>,
+    pub services: Vec<Service>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Destination {
+    Multicast,
+    Unicast(SocketAddr),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum State {
+    Probing { sent: u8 },
+    Announcing { sent: u8 },
+    Running,
+}
+
+pub struct Responder {
+    config: Config,
+    state: State,
+    next_at: Option<Instant>,
+    // Unique names not established yet, which are probed then announced while the others keep
+    // being answered
+    pending: Vec<DnsName>,
+    // Multicast responses waiting for their delay to go by
+    delayed: Vec<(Instant, MdnsPacket)>,
+    // When each record was last multicast, by name and key
+    multicast_at: HashMap<(DnsName, Vec<u8>), Instant>,
+}
+
+impl Responder {
+    pub fn new(config: Config, now: Instant) -> Self {
+        // The first probe is delayed randomly so that hosts powered on together don't collide
+        let delay = rand::thread_rng().gen_range(0..PROBE_INTERVAL.as_millis() as u64);
+
+        let mut responder = Self {
+            config,
+            state: State::Probing { sent: 0 },
+            next_at: Some(now + Duration::from_millis(delay)),
+            pending: Vec::new(),
+            delayed: Vec::new(),
+            multicast_at: HashMap::new(),
+        };
+        responder.pending = responder.unique_names();
+        responder
+    }
+
+    pub fn state(&self) -> State {
+        self.state
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    pub fn host_name(&self) -> DnsName {
+        DnsName::from(format!("{}.local", self.config.hostname).as_str())
+    }
+
+    // Names we claim ownership of, which have to be probed before being used
+    fn unique_names(&self) -> Vec<DnsName> {
+        let mut names = vec![self.host_name()];
+        names.extend(self.config.services.iter().map(Service::instance_name));
+        names
+    }
+
+    fn is_pending(&self, name: &DnsName) -> bool {
+        self.pending.iter().any(|pending| same_name(pending, name))
+    }
+
+    // Whether the name is still being probed, so that it can't be answered for yet
+    fn is_probing(&self, name: &DnsName) -> bool {
+        matches!(self.state, State::Probing { .. }) && self.is_pending(name)
+    }
+
+    pub fn records(&self) -> Vec<MdnsRecord> {
+        self.records_where(|_| true)
+    }
+
+    // Records of the host and of the services whose unique name is kept by `include`
+    fn records_where(&self, include: impl Fn(&DnsName) -> bool) -> Vec<MdnsRecord> {
+        let host_name = self.host_name();
+        let addresses: &[IpAddr] = if include(&host_name) {
+            &self.config.addresses
+        } else {
+            &[]
+        };
+        let mut records: Vec<MdnsRecord> = addresses
+            .iter()
+            .map(|address| MdnsRecord {
+                record: match address {
+                    IpAddr::V4(address) => DnsRecord::A {
+                        name: host_name.clone(),
+                        class: DnsClass::IN,
+                        ttl: HOST_RECORD_TTL,
+                        address: *address,
+                    },
+                    IpAddr::V6(address) => DnsRecord::AAAA {
+                        name: host_name.clone(),
+                        class: DnsClass::IN,
+                        ttl: HOST_RECORD_TTL,
+                        address: *address,
+                    },
+                },
+                cache_flush: true,
+            })
+            .collect();
+
+        for service in &self.config.services {
+            let type_name = service.type_name();
+            let instance_name = service.instance_name();
+            if !include(&instance_name) {
+                continue;
+            }
+            // Shared records can be answered by many hosts, so they don't flush caches
+            let enumeration = MdnsRecord {
+                record: DnsRecord::PTR {
+                    name: DnsName::from(SERVICES_ENUMERATION_NAME),
+                    class: DnsClass::IN,
+                    ttl: OTHER_RECORD_TTL,
+                    pointer: type_name.clone(),
+                },
+                cache_flush: false,
+            };
+            if !records.contains(&enumeration) {
+                records.push(enumeration);
+            }
+            records.push(MdnsRecord {
+                record: DnsRecord::PTR {
+                    name: type_name,
+                    class: DnsClass::IN,
+                    ttl: OTHER_RECORD_TTL,
+                    pointer: instance_name.clone(),
+                },
+                cache_flush: false,
+            });
+            records.push(MdnsRecord {
+                record: DnsRecord::SRV {
+                    name: instance_name.clone(),
+                    class: DnsClass::IN,
+                    ttl: HOST_RECORD_TTL,
+                    priority: 0,
+                    weight: 0,
+                    port: service.port,
+                    target: host_name.clone(),
+                },
+                cache_flush: true,
+            });
+            records.push(MdnsRecord {
+                record: DnsRecord::TXT {
+                    name: instance_name,
+                    class: DnsClass::IN,
+                    ttl: OTHER_RECORD_TTL,
+                    data: service.txt.encode(),
+                },
+                cache_flush: true,
+            });
+        }
+
+        records
+    }
+
+    // Adds a service, whose name is probed before it is announced. The names already established
+    // keep being answered meanwhile
+    pub fn register(&mut self, service: Service, now: Instant) {
+        self.pending.push(service.instance_name());
+        self.config.services.push(service);
+        self.restart_probing(now);
+    }
+
+    // Removes a service and returns the goodbye to multicast so caches forget it
+    // https://datatracker.ietf.org/doc/html/rfc6762#section-10.1
+    pub fn unregister(&mut self, instance: &str) -> Option<MdnsPacket> {
+        let index = self
+            .config
+            .services
+            .iter()
+            .position(|s| s.instance == instance)?;
+        let before = self.records();
+        let service = self.config.services.remove(index);
+        let after = self.records();
+        let name = service.instance_name();
+        self.pending.retain(|pending| !same_name(pending, &name));
+        if self.pending.is_empty() {
+            self.state = State::Running;
+            self.next_at = None;
+        }
+
+        let mut packet = MdnsPacket::new();
+        packet.header.set_flags(response_flags());
+        packet.answers = before
+            .into_iter()
+            .filter(|r| !after.contains(r))
+            .map(|mut r| {
+                r.record.set_ttl(0);
+                r
+            })
+            .collect();
+        Some(packet)
+    }
+
+    pub fn next_timeout(&self) -> Option<Instant> {
+        self.delayed
+            .iter()
+            .map(|(at, _)| *at)
+            .chain(self.next_at)
+            .min()
+    }
+
+    // Returns the probe, announcement or delayed response to multicast once its time has come
+    pub fn tick(&mut self, now: Instant) -> Option<MdnsPacket> {
+        if let Some(index) = self.delayed.iter().position(|(at, _)| *at <= now) {
+            return Some(self.delayed.remove(index).1);
+        }
+
+        if now < self.next_at? {
+            return None;
+        }
+
+        if self.state == (State::Probing { sent: PROBE_COUNT }) {
+            self.state = State::Announcing { sent: 0 };
+        }
+
+        match self.state {
+            State::Probing { sent } => {
+                self.state = State::Probing { sent: sent + 1 };
+                self.next_at = Some(now + PROBE_INTERVAL);
+                Some(self.probe(sent == 0))
+            }
+            State::Announcing { sent } => {
+                let announcement = self.announcement();
+                self.multicast(&announcement.answers, now);
+                if sent + 1 < ANNOUNCE_COUNT {
+                    self.state = State::Announcing { sent: sent + 1 };
+                    self.next_at = Some(now + ANNOUNCE_INTERVAL);
+                } else {
+                    self.state = State::Running;
+                    self.next_at = None;
+                    self.pending.clear();
+                }
+                Some(announcement)
+            }
+            State::Running => {
+                self.next_at = None;
+                None
+            }
+        }
+    }
+
+    // https://datatracker.ietf.org/doc/html/rfc6762#section-8.1
+    fn probe(&self, unicast_response: bool) -> MdnsPacket {
+        let records = self.records();
+        let mut packet = MdnsPacket::new();
+
+        for name in &self.pending {
+            packet.queries.push(MdnsQuery {
+                query: DnsQuery {
+                    name: name.clone(),
+                    record_type: QueryType::ANY,
+                    class: DnsClass::IN.into(),
+                },
+                unicast_response,
+            });
+            // The proposed records are used to break ties with simultaneous probes
+            packet.authorities.extend(
+                records
+                    .iter()
+                    .filter(|r| same_name(r.record.get_name(), name))
+                    .map(|r| MdnsRecord {
+                        record: r.record.clone(),
+                        cache_flush: false,
+                    }),
+            );
+        }
+
+        packet
+    }
+
+    // Only the newly probed records are announced
+    // https://datatracker.ietf.org/doc/html/rfc6762#section-8.3
+    fn announcement(&self) -> MdnsPacket {
+        let mut packet = MdnsPacket::new();
+        packet.header.set_flags(response_flags());
+        packet.answers = self.records_where(|name| self.is_pending(name));
+        packet
+    }
+
+    pub fn handle(
+        &mut self,
+        packet: &MdnsPacket,
+        source: SocketAddr,
+        now: Instant,
+    ) -> Option<(MdnsPacket, Destination)> {
+        if packet.header.flags.response {
+            self.detect_conflicts(packet, now);
+            return None;
+        }
+
+        if let State::Probing { .. } = self.state {
+            self.break_probe_tie(packet, now);
+        }
+
+        self.answer(packet, source, now)
+    }
+
+    // https://datatracker.ietf.org/doc/html/rfc6762#section-9
+    fn detect_conflicts(&mut self, packet: &MdnsPacket, now: Instant) {
+        let ours = self.records();
+
+        let conflict = self.unique_names().into_iter().find(|name| {
+            let probing = self.is_probing(name);
+            packet
+                .answers
+                .iter()
+                .chain(&packet.additional_records)
+                .filter(|theirs| same_name(theirs.record.get_name(), name))
+                .any(|theirs| {
+                    let mut ours = ours
+                        .iter()
+                        .filter(|r| same_name(r.record.get_name(), name))
+                        // Once probed, only records of the same type can conflict
+                        .filter(|r| probing || r.record.type_code() == theirs.record.type_code())
+                        .peekable();
+                    ours.peek().is_some()
+                        && ours.all(|r| record_key(&r.record) != record_key(&theirs.record))
+                })
+        });
+
+        if let Some(name) = conflict {
+            if self.is_probing(&name) {
+                self.rename(&name);
+            } else if !self.is_pending(&name) {
+                // The name goes back to probing, which renames it if the conflict persists
+                self.pending.push(name);
+            }
+            self.restart_probing(now);
+        }
+    }
+
+    // https://datatracker.ietf.org/doc/html/rfc6762#section-8.2
+    fn break_probe_tie(&mut self, packet: &MdnsPacket, now: Instant) {
+        let ours = self.records();
+
+        for name in &self.pending {
+            let mut theirs: Vec<Vec<u8>> = packet
+                .authorities
+                .iter()
+                .filter(|r| same_name(r.record.get_name(), name))
+                .map(|r| record_key(&r.record))
+                .collect();
+            if theirs.is_empty() {
+                continue;
+            }
+
+            let mut our_keys: Vec<Vec<u8>> = ours
+                .iter()
+                .filter(|r| same_name(r.record.get_name(), name))
+                .map(|r| record_key(&r.record))
+                .collect();
+            theirs.sort();
+            our_keys.sort();
+
+            // The lexicographically later set wins, the loser waits a second and probes again
+            if our_keys < theirs {
+                self.state = State::Probing { sent: 0 };
+                self.next_at = Some(now + CONFLICT_DELAY);
+                return;
+            }
+        }
+    }
+
+    fn rename(&mut self, name: &DnsName) {
+        let renamed = if same_name(name, &self.host_name()) {
+            self.config.hostname = next_host_name(&self.config.hostname);
+            self.host_name()
+        } else if let Some(service) = self
+            .config
+            .services
+            .iter_mut()
+            .find(|s| same_name(&s.instance_name(), name))
+        {
+            service.instance = next_instance_name(&service.instance);
+            service.instance_name()
+        } else {
+            return;
+        };
+
+        for pending in self.pending.iter_mut().filter(|p| same_name(p, name)) {
+            *pending = renamed.clone();
+        }
+    }
+
+    fn restart_probing(&mut self, now: Instant) {
+        self.state = State::Probing { sent: 0 };
+        self.next_at = Some(now);
+    }
+
+    // Whether the record was multicast less than `interval` ago
+    fn is_multicast_since(&self, record: &DnsRecord, interval: Duration, now: Instant) -> bool {
+        match self.multicast_at.get(&multicast_key(record)) {
+            Some(&at) => now.saturating_duration_since(at) < interval,
+            None => false,
+        }
+    }
+
+    fn multicast(&mut self, records: &[MdnsRecord], now: Instant) {
+        self.multicast_at
+            .retain(|_, at| now.saturating_duration_since(*at) < MULTICAST_INTERVAL);
+        for record in records {
+            self.multicast_at.insert(multicast_key(&record.record), now);
+        }
+    }
+
+    // Multicast responses are returned by `tick` once their delay went by when they hold shared
+    // records, None being returned here
+    // https://datatracker.ietf.org/doc/html/rfc6762#section-6
+    fn answer(
+        &mut self,
+        request: &MdnsPacket,
+        source: SocketAddr,
+        now: Instant,
+    ) -> Option<(MdnsPacket, Destination)> {
+        let records = self.records_where(|name| !self.is_probing(name));
+        // Queries not sent from the mDNS port come from simple resolvers expecting a unicast reply
+        let legacy = source.port() != MDNS_PORT;
+        let multicast = !legacy && !request.queries.iter().all(|q| q.unicast_response);
+        // Probes carry the records they propose
+        let interval = if request.authorities.is_empty() {
+            MULTICAST_INTERVAL
+        } else {
+            PROBE_DEFENSE_INTERVAL
+        };
+
+        let mut answers: Vec<MdnsRecord> = Vec::new();
+        for question in &request.queries {
+            for record in &records {
+                if answers.contains(record)
+                    || !matches_query(&question.query, &record.record)
+                    || is_known_answer(request, &record.record)
+                    || (multicast && self.is_multicast_since(&record.record, interval, now))
+                {
+                    continue;
+                }
+                answers.push(record.clone());
+            }
+        }
+
+        if answers.is_empty() {
+            return None;
+        }
+
+        let mut additional_records: Vec<MdnsRecord> = Vec::new();
+        let mut add_records_named = |name: &DnsName, answers: &[MdnsRecord]| {
+            for record in &records {
+                if same_name(record.record.get_name(), name)
+                    && !answers.contains(record)
+                    && !additional_records.contains(record)
+                {
+                    additional_records.push(record.clone());
+                }
+            }
+        };
+        // https://datatracker.ietf.org/doc/html/rfc6763#section-12
+        for answer in &answers {
+            match &answer.record {
+                DnsRecord::PTR { pointer, .. } => {
+                    add_records_named(pointer, &answers);
+                    add_records_named(&self.host_name(), &answers);
+                }
+                DnsRecord::SRV { target, .. } => add_records_named(target, &answers),
+                _ => {}
+            }
+        }
+
+        let mut response = MdnsPacket::new();
+        response.header.set_flags(response_flags());
+        response.answers = answers;
+        response.additional_records = additional_records;
+
+        if legacy {
+            // https://datatracker.ietf.org/doc/html/rfc6762#section-6.7
+            response.header.transaction_id = request.header.transaction_id;
+            response.queries = request
+                .queries
+                .iter()
+                .map(|q| MdnsQuery {
+                    query: q.query.clone(),
+                    unicast_response: false,
+                })
+                .collect();
+            for record in response
+                .answers
+                .iter_mut()
+                .chain(response.additional_records.iter_mut())
+            {
+                record.cache_flush = false;
+                let ttl = record.record.get_ttl().unwrap_or(0);
+                record.record.set_ttl(ttl.min(LEGACY_UNICAST_TTL));
+            }
+            return Some((response, Destination::Unicast(source)));
+        }
+
+        if !multicast {
+            return Some((response, Destination::Unicast(source)));
+        }
+
+        self.multicast(&response.answers, now);
+        if response.answers.iter().all(|r| r.cache_flush) {
+            return Some((response, Destination::Multicast));
+        }
+        let delay = rand::thread_rng().gen_range(MIN_SHARED_DELAY..=MAX_SHARED_DELAY);
+        self.delayed.push((now + delay, response));
+        None
+    }
+}
+
+fn response_flags() -> DnsHeaderFlags {
+    DnsHeaderFlags {
+        response: true,
+        authoritative: true,
+        ..Default::default()
+    }
+}
+
+// mDNS names are compared without regard to ASCII case
+pub fn same_name(a: &DnsName, b: &DnsName) -> bool {
+    a.eq_ignore_case(b)
+}
+
+fn matches_query(query: &DnsQuery, record: &DnsRecord) -> bool {
+    let type_matches = match query.record_type {
+        QueryType::ANY => true,
+        QueryType::Record(_) | QueryType::Unknown(_) => {
+            u16::from(query.record_type) == record.type_code()
+        }
+        _ => false,
+    };
+    let class_matches = matches!(
+        query.class,
+        QueryClass::ANY | QueryClass::Class(DnsClass::IN)
+    );
+
+    type_matches && class_matches && same_name(&query.name, record.get_name())
+}
+
+// https://datatracker.ietf.org/doc/html/rfc6762#section-7.1
+fn is_known_answer(request: &MdnsPacket, record: &DnsRecord) -> bool {
+    let ttl = record.get_ttl().unwrap_or(0);
+    request.answers.iter().any(|known| {
+        same_name(known.record.get_name(), record.get_name())
+            && record_key(&known.record) == record_key(record)
+            && known.record.get_ttl().unwrap_or(0) >= ttl / 2
+    })
+}
+
+fn multicast_key(record: &DnsRecord) -> (DnsName, Vec<u8>) {
+    (record.get_name().to_lowercase(), record_key(record))
+}
+
+// Class, type and uncompressed data, in the order used to compare records
+// https://datatracker.ietf.org/doc/html/rfc6762#section-8.2.1
+fn record_key(record: &DnsRecord) -> Vec<u8> {
+    let bytes = record.to_bytes().unwrap();
+    let name_length = record.get_name().to_bytes().unwrap().len();
+    let mut key = bytes[name_length + 2..name_length + 4].to_vec();
+    key.extend_from_slice(&bytes[name_length..name_length + 2]);
+    key.extend_from_slice(&bytes[name_length + 10..]);
+    key
+}
+
+// "host" becomes "host-2", "host-2" becomes "host-3" and so on
+fn next_host_name(name: &str) -> String {
+    match name.rsplit_once('-') {
+        Some((base, number)) => match number.parse::<u32>() {
+            Ok(number) => format!("{}-{}", base, number + 1),
+            Err(_) => format!("{}-2", name),
+        },
+        None => format!("{}-2", name),
+    }
+}
+
+// "My Printer" becomes "My Printer (2)", "My Printer (2)" becomes "My Printer (3)" and so on
+// https://datatracker.ietf.org/doc/html/rfc6763#appendix-D
+fn next_instance_name(name: &str) -> String {
+    let numbered = name
+        .strip_suffix(')')
+        .and_then(|n| n.rsplit_once(" ("))
+        .and_then(|(base, number)| number.parse::<u32>().ok().map(|n| (base, n)));
+
+    match numbered {
+        Some((base, number)) => format!("{} ({})", base, number + 1),
+        None => format!("{} (2)", name),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parser::resources::DnsRecordType;
+    use std::net::Ipv4Addr;
+
+    fn config() -> Config {
+        Config {
+            hostname: "printer".to_owned(),
+            addresses: vec![IpAddr::V4(Ipv4Addr::new(192, 168, 1, 10))],
+            services: vec!["Office Printer,_ipp._tcp,631,rp=queue"
+                .parse::<Service>()
+                .unwrap()],
+        }
+    }
+
+    fn running_responder() -> (Responder, Instant) {
+        let mut now = Instant::now();
+        let mut responder = Responder::new(config(), now);
+        while responder.state() != State::Running {
+            now += Duration::from_secs(1);
+            responder.tick(now);
+        }
+        // The announced records can be multicast again
+        (responder, now + MULTICAST_INTERVAL)
+    }
+
+    fn query(name: &str, record_type: DnsRecordType) -> MdnsPacket {
+        let mut packet = MdnsPacket::new();
+        packet.queries.push(MdnsQuery {
+            query: DnsQuery {
+                name: DnsName::from(name),
+                record_type: record_type.into(),
+                class: DnsClass::IN.into(),
+            },
+            unicast_response: false,
+        });
+        packet
+    }
+
+    fn mdns_source() -> SocketAddr {
+        SocketAddr::from(([192, 168, 1, 20], MDNS_PORT))
+    }
+
+    // The response to the request, whether it is sent right away or once its delay went by
+    fn respond(
+        responder: &mut Responder,
+        request: &MdnsPacket,
+        now: Instant,
+    ) -> Option<(MdnsPacket, Destination)> {
+        responder.handle(request, mdns_source(), now).or_else(|| {
+            let packet = responder.tick(now + MAX_SHARED_DELAY)?;
+            Some((packet, Destination::Multicast))
+        })
+    }
+
+    #[test]
+    fn test_probes_then_announces() {
+        let mut now = Instant::now();
+        let mut responder = Responder::new(config(), now);
+
+        let mut packets = Vec::new();
+        while responder.state() != State::Running {
+            now += PROBE_INTERVAL;
+            packets.extend(responder.tick(now));
+        }
+
+        assert_eq!(packets.len(), 5);
+        for (index, probe) in packets[..3].iter().enumerate() {
+            assert!(!probe.header.flags.response);
+            assert_eq!(probe.queries.len(), 2);
+            assert_eq!(probe.queries[0].unicast_response, index == 0);
+            assert_eq!(probe.queries[0].query.record_type, QueryType::ANY);
+            assert!(probe.authorities.iter().all(|r| !r.cache_flush));
+        }
+        for announcement in &packets[3..] {
+            assert!(announcement.header.flags.response);
+            assert_eq!(announcement.answers, responder.records());
+        }
+        assert_eq!(responder.tick(now + Duration::from_secs(10)), None);
+    }
+
+    #[test]
+    fn test_answers_browse_with_additional_records() {
+        let (mut responder, now) = running_responder();
+
+        let request = query("_ipp._tcp.local", DnsRecordType::PTR);
+        let (response, destination) = respond(&mut responder, &request, now).unwrap();
+
+        assert_eq!(destination, Destination::Multicast);
+        assert_eq!(response.answers.len(), 1);
+        assert!(!response.answers[0].cache_flush);
+        let types: Vec<_> = response
+            .additional_records
+            .iter()
+            .map(|r| r.record.get_type().unwrap())
+            .collect();
+        assert_eq!(
+            types,
+            vec![DnsRecordType::SRV, DnsRecordType::TXT, DnsRecordType::A]
+        );
+    }
+
+    #[test]
+    fn test_known_answer_suppression() {
+        let (mut responder, now) = running_responder();
+        let mut request = query("_ipp._tcp.local", DnsRecordType::PTR);
+        let mut known = responder
+            .records()
+            .into_iter()
+            .find(|r| same_name(r.record.get_name(), &DnsName::from("_ipp._tcp.local")))
+            .unwrap();
+        request.answers.push(known.clone());
+
+        assert_eq!(respond(&mut responder, &request, now), None);
+
+        // A known answer about to expire doesn't suppress ours
+        known.record.set_ttl(OTHER_RECORD_TTL / 2 - 1);
+        request.answers[0] = known;
+        assert!(respond(&mut responder, &request, now).is_some());
+    }
+
+    #[test]
+    fn test_legacy_unicast_response() {
+        let (mut responder, now) = running_responder();
+        let mut request = query("PRINTER.local", DnsRecordType::A);
+        request.header.transaction_id = 4242;
+        let source = SocketAddr::from(([192, 168, 1, 20], 53124));
+
+        let (response, destination) = responder.handle(&request, source, now).unwrap();
+
+        assert_eq!(destination, Destination::Unicast(source));
+        assert_eq!(response.header.transaction_id, 4242);
+        assert_eq!(response.queries, request.queries);
+        assert_eq!(response.answers.len(), 1);
+        assert!(!response.answers[0].cache_flush);
+        assert_eq!(
+            response.answers[0].record.get_ttl(),
+            Some(LEGACY_UNICAST_TTL)
+        );
+    }
+
+    #[test]
+    fn test_conflict_while_probing_renames() {
+        let now = Instant::now();
+        let mut responder = Responder::new(config(), now);
+        responder.tick(now + PROBE_INTERVAL);
+
+        let mut response = MdnsPacket::new();
+        response.header.set_flags(response_flags());
+        response.answers.push(MdnsRecord {
+            record: DnsRecord::A {
+                name: DnsName::from("printer.local"),
+                class: DnsClass::IN,
+                ttl: HOST_RECORD_TTL,
+                address: Ipv4Addr::new(192, 168, 1, 99),
+            },
+            cache_flush: true,
+        });
+        responder.handle(&response, mdns_source(), now);
+
+        assert_eq!(responder.host_name(), DnsName::from("printer-2.local"));
+        assert_eq!(responder.state(), State::Probing { sent: 0 });
+
+        // Our own announcements looping back are not conflicts
+        let (mut responder, now) = running_responder();
+        let mut announcement = MdnsPacket::new();
+        announcement.header.set_flags(response_flags());
+        announcement.answers = responder.records();
+        responder.handle(&announcement, mdns_source(), now);
+        assert_eq!(responder.state(), State::Running);
+    }
+
+    #[test]
+    fn test_simultaneous_probe_tiebreak() {
+        let now = Instant::now();
+        let mut responder = Responder::new(config(), now);
+        responder.tick(now + PROBE_INTERVAL);
+
+        let mut probe = responder.probe(false);
+        for authority in probe.authorities.iter_mut() {
+            if let DnsRecord::A { address, .. } = &mut authority.record {
+                *address = Ipv4Addr::new(192, 168, 1, 1);
+            }
+        }
+        responder.handle(&probe, mdns_source(), now);
+        assert_eq!(responder.state(), State::Probing { sent: 1 });
+
+        for authority in probe.authorities.iter_mut() {
+            if let DnsRecord::A { address, .. } = &mut authority.record {
+                *address = Ipv4Addr::new(192, 168, 1, 200);
+            }
+        }
+        responder.handle(&probe, mdns_source(), now);
+        assert_eq!(responder.state(), State::Probing { sent: 0 });
+        assert_eq!(responder.next_timeout(), Some(now + CONFLICT_DELAY));
+    }
+
+    #[test]
+    fn test_register_and_unregister() {
+        let (mut responder, now) = running_responder();
+        responder.register("Scanner,_uscan._tcp,8080".parse::<Service>().unwrap(), now);
+        assert_eq!(responder.state(), State::Probing { sent: 0 });
+        assert_eq!(responder.records().len(), 9);
+
+        let goodbye = responder.unregister("Office Printer").unwrap();
+        assert_eq!(goodbye.answers.len(), 4);
+        assert!(goodbye
+            .answers
+            .iter()
+            .all(|r| r.record.get_ttl() == Some(0)));
+        assert_eq!(responder.records().len(), 5);
+        assert_eq!(responder.unregister("Office Printer"), None);
+    }
+
+    #[test]
+    fn test_register_keeps_answering_established_names() {
+        let (mut responder, mut now) = running_responder();
+        responder.register("Scanner,_uscan._tcp,8080".parse::<Service>().unwrap(), now);
+
+        // Only the new service is probed
+        now += PROBE_INTERVAL;
+        let probe = responder.tick(now).unwrap();
+        let names: Vec<_> = probe.queries.iter().map(|q| q.query.name.clone()).collect();
+        assert_eq!(names, vec![DnsName::from("Scanner._uscan._tcp.local")]);
+
+        // The established names are answered while the new one isn't
+        let printer = query("_ipp._tcp.local", DnsRecordType::PTR);
+        assert!(respond(&mut responder, &printer, now).is_some());
+        let scanner = query("_uscan._tcp.local", DnsRecordType::PTR);
+        assert_eq!(respond(&mut responder, &scanner, now), None);
+
+        // Then only the records of the new service are announced
+        let mut packets = Vec::new();
+        while responder.state() != State::Running {
+            now += Duration::from_secs(1);
+            packets.extend(responder.tick(now));
+        }
+        let announcement = packets.last().unwrap();
+        assert_eq!(announcement.answers.len(), 4);
+        assert!(announcement
+            .answers
+            .iter()
+            .all(|r| !same_name(r.record.get_name(), &responder.host_name())));
+        // The records that were just announced aren't multicast again right away
+        assert_eq!(respond(&mut responder, &scanner, now), None);
+        now += MULTICAST_INTERVAL;
+        assert!(respond(&mut responder, &scanner, now).is_some());
+    }
+
+    #[test]
+    fn test_shared_records_are_answered_after_a_delay() {
+        let (mut responder, now) = running_responder();
+
+        // Unique records are answered right away
+        let request = query("printer.local", DnsRecordType::A);
+        let (_, destination) = responder.handle(&request, mdns_source(), now).unwrap();
+        assert_eq!(destination, Destination::Multicast);
+
+        let request = query("_ipp._tcp.local", DnsRecordType::PTR);
+        assert_eq!(responder.handle(&request, mdns_source(), now), None);
+        let at = responder.next_timeout().unwrap();
+        assert!(at >= now + MIN_SHARED_DELAY && at <= now + MAX_SHARED_DELAY);
+        assert_eq!(responder.tick(at - Duration::from_millis(1)), None);
+        let response = responder.tick(at).unwrap();
+        assert!(!response.answers[0].cache_flush);
+        assert_eq!(responder.next_timeout(), None);
+    }
+
+    #[test]
+    fn test_multicast_rate_limit() {
+        let (mut responder, now) = running_responder();
+        let request = query("printer.local", DnsRecordType::A);
+
+        assert!(responder.handle(&request, mdns_source(), now).is_some());
+        let later = now + Duration::from_millis(500);
+        assert_eq!(responder.handle(&request, mdns_source(), later), None);
+
+        // Unicast responses aren't limited
+        let mut unicast = request.clone();
+        unicast.queries[0].unicast_response = true;
+        assert!(responder.handle(&unicast, mdns_source(), later).is_some());
+
+        // Nor so much the defense against a probe
+        let mut probe = request.clone();
+        probe.queries[0].query.record_type = QueryType::ANY;
+        probe.authorities.push(MdnsRecord {
+            record: DnsRecord::A {
+                name: DnsName::from("printer.local"),
+                class: DnsClass::IN,
+                ttl: HOST_RECORD_TTL,
+                address: Ipv4Addr::new(192, 168, 1, 11),
+            },
+            cache_flush: false,
+        });
+        assert!(responder.handle(&probe, mdns_source(), later).is_some());
+
+        let later = later + MULTICAST_INTERVAL;
+        assert!(responder.handle(&request, mdns_source(), later).is_some());
+    }
+
+    #[test]
+    fn test_next_names() {
+        assert_eq!(next_host_name("printer"), "printer-2");
+        assert_eq!(next_host_name("printer-2"), "printer-3");
+        assert_eq!(next_host_name("my-printer"), "my-printer-2");
+        assert_eq!(next_instance_name("Office"), "Office (2)");
+        assert_eq!(next_instance_name("Office (2)"), "Office (3)");
+    }
+}
@@ -0,0 +1,100 @@
+use crate::responder::{Destination, Responder};
+use crate::socket;
+use parser::mdns::MdnsPacket;
+use std::io::Result;
+use std::net::{SocketAddr, UdpSocket};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
+use std::time::Instant;
+
+// https://datatracker.ietf.org/doc/html/rfc6762#section-17
+const MAX_MESSAGE_SIZE: usize = 9000;
+
+fn send(sockets: &[(UdpSocket, SocketAddr)], packet: &MdnsPacket, destination: Destination) {
+    let bytes = match packet.serialize() {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            println!("Error serializing packet: {}", e);
+            return;
+        }
+    };
+
+    for (socket, group) in sockets {
+        let target = match destination {
+            Destination::Multicast => *group,
+            Destination::Unicast(address) if address.is_ipv4() == group.is_ipv4() => address,
+            Destination::Unicast(_) => continue,
+        };
+        if let Err(e) = socket.send_to(&bytes[..], target) {
+            println!("Error sending packet to {}: {}", target, e);
+        }
+    }
+}
+
+// Runs the responder on the mDNS groups until the sockets are closed
+pub fn serve(mut responder: Responder, verbose: bool) -> Result<()> {
+    let mut sockets = vec![(socket::multicast_v4()?, socket::ipv4_group())];
+    match socket::multicast_v6() {
+        Ok(v6) => sockets.push((v6, socket::ipv6_group())),
+        Err(e) => println!("IPv6 multicast is unavailable: {}", e),
+    }
+
+    // Every socket is read on its own thread so the responder can wait on them and its timers at once
+    let (sender, receiver) = mpsc::channel();
+    for (socket, _) in &sockets {
+        let socket = socket.try_clone()?;
+        let sender = sender.clone();
+        thread::spawn(move || loop {
+            let mut buffer = [0; MAX_MESSAGE_SIZE];
+            match socket.recv_from(&mut buffer) {
+                Ok((size, src)) => {
+                    if sender.send((buffer[..size].to_vec(), src)).is_err() {
+                        return;
+                    }
+                }
+                Err(e) => println!("Error receiving packet: {}", e),
+            }
+        });
+    }
+
+    loop {
+        let now = Instant::now();
+        while let Some(packet) = responder.tick(now) {
+            send(&sockets, &packet, Destination::Multicast);
+            if verbose {
+                println!("Sent mDNS message:\n{}", &packet);
+            }
+        }
+
+        let received = match responder.next_timeout() {
+            Some(at) => receiver.recv_timeout(at.saturating_duration_since(now)),
+            None => receiver.recv().map_err(|_| RecvTimeoutError::Disconnected),
+        };
+        let (bytes, src) = match received {
+            Ok(received) => received,
+            Err(RecvTimeoutError::Timeout) => continue,
+            Err(RecvTimeoutError::Disconnected) => return Ok(()),
+        };
+
+        let packet = match MdnsPacket::parse(&bytes) {
+            Ok(packet) => packet,
+            Err(e) => {
+                if verbose {
+                    println!("Error parsing packet from {}: {:?}", src, e);
+                }
+                continue;
+            }
+        };
+
+        let host_name = responder.host_name();
+        if let Some((response, destination)) = responder.handle(&packet, src, Instant::now()) {
+            send(&sockets, &response, destination);
+            if verbose {
+                println!("Sent mDNS response to {:?}:\n{}", destination, &response);
+            }
+        }
+        if responder.host_name() != host_name {
+            println!("Name conflict, now probing for {}", responder.host_name());
+        }
+    }
+}
@@ -0,0 +1,41 @@
+use parser::mdns::{MDNS_IPV4_GROUP, MDNS_IPV6_GROUP, MDNS_PORT};
+use socket2::{Domain, Protocol, Socket, Type};
+use std::io::Result;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+
+pub fn ipv4_group() -> SocketAddr {
+    SocketAddr::from((Ipv4Addr::from(MDNS_IPV4_GROUP), MDNS_PORT))
+}
+
+pub fn ipv6_group() -> SocketAddr {
+    SocketAddr::from((Ipv6Addr::from(MDNS_IPV6_GROUP), MDNS_PORT))
+}
+
+// Other responders on the same host are bound to the mDNS port too, so it has to be shared
+fn shared_socket(domain: Domain) -> Result<Socket> {
+    let socket = Socket::new(domain, Type::DGRAM, Some(Protocol::UDP))?;
+    socket.set_reuse_address(true)?;
+    #[cfg(unix)]
+    socket.set_reuse_port(true)?;
+    Ok(socket)
+}
+
+// https://datatracker.ietf.org/doc/html/rfc6762#section-11
+pub fn multicast_v4() -> Result<UdpSocket> {
+    let socket = shared_socket(Domain::IPV4)?;
+    socket.bind(&SocketAddr::from((Ipv4Addr::UNSPECIFIED, MDNS_PORT)).into())?;
+    socket.join_multicast_v4(&Ipv4Addr::from(MDNS_IPV4_GROUP), &Ipv4Addr::UNSPECIFIED)?;
+    socket.set_multicast_ttl_v4(255)?;
+    socket.set_multicast_loop_v4(true)?;
+    Ok(socket.into())
+}
+
+pub fn multicast_v6() -> Result<UdpSocket> {
+    let socket = shared_socket(Domain::IPV6)?;
+    socket.set_only_v6(true)?;
+    socket.bind(&SocketAddr::from((Ipv6Addr::UNSPECIFIED, MDNS_PORT)).into())?;
+    socket.join_multicast_v6(&Ipv6Addr::from(MDNS_IPV6_GROUP), 0)?;
+    socket.set_multicast_hops_v6(255)?;
+    socket.set_multicast_loop_v6(true)?;
+    Ok(socket.into())
+}
@@ -0,0 +1,389 @@
+// Presentation format of the packets, mimicking the output of dig
+use crate::header::flags::{DnsHeaderFlags, Opcode, ReplyCode};
+use crate::header::DnsHeader;
+use crate::mdns::{MdnsPacket, MdnsRecord};
+use crate::packet::DnsPacket;
+use crate::resources::edns::EdnsOption;
+use crate::resources::name::DnsName;
+use crate::resources::query::DnsQuery;
+use crate::resources::record::DnsRecord;
+use crate::resources::{DnsClass, DnsRecordType, QueryClass, QueryType};
+
+use std::convert::TryFrom;
+use std::fmt::{Display, Formatter, Result};
+
+// https://datatracker.ietf.org/doc/html/rfc7873#section-4
+const COOKIE_OPTION_CODE: u16 = 0x0A;
+
+impl Display for DnsName {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        if self.labels().is_empty() {
+            return write!(f, ".");
+        }
+
+        // https://datatracker.ietf.org/doc/html/rfc1035#section-5.1
+        for label in self.labels() {
+            for &byte in &label.data {
+                match byte {
+                    b'.' | b'\\' | b'"' | b'(' | b')' | b';' | b'@' | b'$' => {
+                        write!(f, "\\{}", byte as char)?
+                    }
+                    0x21..=0x7E => write!(f, "{}", byte as char)?,
+                    _ => write!(f, "\\{:03}", byte)?,
+                }
+            }
+            write!(f, ".")?;
+        }
+        Ok(())
+    }
+}
+
+impl Display for Opcode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        let opcode = match self {
+            Opcode::Query => "QUERY",
+            Opcode::IQuery => "IQUERY",
+            Opcode::Status => "STATUS",
+            Opcode::Notify => "NOTIFY",
+            Opcode::Update => "UPDATE",
+        };
+        write!(f, "{}", opcode)
+    }
+}
+
+impl Display for ReplyCode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        let rcode = match self {
+            ReplyCode::NoError => "NOERROR",
+            ReplyCode::FormatError => "FORMERR",
+            ReplyCode::ServerFailure => "SERVFAIL",
+            ReplyCode::NameError => "NXDOMAIN",
+            ReplyCode::NotImplemented => "NOTIMP",
+            ReplyCode::Refused => "REFUSED",
+        };
+        write!(f, "{}", rcode)
+    }
+}
+
+impl Display for DnsHeaderFlags {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        let flags: Vec<&str> = [
+            (self.response, "qr"),
+            (self.authoritative, "aa"),
+            (self.truncated, "tc"),
+            (self.recdesired, "rd"),
+            (self.recavail, "ra"),
+            (self.authenticated, "ad"),
+            (self.checkdisable, "cd"),
+        ]
+        .iter()
+        .filter(|(set, _)| *set)
+        .map(|(_, flag)| *flag)
+        .collect();
+        write!(f, "{}", flags.join(" "))
+    }
+}
+
+impl Display for DnsRecordType {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        // The variants are named after their mnemonic
+        write!(f, "{:?}", self)
+    }
+}
+
+impl Display for DnsClass {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl Display for QueryType {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        match self {
+            QueryType::Record(record_type) => write!(f, "{}", record_type),
+            // https://datatracker.ietf.org/doc/html/rfc3597#section-5
+            QueryType::Unknown(n) => write!(f, "TYPE{}", n),
+            _ => write!(f, "{:?}", self),
+        }
+    }
+}
+
+impl Display for QueryClass {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        match self {
+            QueryClass::Class(class) => write!(f, "{}", class),
+            _ => write!(f, "{:?}", self),
+        }
+    }
+}
+
+impl Display for DnsQuery {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        write!(f, ";{}\t\t{}\t{}", self.name, self.class, self.record_type)
+    }
+}
+
+fn write_character_string(f: &mut Formatter<'_>, data: &[u8]) -> Result {
+    write!(f, "\"")?;
+    for &byte in data {
+        match byte {
+            b'"' | b'\\' => write!(f, "\\{}", byte as char)?,
+            0x20..=0x7E => write!(f, "{}", byte as char)?,
+            _ => write!(f, "\\{:03}", byte)?,
+        }
+    }
+    write!(f, "\"")
+}
+
+impl Display for DnsRecord {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        match self {
+            DnsRecord::A {
+                name,
+                class,
+                ttl,
+                address,
+            } => write!(f, "{}\t{}\t{}\tA\t{}", name, ttl, class, address),
+            DnsRecord::NS {
+                name,
+                class,
+                ttl,
+                name_server,
+            } => write!(f, "{}\t{}\t{}\tNS\t{}", name, ttl, class, name_server),
+            DnsRecord::CNAME {
+                name,
+                class,
+                ttl,
+                canonical_name,
+            } => write!(f, "{}\t{}\t{}\tCNAME\t{}", name, ttl, class, canonical_name),
+            DnsRecord::PTR {
+                name,
+                class,
+                ttl,
+                pointer,
+            } => write!(f, "{}\t{}\t{}\tPTR\t{}", name, ttl, class, pointer),
+            DnsRecord::DNAME {
+                name,
+                class,
+                ttl,
+                target,
+            } => write!(f, "{}\t{}\t{}\tDNAME\t{}", name, ttl, class, target),
+            DnsRecord::SOA {
+                name,
+                class,
+                ttl,
+                mname,
+                rname,
+                serial,
+                refresh,
+                retry,
+                expire,
+                minimum,
+            } => write!(
+                f,
+                "{}\t{}\t{}\tSOA\t{} {} {} {} {} {} {}",
+                name, ttl, class, mname, rname, serial, refresh, retry, expire, minimum
+            ),
+            DnsRecord::HINFO {
+                name,
+                class,
+                ttl,
+                cpu,
+                os,
+            } => {
+                write!(f, "{}\t{}\t{}\tHINFO\t", name, ttl, class)?;
+                write_character_string(f, cpu)?;
+                write!(f, " ")?;
+                write_character_string(f, os)
+            }
+            DnsRecord::TXT {
+                name,
+                class,
+                ttl,
+                data,
+            } => {
+                write!(f, "{}\t{}\t{}\tTXT\t", name, ttl, class)?;
+                for (index, string) in data.iter().enumerate() {
+                    if index > 0 {
+                        write!(f, " ")?;
+                    }
+                    write_character_string(f, string)?;
+                }
+                Ok(())
+            }
+            DnsRecord::AAAA {
+                name,
+                class,
+                ttl,
+                address,
+            } => write!(f, "{}\t{}\t{}\tAAAA\t{}", name, ttl, class, address),
+            DnsRecord::SRV {
+                name,
+                class,
+                ttl,
+                priority,
+                weight,
+                port,
+                target,
+            } => write!(
+                f,
+                "{}\t{}\t{}\tSRV\t{} {} {} {}",
+                name, ttl, class, priority, weight, port, target
+            ),
+            // https://datatracker.ietf.org/doc/html/rfc3597#section-5
+            DnsRecord::Unknown {
+                name,
+                record_type,
+                class,
+                ttl,
+                data,
+            } => {
+                write!(f, "{}\t{}\t{}\t", name, ttl, class)?;
+                match DnsRecordType::try_from(*record_type) {
+                    Ok(known) => write!(f, "{}", known)?,
+                    Err(_) => write!(f, "TYPE{}", record_type)?,
+                }
+                write!(f, "\t\\# {}", data.len())?;
+                if !data.is_empty() {
+                    write!(f, " ")?;
+                }
+                for byte in data {
+                    write!(f, "{:02x}", byte)?;
+                }
+                Ok(())
+            }
+            DnsRecord::OPT {
+                payload_size,
+                version,
+                dnssec_ok,
+                options,
+                ..
+            } => {
+                write!(
+                    f,
+                    "; EDNS: version: {}, flags:{}; udp: {}",
+                    version,
+                    if *dnssec_ok { " do" } else { "" },
+                    payload_size
+                )?;
+                for option in options {
+                    write!(f, "\n{}", option)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Display for EdnsOption {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        match self.code {
+            COOKIE_OPTION_CODE => write!(f, "; COOKIE: ")?,
+            code => write!(f, "; OPT={}: ", code)?,
+        }
+        for byte in &self.data {
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+fn write_section(f: &mut Formatter<'_>, title: &str, records: &[&DnsRecord]) -> Result {
+    if records.is_empty() {
+        return Ok(());
+    }
+
+    writeln!(f, "\n;; {} SECTION:", title)?;
+    for record in records {
+        writeln!(f, "{}", record)?;
+    }
+    Ok(())
+}
+
+// The header along with the number of entries in each section
+fn write_header(f: &mut Formatter<'_>, header: &DnsHeader, lens: [usize; 4]) -> Result {
+    let flags = &header.flags;
+    writeln!(
+        f,
+        ";; ->>HEADER<<- opcode: {}, status: {}, id: {}",
+        flags.opcode, flags.rcode, header.transaction_id
+    )?;
+    writeln!(
+        f,
+        ";; flags: {}; QUERY: {}, ANSWER: {}, AUTHORITY: {}, ADDITIONAL: {}",
+        flags, lens[0], lens[1], lens[2], lens[3]
+    )
+}
+
+impl Display for DnsPacket {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        write_header(
+            f,
+            &self.header,
+            [
+                self.queries().len(),
+                self.answers().len(),
+                self.authorities().len(),
+                self.additional_records().len(),
+            ],
+        )?;
+
+        if let Some(opt) = self.edns() {
+            writeln!(f, "\n;; OPT PSEUDOSECTION:\n{}", opt)?;
+        }
+
+        if !self.queries().is_empty() {
+            writeln!(f, "\n;; QUESTION SECTION:")?;
+            for query in self.queries() {
+                writeln!(f, "{}", query)?;
+            }
+        }
+
+        write_section(f, "ANSWER", &self.answers().iter().collect::<Vec<_>>())?;
+        write_section(
+            f,
+            "AUTHORITY",
+            &self.authorities().iter().collect::<Vec<_>>(),
+        )?;
+        write_section(
+            f,
+            "ADDITIONAL",
+            &self
+                .additional_records()
+                .iter()
+                .filter(|r| r.get_type() != Some(DnsRecordType::OPT))
+                .collect::<Vec<_>>(),
+        )
+    }
+}
+
+// The unicast-response and cache-flush bits aren't shown
+impl Display for MdnsPacket {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        write_header(
+            f,
+            &self.header,
+            [
+                self.queries.len(),
+                self.answers.len(),
+                self.authorities.len(),
+                self.additional_records.len(),
+            ],
+        )?;
+
+        if !self.queries.is_empty() {
+            writeln!(f, "\n;; QUESTION SECTION:")?;
+            for query in &self.queries {
+                writeln!(f, "{}", query.query)?;
+            }
+        }
+
+        write_section(f, "ANSWER", &mdns_records(&self.answers))?;
+        write_section(f, "AUTHORITY", &mdns_records(&self.authorities))?;
+        write_section(f, "ADDITIONAL", &mdns_records(&self.additional_records))
+    }
+}
+
+fn mdns_records(section: &[MdnsRecord]) -> Vec<&DnsRecord> {
+    section.iter().map(|r| &r.record).collect()
+}
@@ -0,0 +1,732 @@
+// Field by field breakdown of a raw message, in the spirit of Wireshark's packet
+// details. Unlike DnsPacket::parse, it keeps going as far as it can on malformed data.
+use crate::header::flags::{Opcode, ReplyCode};
+use crate::resources::{DnsRecordType, QueryClass, QueryType};
+
+use std::convert::TryFrom;
+use std::fmt::{Display, Formatter};
+
+const HEADER_SIZE: usize = 12;
+const COMPRESSION_MASK: u8 = 0xC0;
+// Upper bound on the compression pointers followed for a single name
+const MAX_POINTER_JUMPS: usize = 128;
+// Raw bytes shown on a line before eliding the rest
+const MAX_HEX_BYTES: usize = 8;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Field {
+    pub offset: usize,
+    pub data: Vec<u8>,
+    pub name: String,
+    pub meaning: String,
+    pub malformed: bool,
+    pub children: Vec<Field>,
+}
+
+impl Field {
+    fn new(bytes: &[u8], offset: usize, length: usize, name: &str, meaning: String) -> Self {
+        Self {
+            offset,
+            data: bytes[offset..offset + length].to_vec(),
+            name: name.to_owned(),
+            meaning,
+            malformed: false,
+            children: Vec::new(),
+        }
+    }
+
+    fn malformed(bytes: &[u8], offset: usize, meaning: String) -> Self {
+        let offset = offset.min(bytes.len());
+        Self {
+            malformed: true,
+            ..Self::new(bytes, offset, bytes.len() - offset, "Malformed", meaning)
+        }
+    }
+
+    fn with_children(mut self, children: Vec<Field>) -> Self {
+        self.children = children;
+        self
+    }
+
+    pub fn is_malformed(&self) -> bool {
+        self.malformed || self.children.iter().any(Field::is_malformed)
+    }
+
+    fn write_tree(&self, f: &mut Formatter<'_>, depth: usize) -> std::fmt::Result {
+        let mut hex: Vec<String> = self
+            .data
+            .iter()
+            .take(MAX_HEX_BYTES)
+            .map(|b| format!("{:02x}", b))
+            .collect();
+        if self.data.len() > MAX_HEX_BYTES {
+            hex.push("..".to_owned());
+        }
+
+        writeln!(
+            f,
+            "{:04x} {:>4}  {:<27} {}{}: {}",
+            self.offset,
+            self.data.len(),
+            hex.join(" "),
+            "  ".repeat(depth),
+            self.name,
+            self.meaning
+        )?;
+        for child in &self.children {
+            child.write_tree(f, depth + 1)?;
+        }
+        Ok(())
+    }
+}
+
+impl Display for Field {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        self.write_tree(f, 0)
+    }
+}
+
+pub fn dissect(bytes: &[u8]) -> Vec<Field> {
+    let mut fields = Vec::new();
+
+    if bytes.len() < HEADER_SIZE {
+        fields.push(Field::malformed(
+            bytes,
+            0,
+            format!(
+                "Header needs {} bytes, only {} available",
+                HEADER_SIZE,
+                bytes.len()
+            ),
+        ));
+        return fields;
+    }
+
+    let (header, counts) = dissect_header(bytes);
+    fields.push(header);
+
+    let mut offset = HEADER_SIZE;
+    let sections = [
+        ("Queries", "Query", counts[0]),
+        ("Answers", "Answer", counts[1]),
+        ("Authoritative nameservers", "Authority", counts[2]),
+        ("Additional records", "Additional", counts[3]),
+    ];
+
+    for (index, (section, entry, count)) in sections.iter().enumerate() {
+        if *count == 0 {
+            continue;
+        }
+
+        let start = offset;
+        let mut entries = Vec::new();
+        for _ in 0..*count {
+            if offset >= bytes.len() {
+                entries.push(Field::malformed(
+                    bytes,
+                    offset,
+                    format!("{} {} declared but the message ended", count, section),
+                ));
+                break;
+            }
+
+            let dissected = if index == 0 {
+                dissect_query(bytes, offset, entry)
+            } else {
+                dissect_record(bytes, offset, entry)
+            };
+
+            match dissected {
+                (field, Some(next)) => {
+                    entries.push(field);
+                    offset = next;
+                }
+                (field, None) => {
+                    entries.push(field);
+                    offset = bytes.len();
+                    break;
+                }
+            }
+        }
+
+        fields.push(
+            Field::new(
+                bytes,
+                start,
+                offset - start,
+                section,
+                format!("{} entries", entries.len()),
+            )
+            .with_children(entries),
+        );
+    }
+
+    if offset < bytes.len() {
+        fields.push(Field::malformed(
+            bytes,
+            offset,
+            format!("{} trailing bytes", bytes.len() - offset),
+        ));
+    }
+
+    fields
+}
+
+fn be_u16_at(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_be_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+fn be_u32_at(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes([
+        bytes[offset],
+        bytes[offset + 1],
+        bytes[offset + 2],
+        bytes[offset + 3],
+    ])
+}
+
+// Renders a bit field the way Wireshark does, e.g. ".000 0... .... ...."
+fn bit_pattern(value: u16, mask: u16) -> String {
+    (0..16)
+        .rev()
+        .map(|bit| {
+            let separator = if bit % 4 == 0 && bit != 0 { " " } else { "" };
+            let digit = if mask & (1 << bit) == 0 {
+                '.'
+            } else if value & (1 << bit) == 0 {
+                '0'
+            } else {
+                '1'
+            };
+            format!("{}{}", digit, separator)
+        })
+        .collect()
+}
+
+fn dissect_header(bytes: &[u8]) -> (Field, [u16; 4]) {
+    let flags = be_u16_at(bytes, 2);
+    let bit = |mask: u16| flags & mask != 0;
+
+    let opcode = (flags >> 11) & 0x0F;
+    let opcode = match Opcode::try_from(opcode as usize) {
+        Ok(known) => format!("{} ({})", known, opcode),
+        Err(_) => format!("Unknown ({})", opcode),
+    };
+    let rcode = flags & 0x0F;
+    let rcode = match ReplyCode::try_from(rcode as usize) {
+        Ok(known) => format!("{} ({})", known, rcode),
+        Err(_) => format!("Unknown ({})", rcode),
+    };
+
+    let flag_fields = [
+        (
+            0x8000,
+            "Response",
+            if bit(0x8000) {
+                "Message is a response"
+            } else {
+                "Message is a query"
+            }
+            .to_owned(),
+        ),
+        (0x7800, "Opcode", opcode),
+        (0x0400, "Authoritative", bit(0x0400).to_string()),
+        (0x0200, "Truncated", bit(0x0200).to_string()),
+        (0x0100, "Recursion desired", bit(0x0100).to_string()),
+        (0x0080, "Recursion available", bit(0x0080).to_string()),
+        (0x0040, "Z", bit(0x0040).to_string()),
+        (0x0020, "Authenticated", bit(0x0020).to_string()),
+        (0x0010, "Check disable", bit(0x0010).to_string()),
+        (0x000F, "Reply code", rcode),
+    ]
+    .iter()
+    .map(|(mask, name, meaning)| {
+        Field::new(
+            bytes,
+            2,
+            2,
+            &format!("{} = {}", bit_pattern(flags, *mask), name),
+            meaning.clone(),
+        )
+    })
+    .collect();
+
+    let counts = [
+        be_u16_at(bytes, 4),
+        be_u16_at(bytes, 6),
+        be_u16_at(bytes, 8),
+        be_u16_at(bytes, 10),
+    ];
+
+    let header = Field::new(bytes, 0, HEADER_SIZE, "Header", String::new()).with_children(vec![
+        Field::new(
+            bytes,
+            0,
+            2,
+            "Transaction ID",
+            format!("0x{:04x}", be_u16_at(bytes, 0)),
+        ),
+        Field::new(bytes, 2, 2, "Flags", format!("0x{:04x}", flags)).with_children(flag_fields),
+        Field::new(bytes, 4, 2, "Queries", counts[0].to_string()),
+        Field::new(bytes, 6, 2, "Answer RRs", counts[1].to_string()),
+        Field::new(bytes, 8, 2, "Authority RRs", counts[2].to_string()),
+        Field::new(bytes, 10, 2, "Additional RRs", counts[3].to_string()),
+    ]);
+
+    (header, counts)
+}
+
+// Returns the name field and the offset right after the name, if it could be found
+fn dissect_name(bytes: &[u8], start: usize, field_name: &str) -> (Field, Option<usize>) {
+    let mut labels = Vec::new();
+    let mut children = Vec::new();
+    let mut offset = start;
+    // Offset following the name at its original position, known after the first jump
+    let mut end = None;
+    let mut jumps = 0;
+
+    let error = loop {
+        if offset >= bytes.len() {
+            break Some(format!(
+                "Name runs past the end of the message at {}",
+                offset
+            ));
+        }
+
+        let size = bytes[offset];
+        if size == 0x00 {
+            children.push(Field::new(
+                bytes,
+                offset,
+                1,
+                "Root",
+                "end of name".to_owned(),
+            ));
+            end = end.or(Some(offset + 1));
+            break None;
+        }
+
+        match size & COMPRESSION_MASK {
+            COMPRESSION_MASK => {
+                if offset + 1 >= bytes.len() {
+                    break Some("Compression pointer is cut short".to_owned());
+                }
+                let pointer = (be_u16_at(bytes, offset) & 0x3FFF) as usize;
+                children.push(Field::new(
+                    bytes,
+                    offset,
+                    2,
+                    "Compression pointer",
+                    format!("offset {}", pointer),
+                ));
+                end = end.or(Some(offset + 2));
+
+                jumps += 1;
+                if jumps > MAX_POINTER_JUMPS {
+                    break Some("Compression pointers form a loop".to_owned());
+                }
+                if pointer >= bytes.len() {
+                    break Some(format!(
+                        "Compression pointer to {} is out of bounds",
+                        pointer
+                    ));
+                }
+                offset = pointer;
+            }
+            0x00 => {
+                let size = size as usize;
+                if offset + 1 + size > bytes.len() {
+                    break Some(format!(
+                        "Label of {} bytes runs past the end of the message",
+                        size
+                    ));
+                }
+                let label = String::from_utf8_lossy(&bytes[offset + 1..offset + 1 + size]);
+                children.push(Field::new(
+                    bytes,
+                    offset,
+                    size + 1,
+                    "Label",
+                    label.to_string(),
+                ));
+                labels.push(label.to_string());
+                offset += size + 1;
+            }
+            _ => break Some(format!("Unsupported label type 0x{:02x}", size)),
+        }
+    };
+
+    let length = end.unwrap_or(offset.min(bytes.len())) - start;
+    let name = if labels.is_empty() {
+        ".".to_owned()
+    } else {
+        format!("{}.", labels.join("."))
+    };
+
+    match error {
+        Some(message) => {
+            children.push(Field::malformed(bytes, offset, message));
+            (
+                Field::new(bytes, start, length, field_name, name).with_children(children),
+                None,
+            )
+        }
+        None => (
+            Field::new(bytes, start, length, field_name, name).with_children(children),
+            end,
+        ),
+    }
+}
+
+fn record_type_meaning(value: u16) -> String {
+    match DnsRecordType::try_from(value) {
+        Ok(record_type) => format!("{} ({})", record_type, value),
+        Err(_) => format!("Unknown ({})", value),
+    }
+}
+
+fn dissect_query(bytes: &[u8], offset: usize, entry: &str) -> (Field, Option<usize>) {
+    let (name, next) = dissect_name(bytes, offset, "Name");
+    let next = match next {
+        Some(next) if next + 4 <= bytes.len() => next,
+        Some(next) => {
+            let children = vec![
+                name,
+                Field::malformed(bytes, next, "Type and class are cut short".to_owned()),
+            ];
+            return (
+                Field::new(bytes, offset, bytes.len() - offset, entry, String::new())
+                    .with_children(children),
+                None,
+            );
+        }
+        None => {
+            return (
+                Field::new(bytes, offset, name.data.len(), entry, name.meaning.clone())
+                    .with_children(vec![name]),
+                None,
+            )
+        }
+    };
+
+    let query_type = be_u16_at(bytes, next);
+    let query_type = match QueryType::try_from(query_type) {
+        Ok(known) => format!("{} ({})", known, query_type),
+        Err(_) => format!("Unknown ({})", query_type),
+    };
+    let class = be_u16_at(bytes, next + 2);
+    let class = match QueryClass::try_from(class) {
+        Ok(known) => format!("{} ({})", known, class),
+        Err(_) => format!("Unknown ({})", class),
+    };
+
+    let summary = format!("{} {} {}", name.meaning, class, query_type);
+    let children = vec![
+        name,
+        Field::new(bytes, next, 2, "Type", query_type),
+        Field::new(bytes, next + 2, 2, "Class", class),
+    ];
+
+    (
+        Field::new(bytes, offset, next + 4 - offset, entry, summary).with_children(children),
+        Some(next + 4),
+    )
+}
+
+fn dissect_record(bytes: &[u8], offset: usize, entry: &str) -> (Field, Option<usize>) {
+    let (name, next) = dissect_name(bytes, offset, "Name");
+    let next = match next {
+        Some(next) if next + 10 <= bytes.len() => next,
+        Some(next) => {
+            let children = vec![
+                name,
+                Field::malformed(bytes, next, "Record fields are cut short".to_owned()),
+            ];
+            return (
+                Field::new(bytes, offset, bytes.len() - offset, entry, String::new())
+                    .with_children(children),
+                None,
+            );
+        }
+        None => {
+            return (
+                Field::new(bytes, offset, name.data.len(), entry, name.meaning.clone())
+                    .with_children(vec![name]),
+                None,
+            )
+        }
+    };
+
+    let record_type = be_u16_at(bytes, next);
+    let rdlength = be_u16_at(bytes, next + 8) as usize;
+    let rdata_offset = next + 10;
+    let summary = format!("{} {}", name.meaning, record_type_meaning(record_type));
+
+    let mut children = vec![
+        name,
+        Field::new(bytes, next, 2, "Type", record_type_meaning(record_type)),
+    ];
+
+    if record_type == DnsRecordType::OPT as u16 {
+        let ttl = be_u32_at(bytes, next + 4);
+        children.extend(vec![
+            Field::new(
+                bytes,
+                next + 2,
+                2,
+                "UDP payload size",
+                be_u16_at(bytes, next + 2).to_string(),
+            ),
+            Field::new(
+                bytes,
+                next + 4,
+                1,
+                "Extended RCODE",
+                (ttl >> 24).to_string(),
+            ),
+            Field::new(
+                bytes,
+                next + 5,
+                1,
+                "EDNS version",
+                ((ttl >> 16) & 0xFF).to_string(),
+            ),
+            Field::new(
+                bytes,
+                next + 6,
+                2,
+                "Flags",
+                format!("0x{:04x} (DNSSEC OK: {})", ttl & 0xFFFF, ttl & 0x8000 != 0),
+            ),
+        ]);
+    } else {
+        let class = be_u16_at(bytes, next + 2);
+        let class = match QueryClass::try_from(class) {
+            Ok(known) => format!("{} ({})", known, class),
+            Err(_) => format!("Unknown ({})", class),
+        };
+        children.extend(vec![
+            Field::new(bytes, next + 2, 2, "Class", class),
+            Field::new(
+                bytes,
+                next + 4,
+                4,
+                "Time to live",
+                be_u32_at(bytes, next + 4).to_string(),
+            ),
+        ]);
+    }
+    children.push(Field::new(
+        bytes,
+        next + 8,
+        2,
+        "Data length",
+        rdlength.to_string(),
+    ));
+
+    if rdata_offset + rdlength > bytes.len() {
+        children.push(Field::malformed(
+            bytes,
+            rdata_offset,
+            format!(
+                "Data length of {} runs past the end of the message",
+                rdlength
+            ),
+        ));
+        return (
+            Field::new(bytes, offset, bytes.len() - offset, entry, summary).with_children(children),
+            None,
+        );
+    }
+
+    children.extend(dissect_rdata(bytes, rdata_offset, rdlength, record_type));
+
+    (
+        Field::new(
+            bytes,
+            offset,
+            rdata_offset + rdlength - offset,
+            entry,
+            summary,
+        )
+        .with_children(children),
+        Some(rdata_offset + rdlength),
+    )
+}
+
+fn dissect_rdata(bytes: &[u8], offset: usize, length: usize, record_type: u16) -> Vec<Field> {
+    let end = offset + length;
+    let raw = |name: &str| vec![Field::new(bytes, offset, length, name, String::new())];
+
+    let mut fields = match DnsRecordType::try_from(record_type) {
+        Ok(DnsRecordType::A) if length == 4 => {
+            let octets = &bytes[offset..end];
+            vec![Field::new(
+                bytes,
+                offset,
+                length,
+                "Address",
+                format!("{}.{}.{}.{}", octets[0], octets[1], octets[2], octets[3]),
+            )]
+        }
+        Ok(DnsRecordType::AAAA) if length == 16 => {
+            let mut octets = [0; 16];
+            octets.copy_from_slice(&bytes[offset..end]);
+            vec![Field::new(
+                bytes,
+                offset,
+                length,
+                "Address",
+                std::net::Ipv6Addr::from(octets).to_string(),
+            )]
+        }
+        Ok(DnsRecordType::A) | Ok(DnsRecordType::AAAA) => vec![Field {
+            malformed: true,
+            ..Field::new(
+                bytes,
+                offset,
+                length,
+                "Address",
+                format!("Invalid address length of {} bytes", length),
+            )
+        }],
+        Ok(DnsRecordType::NS)
+        | Ok(DnsRecordType::CNAME)
+        | Ok(DnsRecordType::PTR)
+        | Ok(DnsRecordType::DNAME) => {
+            // Names in the data may point back anywhere in the message
+            let (name, _) = dissect_name(&bytes[..end], offset, "Name");
+            vec![name]
+        }
+        Ok(DnsRecordType::SOA) => {
+            let (mname, next) = dissect_name(&bytes[..end], offset, "Primary name server");
+            let mut fields = vec![mname];
+            if let Some(next) = next {
+                let (rname, next) = dissect_name(&bytes[..end], next, "Responsible mailbox");
+                fields.push(rname);
+                if let Some(mut position) = next.filter(|next| next + 20 == end) {
+                    for name in &["Serial", "Refresh", "Retry", "Expire", "Minimum"] {
+                        fields.push(Field::new(
+                            bytes,
+                            position,
+                            4,
+                            name,
+                            be_u32_at(bytes, position).to_string(),
+                        ));
+                        position += 4;
+                    }
+                }
+            }
+            fields
+        }
+        Ok(DnsRecordType::HINFO) => {
+            let mut fields = Vec::new();
+            let mut position = offset;
+            for name in &["CPU", "OS"] {
+                if position >= end || position + 1 + bytes[position] as usize > end {
+                    break;
+                }
+                let size = bytes[position] as usize;
+                fields.push(Field::new(
+                    bytes,
+                    position,
+                    size + 1,
+                    name,
+                    format!(
+                        "\"{}\"",
+                        String::from_utf8_lossy(&bytes[position + 1..position + 1 + size])
+                    ),
+                ));
+                position += size + 1;
+            }
+            fields
+        }
+        Ok(DnsRecordType::TXT) => {
+            let mut fields = Vec::new();
+            let mut position = offset;
+            while position < end && position + 1 + bytes[position] as usize <= end {
+                let size = bytes[position] as usize;
+                fields.push(Field::new(
+                    bytes,
+                    position,
+                    size + 1,
+                    "Text",
+                    format!(
+                        "\"{}\"",
+                        String::from_utf8_lossy(&bytes[position + 1..position + 1 + size])
+                    ),
+                ));
+                position += size + 1;
+            }
+            fields
+        }
+        Ok(DnsRecordType::SRV) if length > 6 => {
+            let (target, _) = dissect_name(&bytes[..end], offset + 6, "Target");
+            vec![
+                Field::new(
+                    bytes,
+                    offset,
+                    2,
+                    "Priority",
+                    be_u16_at(bytes, offset).to_string(),
+                ),
+                Field::new(
+                    bytes,
+                    offset + 2,
+                    2,
+                    "Weight",
+                    be_u16_at(bytes, offset + 2).to_string(),
+                ),
+                Field::new(
+                    bytes,
+                    offset + 4,
+                    2,
+                    "Port",
+                    be_u16_at(bytes, offset + 4).to_string(),
+                ),
+                target,
+            ]
+        }
+        Ok(DnsRecordType::OPT) => {
+            let mut fields = Vec::new();
+            let mut position = offset;
+            while position + 4 <= end {
+                let code = be_u16_at(bytes, position);
+                let size = be_u16_at(bytes, position + 2) as usize;
+                if position + 4 + size > end {
+                    break;
+                }
+                fields.push(
+                    Field::new(
+                        bytes,
+                        position,
+                        4 + size,
+                        "Option",
+                        format!("code {}", code),
+                    )
+                    .with_children(vec![
+                        Field::new(bytes, position, 2, "Code", code.to_string()),
+                        Field::new(bytes, position + 2, 2, "Length", size.to_string()),
+                        Field::new(bytes, position + 4, size, "Data", String::new()),
+                    ]),
+                );
+                position += 4 + size;
+            }
+            fields
+        }
+        _ => raw("Data"),
+    };
+
+    let decoded: usize = fields.iter().map(|f| f.data.len()).sum();
+    if decoded != length {
+        fields.push(Field::malformed(
+            &bytes[..end],
+            offset + decoded.min(length),
+            format!(
+                "Data does not match its declared length of {} bytes",
+                length
+            ),
+        ));
+    }
+    fields
+}
@@ -107,22 +107,24 @@ impl DnsHeaderFlags {
     }
 
     pub fn serialize<'a, W: Write + 'a>(&'a self) -> impl SerializeFn<W> + 'a {
-        use cf::combinator::slice;
+        use cf::{bytes::be_u8, sequence::tuple};
 
-        move |out| {
-            slice(vec![
+        tuple((
+            be_u8(
                 (self.recdesired as u8)
                     | ((self.truncated as u8) << 1)
                     | ((self.authoritative as u8) << 2)
                     | ((self.opcode as u8) << 3)
-                    | ((self.response as u8) << 7) as u8,
+                    | ((self.response as u8) << 7),
+            ),
+            be_u8(
                 (self.rcode as u8)
                     | ((self.checkdisable as u8) << 4)
                     | ((self.authenticated as u8) << 5)
                     | ((self.z as u8) << 6)
                     | ((self.recavail as u8) << 7),
-            ])(out)
-        }
+            ),
+        ))
     }
 
     pub fn to_bytes(&self) -> Result<Vec<u8>, GenError> {
@@ -4,31 +4,60 @@ mod utils;
 use self::flags::DnsHeaderFlags;
 use crate::types::{ParseInput, ParseResult};
 use cookie_factory::{self as cf, gen_simple, GenError, SerializeFn};
-use std::io::Write;
+use std::convert::TryFrom;
+use std::io::{self, Write};
+
+// https://datatracker.ietf.org/doc/html/rfc1035#section-4.1.1
+pub const HEADER_SIZE: usize = 12;
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct DnsHeader {
     pub transaction_id: u16,
     pub flags: DnsHeaderFlags,
+}
+
+// Number of entries in each section, as declared on the wire. Those are never stored
+// in the header since they are derived from the packet's sections when serializing.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DnsSectionCounts {
     pub queries: u16,
-    pub responses: u16,
+    pub answers: u16,
     pub auth_rr: u16,
     pub add_rr: u16,
 }
 
+impl DnsSectionCounts {
+    // Counts of sections holding `lens` entries, failing when one of them can't be declared
+    // on the two bytes of its count
+    pub fn from_lens(lens: [usize; 4]) -> Result<Self, GenError> {
+        let count = |len: usize| {
+            u16::try_from(len).map_err(|_| {
+                GenError::IoError(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("{} entries don't fit in a section", len),
+                ))
+            })
+        };
+        Ok(Self {
+            queries: count(lens[0])?,
+            answers: count(lens[1])?,
+            auth_rr: count(lens[2])?,
+            add_rr: count(lens[3])?,
+        })
+    }
+}
+
 impl DnsHeader {
+    // The ID of a query is random, so that off-path attackers can't guess it
+    // https://datatracker.ietf.org/doc/html/rfc5452#section-9.2
     pub fn new() -> Self {
         Self {
-            transaction_id: 1337,
+            transaction_id: rand::random(),
             flags: DnsHeaderFlags::default(),
-            queries: 0,
-            responses: 0,
-            auth_rr: 0,
-            add_rr: 0,
         }
     }
 
-    pub fn parse(i: ParseInput) -> ParseResult<Self> {
+    pub fn parse(i: ParseInput) -> ParseResult<(Self, DnsSectionCounts)> {
         use nom::{
             bits::bits, combinator::map, error::context, number::complete::be_u16, sequence::tuple,
         };
@@ -38,36 +67,45 @@ impl DnsHeader {
                 context("Transaction ID", be_u16),
                 context("Flags", bits(DnsHeaderFlags::parse)),
                 context("Queries", be_u16),
-                context("Responses", be_u16),
+                context("Answers", be_u16),
                 context("Authority RRs", be_u16),
                 context("Additional RRs", be_u16),
             )),
-            |(transaction_id, flags, queries, responses, auth_rr, add_rr)| Self {
-                transaction_id,
-                flags,
-                queries,
-                responses,
-                auth_rr,
-                add_rr,
+            |(transaction_id, flags, queries, answers, auth_rr, add_rr)| {
+                (
+                    Self {
+                        transaction_id,
+                        flags,
+                    },
+                    DnsSectionCounts {
+                        queries,
+                        answers,
+                        auth_rr,
+                        add_rr,
+                    },
+                )
             },
         )(i)
     }
 
-    pub fn serialize<'a, W: Write + 'a>(&'a self) -> impl SerializeFn<W> + 'a {
+    pub fn serialize<'a, W: Write + 'a>(
+        &'a self,
+        counts: DnsSectionCounts,
+    ) -> impl SerializeFn<W> + 'a {
         use cf::{bytes::be_u16, sequence::tuple};
 
         tuple((
             be_u16(self.transaction_id),
             self.flags.serialize(),
-            be_u16(self.queries),
-            be_u16(self.responses),
-            be_u16(self.auth_rr),
-            be_u16(self.add_rr),
+            be_u16(counts.queries),
+            be_u16(counts.answers),
+            be_u16(counts.auth_rr),
+            be_u16(counts.add_rr),
         ))
     }
 
-    pub fn to_bytes(&self) -> Result<Vec<u8>, GenError> {
-        gen_simple(self.serialize(), Vec::new())
+    pub fn to_bytes(&self, counts: DnsSectionCounts) -> Result<Vec<u8>, GenError> {
+        gen_simple(self.serialize(counts), Vec::new())
     }
 
     pub fn set_flags(&mut self, flags: DnsHeaderFlags) {
@@ -93,20 +131,25 @@ mod tests {
                 recdesired: true,
                 ..Default::default()
             },
+        };
+        let counts = DnsSectionCounts {
             queries: 0x5678,
-            responses: 0x9abc,
+            answers: 0x9abc,
             auth_rr: 0xdef0,
             add_rr: 0xabcd,
         };
 
         #[rustfmt::skip]
-        assert_eq!(header.to_bytes().unwrap(), vec![
+        let bytes = vec![
             0x12, 0x34, // Transaction ID
             0x01, 0x00, // Flags
             0x56, 0x78, // Queries
-            0x9A, 0xBC, // Responses
+            0x9A, 0xBC, // Answers
             0xDE, 0xF0, // Auth RRs
             0xAB, 0xCD, // Additional RRs
-        ]);
+        ];
+
+        assert_eq!(header.to_bytes(counts).unwrap(), bytes);
+        assert_eq!(DnsHeader::parse(&bytes).unwrap().1, (header, counts));
     }
 }
@@ -1,4 +1,9 @@
+pub mod display;
+pub mod dissect;
 pub mod header;
+pub mod mdns;
 pub mod packet;
 pub mod resources;
 pub mod types;
+
+pub use dissect::dissect;
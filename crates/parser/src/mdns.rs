@@ -0,0 +1,153 @@
+use super::header::{DnsHeader, DnsSectionCounts};
+use super::resources::query::DnsQuery;
+use super::resources::record::DnsRecord;
+use crate::types::{ParseError, ParseInput, ParseResult};
+
+use cookie_factory::{self as cf, gen_simple, GenError};
+use nom::combinator::map;
+use nom::error::context;
+use nom::multi::count;
+
+// https://datatracker.ietf.org/doc/html/rfc6762#section-3
+pub const MDNS_PORT: u16 = 5353;
+pub const MDNS_IPV4_GROUP: [u8; 4] = [224, 0, 0, 251];
+pub const MDNS_IPV6_GROUP: [u16; 8] = [0xff02, 0, 0, 0, 0, 0, 0, 0xfb];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MdnsQuery {
+    pub query: DnsQuery,
+    pub unicast_response: bool,
+}
+
+impl MdnsQuery {
+    pub fn parse<'a>(
+        reference_bytes: ParseInput<'a>,
+    ) -> impl FnMut(ParseInput<'a>) -> ParseResult<'a, Self> {
+        map(
+            DnsQuery::parse_mdns(reference_bytes),
+            |(query, unicast_response)| Self {
+                query,
+                unicast_response,
+            },
+        )
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>, GenError> {
+        self.query.to_bytes_mdns(self.unicast_response)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MdnsRecord {
+    pub record: DnsRecord,
+    pub cache_flush: bool,
+}
+
+impl MdnsRecord {
+    pub fn parse<'a>(
+        reference_bytes: ParseInput<'a>,
+    ) -> impl FnMut(ParseInput<'a>) -> ParseResult<'a, Self> {
+        map(
+            DnsRecord::parse_mdns(reference_bytes),
+            |(record, cache_flush)| Self {
+                record,
+                cache_flush,
+            },
+        )
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>, GenError> {
+        self.record.to_bytes_mdns(self.cache_flush)
+    }
+}
+
+// A DNS message where the top bit of every class carries its mDNS meaning
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MdnsPacket {
+    pub header: DnsHeader,
+    pub queries: Vec<MdnsQuery>,
+    pub answers: Vec<MdnsRecord>,
+    pub authorities: Vec<MdnsRecord>,
+    pub additional_records: Vec<MdnsRecord>,
+}
+
+impl MdnsPacket {
+    pub fn new() -> Self {
+        // mDNS messages are sent with a zero ID, except for legacy unicast replies
+        let mut header = DnsHeader::new();
+        header.transaction_id = 0;
+
+        Self {
+            header,
+            queries: Vec::new(),
+            answers: Vec::new(),
+            authorities: Vec::new(),
+            additional_records: Vec::new(),
+        }
+    }
+
+    pub fn parse(i: ParseInput) -> Result<Self, ParseError<Vec<u8>>> {
+        let (b, (header, counts)) = context("Header", DnsHeader::parse)(i)?;
+        let (b, queries) = context(
+            "Queries",
+            count(MdnsQuery::parse(i), counts.queries as usize),
+        )(b)?;
+        let (b, answers) = context(
+            "Answers",
+            count(MdnsRecord::parse(i), counts.answers as usize),
+        )(b)?;
+        let (b, authorities) = context(
+            "Authority RRs",
+            count(MdnsRecord::parse(i), counts.auth_rr as usize),
+        )(b)?;
+        // Bytes past the last record are ignored, as in unicast messages
+        let (_, additional_records) = context(
+            "Additional RRs",
+            count(MdnsRecord::parse(i), counts.add_rr as usize),
+        )(b)?;
+
+        Ok(Self {
+            header,
+            queries,
+            answers,
+            authorities,
+            additional_records,
+        })
+    }
+
+    pub fn counts(&self) -> Result<DnsSectionCounts, GenError> {
+        DnsSectionCounts::from_lens([
+            self.queries.len(),
+            self.answers.len(),
+            self.authorities.len(),
+            self.additional_records.len(),
+        ])
+    }
+
+    pub fn serialize(&self) -> Result<Vec<u8>, GenError> {
+        use cf::{multi::all, sequence::tuple};
+
+        gen_simple(
+            tuple((
+                self.header.serialize(self.counts()?),
+                all(self
+                    .queries
+                    .iter()
+                    .map(|q| q.query.serialize_mdns(q.unicast_response))),
+                all(self
+                    .answers
+                    .iter()
+                    .chain(&self.authorities)
+                    .chain(&self.additional_records)
+                    .map(|r| r.record.serialize_mdns(r.cache_flush))),
+            )),
+            Vec::new(),
+        )
+    }
+}
+
+impl Default for MdnsPacket {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -1,17 +1,23 @@
-use super::header::DnsHeader;
+use super::header::{DnsHeader, DnsSectionCounts, HEADER_SIZE};
+use super::resources::edns::EdnsOption;
 use super::resources::query::DnsQuery;
 use super::resources::record::DnsRecord;
+use super::resources::DnsRecordType;
 use crate::types::{ParseError, ParseInput};
 
-use cookie_factory::{self as cf, gen_simple, GenError};
+use cookie_factory::{self as cf, gen_simple, GenError, SerializeFn};
 use nom::error::context;
 use nom::multi::fold_many_m_n;
+use std::io::Write;
+
+// https://datatracker.ietf.org/doc/html/rfc1035#section-2.3.4
+pub const DEFAULT_UDP_PAYLOAD_SIZE: usize = 512;
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct DnsPacket {
     pub header: DnsHeader,
     queries: Vec<DnsQuery>,
-    responses: Vec<DnsRecord>,
+    answers: Vec<DnsRecord>,
     authorities: Vec<DnsRecord>,
     additional_records: Vec<DnsRecord>,
 }
@@ -21,108 +27,252 @@ impl DnsPacket {
         Self {
             header: DnsHeader::new(),
             queries: Vec::new(),
-            responses: Vec::new(),
+            answers: Vec::new(),
             authorities: Vec::new(),
             additional_records: Vec::new(),
         }
     }
 
     pub fn parse(i: ParseInput) -> Result<Self, ParseError<Vec<u8>>> {
-        let (b, header) = context("Header", DnsHeader::parse)(i)?;
-
-        let (b, queries) = fold_many_m_n(
-            0,
-            header.queries as usize,
-            DnsQuery::parse(i),
-            Vec::with_capacity(header.queries as usize),
-            |mut queries, query| {
-                queries.push(query);
-                queries
-            },
+        let (b, (header, counts)) = context("Header", DnsHeader::parse)(i)?;
+
+        // The sections must hold exactly as many entries as declared in the header
+        let (b, queries) = context(
+            "Queries",
+            fold_many_m_n(
+                counts.queries as usize,
+                counts.queries as usize,
+                DnsQuery::parse(i),
+                Vec::with_capacity(counts.queries as usize),
+                |mut queries, query| {
+                    queries.push(query);
+                    queries
+                },
+            ),
         )(b)?;
 
-        let (b, responses) = fold_many_m_n(
-            0,
-            header.responses as usize,
-            DnsRecord::parse(i),
-            Vec::with_capacity(header.responses as usize),
-            |mut responses, response| {
-                responses.push(response);
-                responses
-            },
+        let (b, answers) = context(
+            "Answers",
+            fold_many_m_n(
+                counts.answers as usize,
+                counts.answers as usize,
+                DnsRecord::parse(i),
+                Vec::with_capacity(counts.answers as usize),
+                |mut answers, answer| {
+                    answers.push(answer);
+                    answers
+                },
+            ),
         )(b)?;
 
-        let (b, authorities) = fold_many_m_n(
-            0,
-            header.auth_rr as usize,
-            DnsRecord::parse(i),
-            Vec::with_capacity(header.auth_rr as usize),
-            |mut authorities, authority| {
-                authorities.push(authority);
-                authorities
-            },
+        let (b, authorities) = context(
+            "Authority RRs",
+            fold_many_m_n(
+                counts.auth_rr as usize,
+                counts.auth_rr as usize,
+                DnsRecord::parse(i),
+                Vec::with_capacity(counts.auth_rr as usize),
+                |mut authorities, authority| {
+                    authorities.push(authority);
+                    authorities
+                },
+            ),
         )(b)?;
 
-        let (_, additional_records) = fold_many_m_n(
-            0,
-            header.add_rr as usize,
-            DnsRecord::parse(i),
-            Vec::with_capacity(header.add_rr as usize),
-            |mut additional_records, additional_record| {
-                additional_records.push(additional_record);
-                additional_records
-            },
+        // Bytes past the last record, e.g. padding added along the way, are ignored like other
+        // implementations do. The dissector still points them out
+        let (_, additional_records) = context(
+            "Additional RRs",
+            fold_many_m_n(
+                counts.add_rr as usize,
+                counts.add_rr as usize,
+                DnsRecord::parse(i),
+                Vec::with_capacity(counts.add_rr as usize),
+                |mut additional_records, additional_record| {
+                    additional_records.push(additional_record);
+                    additional_records
+                },
+            ),
         )(b)?;
 
         Ok(Self {
             header,
             queries,
-            responses,
+            answers,
             authorities,
             additional_records,
         })
     }
 
+    pub fn counts(&self) -> Result<DnsSectionCounts, GenError> {
+        DnsSectionCounts::from_lens([
+            self.queries.len(),
+            self.answers.len(),
+            self.authorities.len(),
+            self.additional_records.len(),
+        ])
+    }
+
+    fn serializer<'a, W: Write + 'a>(
+        &'a self,
+        counts: DnsSectionCounts,
+    ) -> impl SerializeFn<W> + 'a {
+        use cf::{multi::all, sequence::tuple};
+
+        tuple((
+            self.header.serialize(counts),
+            all(self.queries.iter().map(|q| q.serialize())),
+            all(self.answers.iter().map(|r| r.serialize())),
+            all(self.authorities.iter().map(|a| a.serialize())),
+            all(self.additional_records.iter().map(|ar| ar.serialize())),
+        ))
+    }
+
     pub fn serialize(&self) -> Result<Vec<u8>, GenError> {
-        use cf::{combinator::slice, sequence::tuple};
-
-        let bytes = tuple((
-            self.header.serialize(),
-            slice(
-                self.queries
-                    .iter()
-                    // TODO: Don't use unwrap here and handle the error.
-                    .map(|q| q.to_bytes().unwrap())
-                    .flatten()
-                    .collect::<Vec<u8>>(),
-            ),
-            slice(
-                self.responses
-                    .iter()
-                    // TODO: Don't use unwrap here and handle the error.
-                    .map(|r| r.to_bytes().unwrap())
-                    .flatten()
-                    .collect::<Vec<u8>>(),
-            ),
-            slice(
-                self.authorities
-                    .iter()
-                    // TODO: Don't use unwrap here and handle the error.
-                    .map(|a| a.to_bytes().unwrap())
-                    .flatten()
-                    .collect::<Vec<u8>>(),
-            ),
-            slice(
-                self.additional_records
-                    .iter()
-                    // TODO: Don't use unwrap here and handle the error.
-                    .map(|ar| ar.to_bytes().unwrap())
-                    .flatten()
-                    .collect::<Vec<u8>>(),
-            ),
-        ));
+        gen_simple(
+            self.serializer(self.counts()?),
+            Vec::with_capacity(self.wire_len()),
+        )
+    }
+
+    // Writes the packet into `buffer` without allocating and returns the number of bytes
+    // written, or GenError::BufferTooSmall when it doesn't fit
+    pub fn write_to(&self, buffer: &mut [u8]) -> Result<usize, GenError> {
+        let (_, len) = cf::gen(self.serializer(self.counts()?), buffer)?;
+        Ok(len as usize)
+    }
+
+    // Size of the serialized packet, computed without serializing it
+    pub fn wire_len(&self) -> usize {
+        HEADER_SIZE
+            + self.queries.iter().map(DnsQuery::wire_len).sum::<usize>()
+            + self
+                .answers
+                .iter()
+                .chain(&self.authorities)
+                .chain(&self.additional_records)
+                .map(DnsRecord::wire_len)
+                .sum::<usize>()
+    }
+
+    // Serializes the packet while making sure it fits in `max` bytes, following
+    // https://datatracker.ietf.org/doc/html/rfc2181#section-9: additional records
+    // are dropped first, then the authorities and finally whole answer RRsets,
+    // in which case the message is flagged as truncated.
+    pub fn serialize_with_limit(&self, max: usize) -> Result<Vec<u8>, GenError> {
+        self.truncated(max).as_ref().unwrap_or(self).serialize()
+    }
 
-        gen_simple(bytes, Vec::new())
+    // Same as serialize_with_limit, writing into `buffer` with no allocation unless
+    // the packet has to be truncated to fit in `max` bytes
+    pub fn write_with_limit(&self, buffer: &mut [u8], max: usize) -> Result<usize, GenError> {
+        let max = max.min(buffer.len());
+        match self.truncated(max) {
+            Some(packet) => packet.write_to(&mut buffer[..max]),
+            None => self.write_to(&mut buffer[..max]),
+        }
+    }
+
+    // Returns a copy of the packet trimmed down to `max` bytes, or None if it already fits
+    fn truncated(&self, max: usize) -> Option<Self> {
+        if self.wire_len() <= max {
+            return None;
+        }
+
+        let mut packet = self.clone();
+
+        // The OPT pseudo-record is kept so the requester still knows our payload size
+        packet
+            .additional_records
+            .retain(|r| r.get_type() == Some(DnsRecordType::OPT));
+        if packet.wire_len() <= max {
+            return Some(packet);
+        }
+
+        packet.authorities.clear();
+        if packet.wire_len() <= max {
+            return Some(packet);
+        }
+
+        packet.header.flags.truncated = true;
+        while let Some(last) = packet.answers.last() {
+            let name = last.get_name().clone();
+            let record_type = last.type_code();
+
+            packet
+                .answers
+                .retain(|r| !(r.get_name() == &name && r.type_code() == record_type));
+
+            if packet.wire_len() <= max {
+                return Some(packet);
+            }
+        }
+
+        // Only the header and the question are left when even the OPT record doesn't fit
+        packet.additional_records.clear();
+        Some(packet)
+    }
+
+    // Starts a response to this packet with the same transaction ID and queries.
+    // An OPT record is added when the requester used EDNS, so it knows we support it.
+    pub fn reply(&self) -> Self {
+        let mut response = Self::new();
+        response.header.transaction_id = self.header.transaction_id;
+        response.add_queries(self.queries.clone());
+
+        if let Some(DnsRecord::OPT { name, version, .. }) = self.edns() {
+            response.add_record(DnsRecord::OPT {
+                name: name.clone(),
+                payload_size: DEFAULT_UDP_PAYLOAD_SIZE as u16,
+                extended_rcode: 0,
+                version: *version,
+                dnssec_ok: false,
+                options: Vec::new(),
+            });
+        }
+
+        response
+    }
+
+    // Whether this packet answers `request`, echoing its transaction ID and its questions
+    // https://datatracker.ietf.org/doc/html/rfc5452#section-4.3
+    pub fn is_response_to(&self, request: &DnsPacket) -> bool {
+        self.header.flags.response
+            && self.header.transaction_id == request.header.transaction_id
+            && self.queries.len() == request.queries.len()
+            && self.queries.iter().zip(&request.queries).all(|(a, b)| {
+                a.record_type == b.record_type
+                    && a.class == b.class
+                    && a.name.eq_ignore_case(&b.name)
+            })
+    }
+
+    // The largest response the requester is able to receive over UDP,
+    // as advertised in its EDNS OPT record
+    pub fn max_payload_size(&self) -> usize {
+        match self.edns() {
+            Some(DnsRecord::OPT { payload_size, .. }) => {
+                (*payload_size as usize).max(DEFAULT_UDP_PAYLOAD_SIZE)
+            }
+            _ => DEFAULT_UDP_PAYLOAD_SIZE,
+        }
+    }
+
+    pub fn edns(&self) -> Option<&DnsRecord> {
+        self.additional_records
+            .iter()
+            .find(|r| r.get_type() == Some(DnsRecordType::OPT))
+    }
+
+    // Options can only be sent to requesters that used EDNS, so they are dropped otherwise
+    pub fn add_edns_option(&mut self, option: EdnsOption) {
+        let opt = self
+            .additional_records
+            .iter_mut()
+            .find(|r| r.get_type() == Some(DnsRecordType::OPT));
+        if let Some(DnsRecord::OPT { options, .. }) = opt {
+            options.push(option);
+        }
     }
 
     // IMPROVEMENT: Could make a macro for this.
@@ -130,56 +280,64 @@ impl DnsPacket {
         &self.queries
     }
 
+    pub fn queries_mut(&mut self) -> &mut Vec<DnsQuery> {
+        &mut self.queries
+    }
+
     pub fn add_query(&mut self, query: DnsQuery) {
-        self.header.queries += 1;
         self.queries.push(query);
     }
 
     pub fn add_queries(&mut self, queries: Vec<DnsQuery>) {
-        self.header.queries += queries.len() as u16;
-        self.queries.extend(queries.iter().cloned());
+        self.queries.extend(queries);
+    }
+
+    pub fn answers(&self) -> &Vec<DnsRecord> {
+        &self.answers
     }
 
-    pub fn responses(&self) -> &Vec<DnsRecord> {
-        &self.responses
+    pub fn answers_mut(&mut self) -> &mut Vec<DnsRecord> {
+        &mut self.answers
     }
 
-    pub fn add_response(&mut self, response: DnsRecord) {
-        self.header.responses += 1;
-        self.responses.push(response);
+    pub fn add_answer(&mut self, answer: DnsRecord) {
+        self.answers.push(answer);
     }
 
-    pub fn add_responses(&mut self, responses: Vec<DnsRecord>) {
-        self.header.responses += responses.len() as u16;
-        self.responses.extend(responses.iter().cloned());
+    pub fn add_answers(&mut self, answers: Vec<DnsRecord>) {
+        self.answers.extend(answers);
     }
 
     pub fn authorities(&self) -> &Vec<DnsRecord> {
         &self.authorities
     }
 
+    pub fn authorities_mut(&mut self) -> &mut Vec<DnsRecord> {
+        &mut self.authorities
+    }
+
     pub fn add_authority(&mut self, authority: DnsRecord) {
-        self.header.auth_rr += 1;
         self.authorities.push(authority);
     }
 
     pub fn add_authorities(&mut self, authorities: Vec<DnsRecord>) {
-        self.header.auth_rr += authorities.len() as u16;
-        self.authorities.extend(authorities.iter().cloned());
+        self.authorities.extend(authorities);
     }
 
     pub fn additional_records(&self) -> &Vec<DnsRecord> {
         &self.additional_records
     }
 
+    pub fn additional_records_mut(&mut self) -> &mut Vec<DnsRecord> {
+        &mut self.additional_records
+    }
+
     pub fn add_record(&mut self, record: DnsRecord) {
-        self.header.add_rr += 1;
         self.additional_records.push(record);
     }
 
     pub fn add_records(&mut self, records: Vec<DnsRecord>) {
-        self.header.add_rr += records.len() as u16;
-        self.additional_records.extend(records.iter().cloned());
+        self.additional_records.extend(records);
     }
 }
 
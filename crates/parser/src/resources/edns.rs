@@ -0,0 +1,59 @@
+use crate::types::{ParseInput, ParseResult};
+
+use cookie_factory::{self as cf, gen_simple, GenError, SerializeFn};
+use nom::{bytes::complete::take, combinator::map, error::context, number::complete::be_u16};
+use std::io::Write;
+
+// https://datatracker.ietf.org/doc/html/rfc8914#section-2
+pub const EXTENDED_ERROR_CODE: u16 = 15;
+// https://datatracker.ietf.org/doc/html/rfc8914#section-4.4
+pub const EXTENDED_ERROR_STALE_ANSWER: u16 = 3;
+
+// https://datatracker.ietf.org/doc/html/rfc6891#section-6.1.2
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EdnsOption {
+    pub code: u16,
+    pub data: Vec<u8>,
+}
+
+impl EdnsOption {
+    // Extended DNS Error, with an optional UTF-8 text for humans
+    pub fn extended_error(info_code: u16, extra_text: &str) -> Self {
+        let mut data = info_code.to_be_bytes().to_vec();
+        data.extend_from_slice(extra_text.as_bytes());
+        Self {
+            code: EXTENDED_ERROR_CODE,
+            data,
+        }
+    }
+
+    pub fn parse(i: ParseInput) -> ParseResult<Self> {
+        let (i, code) = context("Option code", be_u16)(i)?;
+        let (i, len) = context("Option length", be_u16)(i)?;
+        map(
+            context("Option data", take(len)),
+            move |data: ParseInput| Self {
+                code,
+                data: data.to_vec(),
+            },
+        )(i)
+    }
+
+    pub fn serialize<'a, W: Write + 'a>(&'a self) -> impl SerializeFn<W> + 'a {
+        use cf::{bytes::be_u16, combinator::slice, sequence::tuple};
+
+        tuple((
+            be_u16(self.code),
+            be_u16(self.data.len() as u16),
+            slice(&self.data[..]),
+        ))
+    }
+
+    pub fn wire_len(&self) -> usize {
+        4 + self.data.len()
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>, GenError> {
+        gen_simple(self.serialize(), Vec::new())
+    }
+}
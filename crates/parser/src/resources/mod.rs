@@ -1,11 +1,13 @@
+pub mod edns;
 pub mod name;
 pub mod query;
 pub mod record;
 
 use derive_try_from_primitive::*;
+use std::convert::TryFrom;
 
 #[allow(clippy::upper_case_acronyms)]
-#[derive(Debug, TryFromPrimitive, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, TryFromPrimitive, Clone, Copy, PartialEq, Eq, Hash)]
 #[repr(u16)]
 pub enum DnsRecordType {
     A = 0x01,
@@ -24,10 +26,13 @@ pub enum DnsRecordType {
     MINFO = 0x0E,
     MX = 0x0F,
     TXT = 0x10,
-    AAAA = 0x1C, // RFC 3596
+    AAAA = 0x1C,  // RFC 3596
+    SRV = 0x21,   // RFC 2782
+    DNAME = 0x27, // RFC 6672
+    OPT = 0x29,   // RFC 6891
 }
 
-#[derive(Debug, TryFromPrimitive, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, TryFromPrimitive, Clone, Copy, PartialEq, Eq, Hash)]
 #[repr(u16)]
 pub enum DnsClass {
     IN = 0x01,
@@ -35,3 +40,92 @@ pub enum DnsClass {
     CH = 0x03,
     HS = 0x04,
 }
+
+// QTYPE values are a superset of the record types
+// https://datatracker.ietf.org/doc/html/rfc1035#section-3.2.3
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QueryType {
+    Record(DnsRecordType),
+    IXFR, // RFC 1995
+    AXFR,
+    MAILB,
+    MAILA,
+    ANY,
+    // A type this parser doesn't know, which can still be asked for
+    // https://datatracker.ietf.org/doc/html/rfc3597#section-2
+    Unknown(u16),
+}
+
+impl TryFrom<u16> for QueryType {
+    type Error = u16;
+
+    fn try_from(n: u16) -> Result<Self, Self::Error> {
+        match n {
+            0xFB => Ok(Self::IXFR),
+            0xFC => Ok(Self::AXFR),
+            0xFD => Ok(Self::MAILB),
+            0xFE => Ok(Self::MAILA),
+            0xFF => Ok(Self::ANY),
+            _ => DnsRecordType::try_from(n).map(Self::Record),
+        }
+    }
+}
+
+impl From<QueryType> for u16 {
+    fn from(query_type: QueryType) -> Self {
+        match query_type {
+            QueryType::Record(record_type) => record_type as u16,
+            QueryType::IXFR => 0xFB,
+            QueryType::AXFR => 0xFC,
+            QueryType::MAILB => 0xFD,
+            QueryType::MAILA => 0xFE,
+            QueryType::ANY => 0xFF,
+            QueryType::Unknown(n) => n,
+        }
+    }
+}
+
+impl From<DnsRecordType> for QueryType {
+    fn from(record_type: DnsRecordType) -> Self {
+        Self::Record(record_type)
+    }
+}
+
+// QCLASS values are a superset of the classes
+// https://datatracker.ietf.org/doc/html/rfc1035#section-3.2.5
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QueryClass {
+    Class(DnsClass),
+    NONE, // RFC 2136
+    ANY,
+}
+
+impl TryFrom<u16> for QueryClass {
+    type Error = u16;
+
+    fn try_from(n: u16) -> Result<Self, Self::Error> {
+        match n {
+            0xFE => Ok(Self::NONE),
+            0xFF => Ok(Self::ANY),
+            _ => DnsClass::try_from(n).map(Self::Class),
+        }
+    }
+}
+
+impl From<QueryClass> for u16 {
+    fn from(class: QueryClass) -> Self {
+        match class {
+            QueryClass::Class(class) => class as u16,
+            QueryClass::NONE => 0xFE,
+            QueryClass::ANY => 0xFF,
+        }
+    }
+}
+
+impl From<DnsClass> for QueryClass {
+    fn from(class: DnsClass) -> Self {
+        Self::Class(class)
+    }
+}
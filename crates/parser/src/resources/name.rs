@@ -1,13 +1,21 @@
 use crate::types::{ParseInput, ParseResult};
 
 use cookie_factory::{self as cf, gen_simple, GenError, SerializeFn};
-use nom::{bytes::complete::take, combinator::map, number::complete::be_u8};
+use nom::{
+    bytes::complete::take,
+    combinator::map,
+    error::{ErrorKind, ParseError as NomParseError},
+    number::complete::be_u8,
+    Err as NomErr,
+};
+use std::fmt::Debug;
 use std::io::Write;
-use std::{fmt::Debug, str::from_utf8};
 
 const COMPRESSION_MASK: u8 = 0xC0;
+// https://datatracker.ietf.org/doc/html/rfc1035#section-2.3.4
+pub const MAX_NAME_LENGTH: usize = 255;
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct DnsLabel {
     pub data: Vec<u8>,
 }
@@ -18,17 +26,17 @@ impl DnsLabel {
     }
 }
 
-#[derive(Clone, PartialEq, Eq)]
+#[derive(Clone, PartialEq, Eq, Hash)]
 pub struct DnsName {
     labels: Vec<DnsLabel>,
 }
 
 impl Debug for DnsName {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let name: Vec<&str> = self
+        let name: Vec<_> = self
             .labels
             .iter()
-            .map(|label| from_utf8(&label.data[..]).unwrap())
+            .map(|label| String::from_utf8_lossy(&label.data[..]))
             .collect();
         write!(f, "{}", name.join("."))
     }
@@ -37,17 +45,134 @@ impl Debug for DnsName {
 impl From<&str> for DnsName {
     fn from(s: &str) -> Self {
         Self {
-            labels: s.split('.').map(|s| DnsLabel::new(s.as_bytes())).collect(),
+            labels: s
+                .split('.')
+                .filter(|s| !s.is_empty())
+                .map(|s| DnsLabel::new(s.as_bytes()))
+                .collect(),
         }
     }
 }
 
+impl From<Vec<DnsLabel>> for DnsName {
+    fn from(labels: Vec<DnsLabel>) -> Self {
+        Self { labels }
+    }
+}
+
 impl DnsName {
+    pub fn root() -> Self {
+        Self { labels: Vec::new() }
+    }
+
+    pub fn labels(&self) -> &[DnsLabel] {
+        &self.labels
+    }
+
+    // Names compare case-insensitively, so lowercase is their canonical form
+    // https://datatracker.ietf.org/doc/html/rfc4343
+    pub fn to_lowercase(&self) -> Self {
+        Self {
+            labels: self
+                .labels
+                .iter()
+                .map(|label| DnsLabel {
+                    data: label.data.to_ascii_lowercase(),
+                })
+                .collect(),
+        }
+    }
+
+    // Whether both names are the same regardless of the ASCII case of their labels
+    pub fn eq_ignore_case(&self, other: &DnsName) -> bool {
+        self.labels.len() == other.labels.len()
+            && self
+                .labels
+                .iter()
+                .zip(&other.labels)
+                .all(|(a, b)| a.data.eq_ignore_ascii_case(&b.data))
+    }
+
+    // Whether the name is `zone` or one of the names below it
+    pub fn is_subdomain_of(&self, zone: &DnsName) -> bool {
+        let depth = zone.labels.len();
+        self.labels.len() >= depth
+            && DnsName::from(self.labels[self.labels.len() - depth..].to_vec()).eq_ignore_case(zone)
+    }
+
+    // Substitutes `target` for the `owner` suffix of a name below it, as DNAME records do. None
+    // when the name isn't below `owner` or when the result would be too long
+    // https://datatracker.ietf.org/doc/html/rfc6672#section-2.2
+    pub fn rebase(&self, owner: &DnsName, target: &DnsName) -> Option<DnsName> {
+        if self.labels.len() <= owner.labels.len() || !self.is_subdomain_of(owner) {
+            return None;
+        }
+        let prefix = &self.labels[..self.labels.len() - owner.labels.len()];
+        let name = DnsName::from([prefix, &target.labels[..]].concat());
+        if name.wire_len() > MAX_NAME_LENGTH {
+            return None;
+        }
+        Some(name)
+    }
+
+    // Flips the case of each letter at random, for resolvers to check that responses echo it
+    // https://datatracker.ietf.org/doc/html/draft-vixie-dnsext-dns0x20-00#section-4
+    pub fn randomize_case(&self) -> Self {
+        Self {
+            labels: self
+                .labels
+                .iter()
+                .map(|label| DnsLabel {
+                    data: label
+                        .data
+                        .iter()
+                        .map(|&c| {
+                            if rand::random() {
+                                c.to_ascii_uppercase()
+                            } else {
+                                c.to_ascii_lowercase()
+                            }
+                        })
+                        .collect(),
+                })
+                .collect(),
+        }
+    }
+
+    // Bytes allocated on the heap for the labels, past the size of the name itself
+    pub fn heap_size(&self) -> usize {
+        self.labels.capacity() * std::mem::size_of::<DnsLabel>()
+            + self
+                .labels
+                .iter()
+                .map(|label| label.data.capacity())
+                .sum::<usize>()
+    }
+
     pub fn process_name<'a>(
+        i: ParseInput<'a>,
+        reference_bytes: ParseInput<'a>,
+        labels: Vec<DnsLabel>,
+    ) -> ParseResult<'a, Vec<DnsLabel>> {
+        // A name can't point to itself, so its first pointer must jump before it. The input may
+        // be a slice of the message, e.g. the data of a record, so its position is taken from
+        // where it starts
+        let limit = (i.as_ptr() as usize)
+            .checked_sub(reference_bytes.as_ptr() as usize)
+            .filter(|&position| position <= reference_bytes.len())
+            .unwrap_or(0);
+        Self::process_labels(i, reference_bytes, labels, limit)
+    }
+
+    // Reads the labels up to the end of the name, following pointers that must each land before
+    // `limit`, the target of the previous one
+    fn process_labels<'a>(
         i: ParseInput<'a>,
         reference_bytes: ParseInput<'a>,
         mut labels: Vec<DnsLabel>,
+        limit: usize,
     ) -> ParseResult<'a, Vec<DnsLabel>> {
+        let start = i;
         let (i, size) = be_u8(i)?;
         if size == 0x00 {
             return Ok((i, labels));
@@ -57,18 +182,34 @@ impl DnsName {
         // means that we need to jump to an offset in our reference_bytes
         // because of compression
         if (size & COMPRESSION_MASK) == COMPRESSION_MASK {
-            let (i, offset) = be_u8(i)?;
-            let (_, parts) = Self::process_name(
-                &reference_bytes[(offset as usize)..],
-                reference_bytes,
-                labels,
-            )?;
+            let (i, next) = be_u8(i)?;
+            let offset = (((size & !COMPRESSION_MASK) as usize) << 8) | next as usize;
+            // Each jump goes further back than the previous one so that pointers can't loop
+            if offset >= limit {
+                return Err(NomErr::Error(NomParseError::from_error_kind(
+                    start,
+                    ErrorKind::Verify,
+                )));
+            }
+            let (_, parts) =
+                Self::process_labels(&reference_bytes[offset..], reference_bytes, labels, offset)?;
             return Ok((i, parts));
         }
 
         let (i, part) = take(size)(i)?;
         labels.push(DnsLabel::new(part));
-        Self::process_name(i, reference_bytes, labels)
+        let length = labels
+            .iter()
+            .map(|label| 1 + label.data.len())
+            .sum::<usize>()
+            + 1;
+        if length > MAX_NAME_LENGTH {
+            return Err(NomErr::Error(NomParseError::from_error_kind(
+                start,
+                ErrorKind::TooLarge,
+            )));
+        }
+        Self::process_labels(i, reference_bytes, labels, limit)
     }
 
     pub fn parse<'a>(
@@ -83,24 +224,25 @@ impl DnsName {
     }
 
     pub fn serialize<'a, W: Write + 'a>(&'a self) -> impl SerializeFn<W> + 'a {
-        use cf::combinator::slice;
+        use cf::{bytes::be_u8, combinator::slice, multi::all, sequence::tuple};
 
-        move |out| {
-            let mut serialized_labels: Vec<u8> = self
+        // TODO: Implement label compression
+        tuple((
+            all(self
                 .labels
                 .iter()
-                .map(|label| {
-                    // TODO: Implement label compression
-                    let mut vec = vec![label.data.len() as u8];
-                    vec.extend_from_slice(&label.data[..]);
-                    vec
-                })
-                .flatten()
-                .collect();
-            serialized_labels.push(0x00);
+                .map(|label| tuple((be_u8(label.data.len() as u8), slice(&label.data[..]))))),
+            be_u8(0x00),
+        ))
+    }
 
-            slice(serialized_labels)(out)
-        }
+    // Size of the uncompressed name on the wire, including the null terminator
+    pub fn wire_len(&self) -> usize {
+        self.labels
+            .iter()
+            .map(|label| 1 + label.data.len())
+            .sum::<usize>()
+            + 1
     }
 
     pub fn to_bytes(&self) -> Result<Vec<u8>, GenError> {
@@ -153,6 +295,56 @@ mod tests {
         assert_eq!(name, DnsName::from("subdomain.local.com"));
     }
 
+    #[test]
+    fn test_parse_compression_loop() {
+        let bytes = [
+            0x03, 0x77, 0x77, 0x77, // "www"
+            0xC0, 0x04, // Compression jump to itself
+        ];
+
+        assert!(DnsName::parse(&bytes)(&bytes).is_err());
+    }
+
+    #[test]
+    fn test_parse_compression_loop_through_label() {
+        let bytes = [
+            0x00, 0x01, 0x01, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // Header
+            0x03, 0x77, 0x77, 0x77, // "www"
+            0xC0, 0x0C, // Compression jump back to "www"
+            0x00, 0x01, 0x00, 0x01, // Type and class
+        ];
+
+        assert!(DnsName::parse(&bytes)(&bytes[12..]).is_err());
+        assert!(crate::packet::DnsPacket::parse(&bytes).is_err());
+    }
+
+    #[test]
+    fn test_parse_name_too_long() {
+        let label = [&[63][..], &[0x61; 63][..]].concat();
+        let mut bytes = label.repeat(3);
+        bytes.extend_from_slice(&[61]);
+        bytes.extend_from_slice(&[0x61; 61]);
+        bytes.push(0x00);
+        // 255 bytes, the longest name allowed
+        assert_eq!(DnsName::parse(&bytes)(&bytes).unwrap().1.wire_len(), 255);
+
+        bytes.truncate(bytes.len() - 1);
+        bytes.extend_from_slice(&[0x61, 0x00]);
+        bytes[3 * 64] = 62;
+        assert!(DnsName::parse(&bytes)(&bytes).is_err());
+    }
+
+    #[test]
+    fn test_parse_long_compression_offset() {
+        let mut bytes = vec![0; 0x0123];
+        bytes.extend_from_slice(&[0x05, 0x6c, 0x6f, 0x63, 0x61, 0x6c, 0x00]); // "local"
+        bytes.extend_from_slice(&[0xC1, 0x23]); // Compression jump to 0x0123
+
+        let (_, name) = DnsName::parse(&bytes)(&bytes[0x012A..]).unwrap();
+
+        assert_eq!(name, DnsName::from("local"));
+    }
+
     #[test]
     fn test_serialize() {
         let name = DnsName::from("www.local.com");
@@ -167,4 +359,11 @@ mod tests {
             ]
         );
     }
+
+    #[test]
+    fn test_serialize_root() {
+        assert_eq!(DnsName::root().to_bytes().unwrap(), vec![0x00]);
+        assert_eq!(DnsName::from(""), DnsName::root());
+        assert_eq!(DnsName::from("local.com."), DnsName::from("local.com"));
+    }
 }
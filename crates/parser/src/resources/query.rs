@@ -13,44 +13,106 @@ use nom::{
 };
 use std::convert::TryFrom;
 
+// mDNS queries use the top bit of the class to ask for a unicast response
+// https://datatracker.ietf.org/doc/html/rfc6762#section-5.4
+pub const UNICAST_RESPONSE_MASK: u16 = 0x8000;
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct DnsQuery {
     pub name: DnsName,
-    pub record_type: DnsRecordType,
-    pub class: DnsClass,
+    pub record_type: QueryType,
+    pub class: QueryClass,
 }
 
 impl DnsQuery {
     pub fn parse<'a>(
         reference_bytes: ParseInput<'a>,
-    ) -> impl FnMut(ParseInput<'a>) -> ParseResult<Self> {
-        move |i: ParseInput| {
+    ) -> impl FnMut(ParseInput<'a>) -> ParseResult<'a, Self> {
+        map(
+            Self::parse_with_class_mask(reference_bytes, 0),
+            |(query, _)| query,
+        )
+    }
+
+    // Parses a query as sent over mDNS, along with its unicast-response bit
+    pub fn parse_mdns<'a>(
+        reference_bytes: ParseInput<'a>,
+    ) -> impl FnMut(ParseInput<'a>) -> ParseResult<'a, (Self, bool)> {
+        Self::parse_with_class_mask(reference_bytes, UNICAST_RESPONSE_MASK)
+    }
+
+    fn parse_with_class_mask<'a>(
+        reference_bytes: ParseInput<'a>,
+        mask: u16,
+    ) -> impl FnMut(ParseInput<'a>) -> ParseResult<'a, (Self, bool)> {
+        move |i: ParseInput<'a>| {
             map(
                 tuple((
                     context("Name", DnsName::parse(reference_bytes)),
-                    context("Type", map_res(be_u16, DnsRecordType::try_from)),
-                    context("Class", map_res(be_u16, DnsClass::try_from)),
+                    context(
+                        "Type",
+                        map(be_u16, |n| {
+                            QueryType::try_from(n).unwrap_or(QueryType::Unknown(n))
+                        }),
+                    ),
+                    context(
+                        "Class",
+                        map_res(be_u16, |class| {
+                            QueryClass::try_from(class & !mask).map(|c| (c, class & mask != 0))
+                        }),
+                    ),
                 )),
-                |(name, record_type, class)| DnsQuery {
-                    name,
-                    record_type,
-                    class,
+                |(name, record_type, (class, class_bit))| {
+                    (
+                        DnsQuery {
+                            name,
+                            record_type,
+                            class,
+                        },
+                        class_bit,
+                    )
                 },
             )(i)
         }
     }
 
     pub fn serialize<'a, W: Write + 'a>(&'a self) -> impl SerializeFn<W> + 'a {
-        use cf::{bytes::be_u16, combinator::slice, sequence::tuple};
+        self.serialize_with_class_bits(0)
+    }
+
+    pub fn serialize_mdns<'a, W: Write + 'a>(
+        &'a self,
+        unicast_response: bool,
+    ) -> impl SerializeFn<W> + 'a {
+        self.serialize_with_class_bits(if unicast_response {
+            UNICAST_RESPONSE_MASK
+        } else {
+            0
+        })
+    }
+
+    fn serialize_with_class_bits<'a, W: Write + 'a>(
+        &'a self,
+        class_bits: u16,
+    ) -> impl SerializeFn<W> + 'a {
+        use cf::{bytes::be_u16, sequence::tuple};
 
         tuple((
-            slice(self.name.to_bytes().unwrap()),
-            be_u16(self.record_type as u16),
-            be_u16(self.class as u16),
+            self.name.serialize(),
+            be_u16(self.record_type.into()),
+            be_u16(u16::from(self.class) | class_bits),
         ))
     }
 
+    pub fn wire_len(&self) -> usize {
+        self.name.wire_len() + 4
+    }
+
     pub fn to_bytes(&self) -> Result<Vec<u8>, GenError> {
         gen_simple(self.serialize(), Vec::new())
     }
+
+    pub fn to_bytes_mdns(&self, unicast_response: bool) -> Result<Vec<u8>, GenError> {
+        gen_simple(self.serialize_mdns(unicast_response), Vec::new())
+    }
 }
@@ -1,17 +1,22 @@
-use super::{name::DnsName, DnsClass, DnsRecordType};
-use crate::types::{ParseInput, ParseResult};
+use super::{edns::EdnsOption, name::DnsName, DnsClass, DnsRecordType};
+use crate::types::{ParseError, ParseInput, ParseResult};
 
 use cookie_factory::{self as cf, gen_simple, GenError, SerializeFn};
-use std::{io::Write, net::IpAddr};
+use std::{
+    io::{self, Write},
+    net::IpAddr,
+};
 
 use nom::{
     bytes::complete::take,
-    combinator::map_res,
-    error::context,
-    number::complete::{be_u16, be_u32},
+    combinator::{all_consuming, map, map_res},
+    error::{context, ContextError, ErrorKind, ParseError as NomParseError},
+    multi::{many0, many1},
+    number::complete::{be_u16, be_u32, be_u8},
+    Err as NomErr,
 };
 use std::{
-    convert::{TryFrom, TryInto},
+    convert::TryFrom,
     net::{Ipv4Addr, Ipv6Addr},
 };
 
@@ -35,6 +40,37 @@ pub enum DnsRecord {
         ttl: u32,
         canonical_name: DnsName,
     },
+    PTR {
+        name: DnsName,
+        class: DnsClass,
+        ttl: u32,
+        pointer: DnsName,
+    },
+    SOA {
+        name: DnsName,
+        class: DnsClass,
+        ttl: u32,
+        mname: DnsName,
+        rname: DnsName,
+        serial: u32,
+        refresh: u32,
+        retry: u32,
+        expire: u32,
+        minimum: u32,
+    },
+    HINFO {
+        name: DnsName,
+        class: DnsClass,
+        ttl: u32,
+        cpu: Vec<u8>,
+        os: Vec<u8>,
+    },
+    TXT {
+        name: DnsName,
+        class: DnsClass,
+        ttl: u32,
+        data: Vec<Vec<u8>>,
+    },
     // RFC 3596
     AAAA {
         name: DnsName,
@@ -42,167 +78,585 @@ pub enum DnsRecord {
         ttl: u32,
         address: Ipv6Addr,
     },
+    // RFC 2782
+    SRV {
+        name: DnsName,
+        class: DnsClass,
+        ttl: u32,
+        priority: u16,
+        weight: u16,
+        port: u16,
+        target: DnsName,
+    },
+    // RFC 6672
+    DNAME {
+        name: DnsName,
+        class: DnsClass,
+        ttl: u32,
+        target: DnsName,
+    },
+    // A record of a type that isn't supported, its data being kept as is
+    // https://datatracker.ietf.org/doc/html/rfc3597
+    Unknown {
+        name: DnsName,
+        record_type: u16,
+        class: DnsClass,
+        ttl: u32,
+        data: Vec<u8>,
+    },
+    // RFC 6891, the class and TTL fields are repurposed for the EDNS metadata
+    OPT {
+        name: DnsName,
+        payload_size: u16,
+        extended_rcode: u8,
+        version: u8,
+        dnssec_ok: bool,
+        options: Vec<EdnsOption>,
+    },
 }
 
+const DNSSEC_OK_MASK: u16 = 0x8000;
+// mDNS uses the top bit of the class to tell caches to flush the other records of the RRset
+// https://datatracker.ietf.org/doc/html/rfc6762#section-10.2
+pub const CACHE_FLUSH_MASK: u16 = 0x8000;
+
 impl DnsRecord {
     pub fn parse<'a>(
         reference_bytes: ParseInput<'a>,
     ) -> impl FnMut(ParseInput<'a>) -> ParseResult<'a, Self> {
+        map(
+            Self::parse_with_class_mask(reference_bytes, 0),
+            |(record, _)| record,
+        )
+    }
+
+    // Parses a record as sent over mDNS, along with its cache-flush bit
+    pub fn parse_mdns<'a>(
+        reference_bytes: ParseInput<'a>,
+    ) -> impl FnMut(ParseInput<'a>) -> ParseResult<'a, (Self, bool)> {
+        Self::parse_with_class_mask(reference_bytes, CACHE_FLUSH_MASK)
+    }
+
+    fn parse_with_class_mask<'a>(
+        reference_bytes: ParseInput<'a>,
+        mask: u16,
+    ) -> impl FnMut(ParseInput<'a>) -> ParseResult<'a, (Self, bool)> {
         move |i: ParseInput<'a>| {
             let (i, name) = context("Name", DnsName::parse(reference_bytes))(i)?;
-            let (i, record_type) = context("Type", map_res(be_u16, DnsRecordType::try_from))(i)?;
-            let (i, class) = context("Class", map_res(be_u16, DnsClass::try_from))(i)?;
+            let (i, record_type) = context("Type", be_u16)(i)?;
+            if record_type == DnsRecordType::OPT as u16 {
+                return context(
+                    "OPT",
+                    map(|i| Self::parse_opt(i, name.clone()), |r| (r, false)),
+                )(i);
+            }
+
+            let (i, class) = context("Class", be_u16)(i)?;
+            let class_bit = class & mask != 0;
+            let (i, class) = match DnsClass::try_from(class & !mask) {
+                Ok(class) => (i, class),
+                Err(_) => {
+                    return Err(NomErr::Error(ParseError::from_error_kind(
+                        i,
+                        ErrorKind::MapRes,
+                    )))
+                }
+            };
             let (i, ttl) = context("Time to live", be_u32)(i)?;
             let (i, len) = context("Data length", be_u16)(i)?;
+            // The data can't be read past its declared length
+            let (i, data) = context("Data", take(len))(i)?;
 
-            match record_type {
-                DnsRecordType::A => {
-                    let (i, bytes) = context("Address", take(len))(i)?;
-                    let bytes: [u8; 4] = bytes.try_into().unwrap();
-                    let address = Ipv4Addr::from(bytes);
+            let (_, record) =
+                Self::parse_data(data, reference_bytes, record_type, name, class, ttl, len)?;
+            Ok((i, (record, class_bit)))
+        }
+    }
 
-                    Ok((
-                        i,
-                        Self::A {
-                            name,
-                            class,
-                            ttl,
-                            address,
-                        },
-                    ))
-                }
-                DnsRecordType::NS => {
-                    let (i, name_server) =
-                        context("Name Server", DnsName::parse(reference_bytes))(i)?;
+    #[allow(clippy::too_many_arguments)]
+    fn parse_data<'a>(
+        i: ParseInput<'a>,
+        reference_bytes: ParseInput<'a>,
+        record_type: u16,
+        name: DnsName,
+        class: DnsClass,
+        ttl: u32,
+        len: u16,
+    ) -> ParseResult<'a, Self> {
+        match DnsRecordType::try_from(record_type) {
+            Ok(DnsRecordType::A) => {
+                let (i, bytes) = context("Address", map_res(take(len), <[u8; 4]>::try_from))(i)?;
+                let address = Ipv4Addr::from(bytes);
 
-                    Ok((
-                        i,
-                        Self::NS {
-                            name,
-                            class,
-                            ttl,
-                            name_server,
-                        },
-                    ))
-                }
-                DnsRecordType::CNAME => {
-                    let (i, canonical_name) =
-                        context("Canonical Name", DnsName::parse(reference_bytes))(i)?;
+                Ok((
+                    i,
+                    Self::A {
+                        name,
+                        class,
+                        ttl,
+                        address,
+                    },
+                ))
+            }
+            Ok(DnsRecordType::NS) => {
+                let (i, name_server) = context("Name Server", DnsName::parse(reference_bytes))(i)?;
 
-                    Ok((
-                        i,
-                        Self::CNAME {
-                            name,
-                            class,
-                            ttl,
-                            canonical_name,
-                        },
-                    ))
-                }
-                DnsRecordType::AAAA => {
-                    let (i, bytes) = context("Address", take(len))(i)?;
-                    let bytes: [u8; 16] = bytes.try_into().unwrap();
-                    let address = Ipv6Addr::from(bytes);
+                Ok((
+                    i,
+                    Self::NS {
+                        name,
+                        class,
+                        ttl,
+                        name_server,
+                    },
+                ))
+            }
+            Ok(DnsRecordType::CNAME) => {
+                let (i, canonical_name) =
+                    context("Canonical Name", DnsName::parse(reference_bytes))(i)?;
 
-                    Ok((
-                        i,
-                        Self::AAAA {
-                            name,
-                            class,
-                            ttl,
-                            address,
-                        },
-                    ))
-                }
-                _ => unimplemented!(),
+                Ok((
+                    i,
+                    Self::CNAME {
+                        name,
+                        class,
+                        ttl,
+                        canonical_name,
+                    },
+                ))
+            }
+            Ok(DnsRecordType::DNAME) => {
+                let (i, target) = context("Target", DnsName::parse(reference_bytes))(i)?;
+
+                Ok((
+                    i,
+                    Self::DNAME {
+                        name,
+                        class,
+                        ttl,
+                        target,
+                    },
+                ))
+            }
+            Ok(DnsRecordType::PTR) => {
+                let (i, pointer) = context("Domain Name", DnsName::parse(reference_bytes))(i)?;
+
+                Ok((
+                    i,
+                    Self::PTR {
+                        name,
+                        class,
+                        ttl,
+                        pointer,
+                    },
+                ))
+            }
+            Ok(DnsRecordType::SOA) => {
+                let (i, mname) =
+                    context("Primary name server", DnsName::parse(reference_bytes))(i)?;
+                let (i, rname) =
+                    context("Responsible mailbox", DnsName::parse(reference_bytes))(i)?;
+                let (i, serial) = context("Serial", be_u32)(i)?;
+                let (i, refresh) = context("Refresh", be_u32)(i)?;
+                let (i, retry) = context("Retry", be_u32)(i)?;
+                let (i, expire) = context("Expire", be_u32)(i)?;
+                let (i, minimum) = context("Minimum", be_u32)(i)?;
+
+                Ok((
+                    i,
+                    Self::SOA {
+                        name,
+                        class,
+                        ttl,
+                        mname,
+                        rname,
+                        serial,
+                        refresh,
+                        retry,
+                        expire,
+                        minimum,
+                    },
+                ))
+            }
+            Ok(DnsRecordType::TXT) => {
+                let (i, data) = context("Text", take(len))(i)?;
+                let (_, data) =
+                    context("Text", all_consuming(many1(parse_character_string)))(data)?;
+
+                Ok((
+                    i,
+                    Self::TXT {
+                        name,
+                        class,
+                        ttl,
+                        data,
+                    },
+                ))
+            }
+            Ok(DnsRecordType::SRV) => {
+                let (i, priority) = context("Priority", be_u16)(i)?;
+                let (i, weight) = context("Weight", be_u16)(i)?;
+                let (i, port) = context("Port", be_u16)(i)?;
+                let (i, target) = context("Target", DnsName::parse(reference_bytes))(i)?;
+
+                Ok((
+                    i,
+                    Self::SRV {
+                        name,
+                        class,
+                        ttl,
+                        priority,
+                        weight,
+                        port,
+                        target,
+                    },
+                ))
+            }
+            Ok(DnsRecordType::HINFO) => {
+                let (i, cpu) = context("CPU", parse_character_string)(i)?;
+                let (i, os) = context("OS", parse_character_string)(i)?;
+
+                Ok((
+                    i,
+                    Self::HINFO {
+                        name,
+                        class,
+                        ttl,
+                        cpu,
+                        os,
+                    },
+                ))
+            }
+            Ok(DnsRecordType::AAAA) => {
+                let (i, bytes) = context("Address", map_res(take(len), <[u8; 16]>::try_from))(i)?;
+                let address = Ipv6Addr::from(bytes);
+
+                Ok((
+                    i,
+                    Self::AAAA {
+                        name,
+                        class,
+                        ttl,
+                        address,
+                    },
+                ))
+            }
+            // The data of those types can hold compressed names, which would point to nowhere once
+            // copied to another message
+            // https://datatracker.ietf.org/doc/html/rfc3597#section-4
+            Ok(DnsRecordType::MD)
+            | Ok(DnsRecordType::MF)
+            | Ok(DnsRecordType::MB)
+            | Ok(DnsRecordType::MG)
+            | Ok(DnsRecordType::MR)
+            | Ok(DnsRecordType::MINFO)
+            | Ok(DnsRecordType::MX) => Err(NomErr::Error(ContextError::add_context(
+                i,
+                "Unsupported record type",
+                ParseError::from_error_kind(i, ErrorKind::Switch),
+            ))),
+            _ => {
+                let (i, data) = context("Data", take(len))(i)?;
+
+                Ok((
+                    i,
+                    Self::Unknown {
+                        name,
+                        record_type,
+                        class,
+                        ttl,
+                        data: data.to_vec(),
+                    },
+                ))
             }
         }
     }
 
+    fn parse_opt(i: ParseInput, name: DnsName) -> ParseResult<Self> {
+        let (i, payload_size) = context("UDP payload size", be_u16)(i)?;
+        let (i, extended_rcode) = context("Extended RCODE", be_u8)(i)?;
+        let (i, version) = context("Version", be_u8)(i)?;
+        let (i, flags) = context("Flags", be_u16)(i)?;
+        let (i, len) = context("Data length", be_u16)(i)?;
+        let (i, data) = context("Options", take(len))(i)?;
+        let (_, options) = context("Options", all_consuming(many0(EdnsOption::parse)))(data)?;
+
+        Ok((
+            i,
+            Self::OPT {
+                name,
+                payload_size,
+                extended_rcode,
+                version,
+                dnssec_ok: flags & DNSSEC_OK_MASK != 0,
+                options,
+            },
+        ))
+    }
+
     pub fn serialize<'a, W: Write + 'a>(&'a self) -> impl SerializeFn<W> + 'a {
+        self.serialize_with_class_bits(0)
+    }
+
+    pub fn serialize_mdns<'a, W: Write + 'a>(
+        &'a self,
+        cache_flush: bool,
+    ) -> impl SerializeFn<W> + 'a {
+        self.serialize_with_class_bits(if cache_flush { CACHE_FLUSH_MASK } else { 0 })
+    }
+
+    fn serialize_with_class_bits<'a, W: Write + 'a>(
+        &'a self,
+        class_bits: u16,
+    ) -> impl SerializeFn<W> + 'a {
         use cf::{
             bytes::{be_u16, be_u32},
-            combinator::slice,
             sequence::tuple,
         };
 
-        match self {
-            DnsRecord::A {
-                ref name,
-                ref class,
-                ref ttl,
-                ref address,
-            } => tuple((
-                slice(name.to_bytes().unwrap()),
-                be_u16(DnsRecordType::A as u16),
-                be_u16(*class as u16),
-                be_u32(*ttl),
-                be_u16(address.octets().len() as u16),
-                slice(address.octets().to_vec()),
-            )),
-            DnsRecord::NS {
-                ref name,
-                ref class,
-                ref ttl,
-                ref name_server,
+        // OPT records repurpose the class and TTL fields for the EDNS metadata
+        let (class, ttl) = match self {
+            DnsRecord::A { class, ttl, .. }
+            | DnsRecord::NS { class, ttl, .. }
+            | DnsRecord::CNAME { class, ttl, .. }
+            | DnsRecord::DNAME { class, ttl, .. }
+            | DnsRecord::PTR { class, ttl, .. }
+            | DnsRecord::SOA { class, ttl, .. }
+            | DnsRecord::HINFO { class, ttl, .. }
+            | DnsRecord::TXT { class, ttl, .. }
+            | DnsRecord::AAAA { class, ttl, .. }
+            | DnsRecord::SRV { class, ttl, .. }
+            | DnsRecord::Unknown { class, ttl, .. } => (*class as u16 | class_bits, *ttl),
+            DnsRecord::OPT {
+                payload_size,
+                extended_rcode,
+                version,
+                dnssec_ok,
+                ..
             } => {
-                let name_bytes = name.to_bytes().unwrap();
-
-                tuple((
-                    slice(name.to_bytes().unwrap()),
-                    be_u16(DnsRecordType::NS as u16),
-                    be_u16(*class as u16),
-                    be_u32(*ttl),
-                    be_u16(name_bytes.len() as u16),
-                    slice(name_server.to_bytes().unwrap()),
-                ))
+                let flags = if *dnssec_ok { DNSSEC_OK_MASK } else { 0 };
+                (
+                    *payload_size,
+                    (*extended_rcode as u32) << 24 | (*version as u32) << 16 | flags as u32,
+                )
             }
-            DnsRecord::CNAME {
-                ref name,
-                ref class,
-                ref ttl,
-                ref canonical_name,
-            } => {
-                let name_bytes = name.to_bytes().unwrap();
-
-                tuple((
-                    slice(name.to_bytes().unwrap()),
-                    be_u16(DnsRecordType::NS as u16),
-                    be_u16(*class as u16),
-                    be_u32(*ttl),
-                    be_u16(name_bytes.len() as u16),
-                    slice(canonical_name.to_bytes().unwrap()),
-                ))
+        };
+
+        tuple((
+            self.get_name().serialize(),
+            be_u16(self.type_code()),
+            be_u16(class),
+            be_u32(ttl),
+            be_u16(self.data_len() as u16),
+            self.serialize_data(),
+        ))
+    }
+
+    fn serialize_data<'a, W: Write + 'a>(&'a self) -> impl SerializeFn<W> + 'a {
+        use cf::{
+            bytes::{be_u16, be_u32},
+            combinator::slice,
+            multi::all,
+            sequence::tuple,
+        };
+
+        move |out| match self {
+            DnsRecord::A { address, .. } => slice(address.octets())(out),
+            DnsRecord::AAAA { address, .. } => slice(address.octets())(out),
+            DnsRecord::NS { name_server, .. } => name_server.serialize()(out),
+            DnsRecord::CNAME { canonical_name, .. } => canonical_name.serialize()(out),
+            DnsRecord::DNAME { target, .. } => target.serialize()(out),
+            DnsRecord::PTR { pointer, .. } => pointer.serialize()(out),
+            DnsRecord::SOA {
+                mname,
+                rname,
+                serial,
+                refresh,
+                retry,
+                expire,
+                minimum,
+                ..
+            } => tuple((
+                mname.serialize(),
+                rname.serialize(),
+                be_u32(*serial),
+                be_u32(*refresh),
+                be_u32(*retry),
+                be_u32(*expire),
+                be_u32(*minimum),
+            ))(out),
+            DnsRecord::HINFO { cpu, os, .. } => tuple((
+                serialize_character_string(cpu),
+                serialize_character_string(os),
+            ))(out),
+            DnsRecord::TXT { data, .. } => {
+                all(data.iter().map(|string| serialize_character_string(string)))(out)
             }
-            DnsRecord::AAAA {
-                ref name,
-                ref class,
-                ref ttl,
-                ref address,
+            DnsRecord::SRV {
+                priority,
+                weight,
+                port,
+                target,
+                ..
             } => tuple((
-                slice(name.to_bytes().unwrap()),
-                be_u16(DnsRecordType::AAAA as u16),
-                be_u16(*class as u16),
-                be_u32(*ttl),
-                be_u16(address.octets().len() as u16),
-                slice(address.octets().to_vec()),
-            )),
+                be_u16(*priority),
+                be_u16(*weight),
+                be_u16(*port),
+                target.serialize(),
+            ))(out),
+            DnsRecord::Unknown { data, .. } => slice(data)(out),
+            DnsRecord::OPT { options, .. } => all(options.iter().map(EdnsOption::serialize))(out),
         }
     }
 
+    // Size of the record data on the wire
+    fn data_len(&self) -> usize {
+        match self {
+            DnsRecord::A { .. } => 4,
+            DnsRecord::AAAA { .. } => 16,
+            DnsRecord::NS { name_server, .. } => name_server.wire_len(),
+            DnsRecord::CNAME { canonical_name, .. } => canonical_name.wire_len(),
+            DnsRecord::DNAME { target, .. } => target.wire_len(),
+            DnsRecord::PTR { pointer, .. } => pointer.wire_len(),
+            DnsRecord::SOA { mname, rname, .. } => mname.wire_len() + rname.wire_len() + 20,
+            DnsRecord::HINFO { cpu, os, .. } => 2 + cpu.len() + os.len(),
+            DnsRecord::TXT { data, .. } => data.iter().map(|string| 1 + string.len()).sum(),
+            DnsRecord::SRV { target, .. } => 6 + target.wire_len(),
+            DnsRecord::Unknown { data, .. } => data.len(),
+            DnsRecord::OPT { options, .. } => options.iter().map(EdnsOption::wire_len).sum(),
+        }
+    }
+
+    // Size of the whole record on the wire, name and fixed fields included
+    pub fn wire_len(&self) -> usize {
+        self.get_name().wire_len() + 10 + self.data_len()
+    }
+
     pub fn to_bytes(&self) -> Result<Vec<u8>, GenError> {
         gen_simple(self.serialize(), Vec::new())
     }
 
+    pub fn to_bytes_mdns(&self, cache_flush: bool) -> Result<Vec<u8>, GenError> {
+        gen_simple(self.serialize_mdns(cache_flush), Vec::new())
+    }
+
     pub fn get_name(&self) -> &DnsName {
         match self {
             DnsRecord::A { ref name, .. } => name,
             DnsRecord::NS { ref name, .. } => name,
             DnsRecord::CNAME { ref name, .. } => name,
+            DnsRecord::DNAME { ref name, .. } => name,
+            DnsRecord::PTR { ref name, .. } => name,
+            DnsRecord::SOA { ref name, .. } => name,
+            DnsRecord::HINFO { ref name, .. } => name,
+            DnsRecord::TXT { ref name, .. } => name,
             DnsRecord::AAAA { ref name, .. } => name,
+            DnsRecord::SRV { ref name, .. } => name,
+            DnsRecord::Unknown { ref name, .. } => name,
+            DnsRecord::OPT { ref name, .. } => name,
+        }
+    }
+
+    // Value of the type field on the wire
+    pub fn type_code(&self) -> u16 {
+        let record_type = match self {
+            DnsRecord::A { .. } => DnsRecordType::A,
+            DnsRecord::NS { .. } => DnsRecordType::NS,
+            DnsRecord::CNAME { .. } => DnsRecordType::CNAME,
+            DnsRecord::DNAME { .. } => DnsRecordType::DNAME,
+            DnsRecord::PTR { .. } => DnsRecordType::PTR,
+            DnsRecord::SOA { .. } => DnsRecordType::SOA,
+            DnsRecord::HINFO { .. } => DnsRecordType::HINFO,
+            DnsRecord::TXT { .. } => DnsRecordType::TXT,
+            DnsRecord::AAAA { .. } => DnsRecordType::AAAA,
+            DnsRecord::SRV { .. } => DnsRecordType::SRV,
+            DnsRecord::OPT { .. } => DnsRecordType::OPT,
+            DnsRecord::Unknown { record_type, .. } => return *record_type,
+        };
+        record_type as u16
+    }
+
+    // None for the records of a type this parser doesn't know
+    pub fn get_type(&self) -> Option<DnsRecordType> {
+        DnsRecordType::try_from(self.type_code()).ok()
+    }
+
+    // OPT records repurpose the TTL field, so they don't have one
+    pub fn get_ttl(&self) -> Option<u32> {
+        match self {
+            DnsRecord::A { ttl, .. }
+            | DnsRecord::NS { ttl, .. }
+            | DnsRecord::CNAME { ttl, .. }
+            | DnsRecord::DNAME { ttl, .. }
+            | DnsRecord::PTR { ttl, .. }
+            | DnsRecord::SOA { ttl, .. }
+            | DnsRecord::HINFO { ttl, .. }
+            | DnsRecord::TXT { ttl, .. }
+            | DnsRecord::AAAA { ttl, .. }
+            | DnsRecord::SRV { ttl, .. }
+            | DnsRecord::Unknown { ttl, .. } => Some(*ttl),
+            DnsRecord::OPT { .. } => None,
+        }
+    }
+
+    pub fn set_ttl(&mut self, new_ttl: u32) {
+        match self {
+            DnsRecord::A { ttl, .. }
+            | DnsRecord::NS { ttl, .. }
+            | DnsRecord::CNAME { ttl, .. }
+            | DnsRecord::DNAME { ttl, .. }
+            | DnsRecord::PTR { ttl, .. }
+            | DnsRecord::SOA { ttl, .. }
+            | DnsRecord::HINFO { ttl, .. }
+            | DnsRecord::TXT { ttl, .. }
+            | DnsRecord::AAAA { ttl, .. }
+            | DnsRecord::SRV { ttl, .. }
+            | DnsRecord::Unknown { ttl, .. } => *ttl = new_ttl,
+            DnsRecord::OPT { .. } => {}
         }
     }
 
+    pub fn get_class(&self) -> Option<DnsClass> {
+        match self {
+            DnsRecord::A { class, .. }
+            | DnsRecord::NS { class, .. }
+            | DnsRecord::CNAME { class, .. }
+            | DnsRecord::DNAME { class, .. }
+            | DnsRecord::PTR { class, .. }
+            | DnsRecord::SOA { class, .. }
+            | DnsRecord::HINFO { class, .. }
+            | DnsRecord::TXT { class, .. }
+            | DnsRecord::AAAA { class, .. }
+            | DnsRecord::SRV { class, .. }
+            | DnsRecord::Unknown { class, .. } => Some(*class),
+            DnsRecord::OPT { .. } => None,
+        }
+    }
+
+    // Bytes allocated on the heap for the names and data, past the size of the record itself
+    pub fn heap_size(&self) -> usize {
+        let data = match self {
+            DnsRecord::A { .. } | DnsRecord::AAAA { .. } => 0,
+            DnsRecord::NS { name_server, .. } => name_server.heap_size(),
+            DnsRecord::CNAME { canonical_name, .. } => canonical_name.heap_size(),
+            DnsRecord::DNAME { target, .. } => target.heap_size(),
+            DnsRecord::PTR { pointer, .. } => pointer.heap_size(),
+            DnsRecord::SOA { mname, rname, .. } => mname.heap_size() + rname.heap_size(),
+            DnsRecord::HINFO { cpu, os, .. } => cpu.capacity() + os.capacity(),
+            DnsRecord::TXT { data, .. } => {
+                data.capacity() * std::mem::size_of::<Vec<u8>>()
+                    + data.iter().map(|s| s.capacity()).sum::<usize>()
+            }
+            DnsRecord::SRV { target, .. } => target.heap_size(),
+            DnsRecord::Unknown { data, .. } => data.capacity(),
+            DnsRecord::OPT { options, .. } => {
+                options.capacity() * std::mem::size_of::<EdnsOption>()
+                    + options.iter().map(|o| o.data.capacity()).sum::<usize>()
+            }
+        };
+        self.get_name().heap_size() + data
+    }
+
     pub fn get_address(&self) -> Option<IpAddr> {
         match self {
             DnsRecord::A { ref address, .. } => Some(IpAddr::V4(*address)),
@@ -211,3 +665,23 @@ impl DnsRecord {
         }
     }
 }
+
+// https://datatracker.ietf.org/doc/html/rfc1035#section-3.3
+fn serialize_character_string<'a, W: Write + 'a>(string: &'a [u8]) -> impl SerializeFn<W> + 'a {
+    use cf::{bytes::be_u8, combinator::slice, sequence::tuple};
+
+    move |out| {
+        let len = u8::try_from(string.len()).map_err(|_| {
+            GenError::IoError(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} bytes don't fit in a character string", string.len()),
+            ))
+        })?;
+        tuple((be_u8(len), slice(string)))(out)
+    }
+}
+
+fn parse_character_string(i: ParseInput) -> ParseResult<Vec<u8>> {
+    let (i, len) = be_u8(i)?;
+    map(take(len), |data: ParseInput| data.to_vec())(i)
+}
@@ -21,6 +21,8 @@ pub type ParseResult<'a, T> = IResult<ParseInput<'a>, T, ParseError<ParseInput<'
 pub enum ErrorKind {
     Nom(NomErrorKind),
     Context(&'static str),
+    // The message ended before a parser had all the bytes it needed
+    Incomplete,
 }
 
 pub struct ParseError<I> {
@@ -31,29 +33,40 @@ impl Error for ParseError<Vec<u8>> {}
 
 impl<'a> Debug for ParseError<ParseInput<'a>> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        writeln!(f, "dns-rs parsing error\n[Stacktrace]:")?;
-        for (input, kind) in self.errors.iter().rev() {
-            let prefix = match kind {
-                ErrorKind::Context(ctx) => format!("> in {}", ctx),
-                ErrorKind::Nom(err) => format!("* nom error: {:?}", err),
-            };
-
-            let maxlen = 40;
-            let input = if input.len() > maxlen {
-                &input[input.len() - maxlen..]
-            } else {
-                input
-            };
-
-            writeln!(f, "{:<30} {:02X?}", prefix, input)?;
-        }
-        Ok(())
+        write_stacktrace(f, self.errors.iter().map(|(input, kind)| (*input, kind)))
+    }
+}
+
+fn write_stacktrace<'a>(
+    f: &mut std::fmt::Formatter<'_>,
+    errors: impl DoubleEndedIterator<Item = (&'a [u8], &'a ErrorKind)>,
+) -> std::fmt::Result {
+    writeln!(f, "dns-rs parsing error\n[Stacktrace]:")?;
+    for (input, kind) in errors.rev() {
+        let prefix = match kind {
+            ErrorKind::Context(ctx) => format!("> in {}", ctx),
+            ErrorKind::Nom(err) => format!("* nom error: {:?}", err),
+            ErrorKind::Incomplete => "* incomplete input".to_string(),
+        };
+
+        let maxlen = 40;
+        let input = if input.len() > maxlen {
+            &input[input.len() - maxlen..]
+        } else {
+            input
+        };
+
+        writeln!(f, "{:<30} {:02X?}", prefix, input)?;
     }
+    Ok(())
 }
 
 impl Debug for ParseError<Vec<u8>> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{:?}", &self)
+        write_stacktrace(
+            f,
+            self.errors.iter().map(|(input, kind)| (&input[..], kind)),
+        )
     }
 }
 
@@ -122,7 +135,9 @@ impl From<NomErr<ParseError<&[u8]>>> for ParseError<Vec<u8>> {
                     .map(|(input, error_kind)| (input.to_vec(), error_kind))
                     .collect(),
             },
-            _ => unimplemented!(),
+            NomErr::Incomplete(_) => Self {
+                errors: vec![(Vec::new(), ErrorKind::Incomplete)],
+            },
         }
     }
 }
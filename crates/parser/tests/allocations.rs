@@ -0,0 +1,120 @@
+use parser::{
+    packet::*,
+    resources::{edns::*, name::*, query::*, record::*, DnsClass, DnsRecordType},
+};
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    cell::Cell,
+    net::{Ipv4Addr, Ipv6Addr},
+};
+
+// Counts the allocations made by the current thread, since tests run in parallel
+struct CountingAllocator;
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.with(|a| a.set(a.get() + 1));
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+fn allocations_during(f: impl FnOnce()) -> usize {
+    let before = ALLOCATIONS.with(Cell::get);
+    f();
+    ALLOCATIONS.with(Cell::get) - before
+}
+
+#[test]
+fn test_write_to_does_not_allocate() {
+    let name = DnsName::from("www.example.com");
+    let mut packet = DnsPacket::new();
+    packet.add_query(DnsQuery {
+        name: name.clone(),
+        record_type: DnsRecordType::A.into(),
+        class: DnsClass::IN.into(),
+    });
+    packet.add_answers(vec![
+        DnsRecord::A {
+            name: name.clone(),
+            class: DnsClass::IN,
+            ttl: 300,
+            address: Ipv4Addr::new(93, 184, 216, 34),
+        },
+        DnsRecord::AAAA {
+            name: name.clone(),
+            class: DnsClass::IN,
+            ttl: 300,
+            address: Ipv6Addr::LOCALHOST,
+        },
+        DnsRecord::CNAME {
+            name: name.clone(),
+            class: DnsClass::IN,
+            ttl: 300,
+            canonical_name: DnsName::from("example.com"),
+        },
+        DnsRecord::TXT {
+            name: name.clone(),
+            class: DnsClass::IN,
+            ttl: 300,
+            data: vec![b"v=spf1 -all".to_vec()],
+        },
+        DnsRecord::SRV {
+            name: name.clone(),
+            class: DnsClass::IN,
+            ttl: 300,
+            priority: 1,
+            weight: 2,
+            port: 443,
+            target: name.clone(),
+        },
+    ]);
+    packet.add_authority(DnsRecord::NS {
+        name: DnsName::from("example.com"),
+        class: DnsClass::IN,
+        ttl: 3600,
+        name_server: DnsName::from("a.iana-servers.net"),
+    });
+    packet.add_record(DnsRecord::OPT {
+        name: DnsName::root(),
+        payload_size: 1232,
+        extended_rcode: 0,
+        version: 0,
+        dnssec_ok: false,
+        options: vec![EdnsOption {
+            code: 10,
+            data: vec![1, 2, 3, 4, 5, 6, 7, 8],
+        }],
+    });
+
+    let mut buffer = [0; 1024];
+    let mut size = 0;
+    let mut wire_len = 0;
+    let allocations = allocations_during(|| {
+        wire_len = packet.wire_len();
+        size = packet.write_to(&mut buffer).unwrap();
+    });
+
+    assert_eq!(allocations, 0);
+    assert_eq!(size, wire_len);
+    assert_eq!(&buffer[..size], &packet.serialize().unwrap()[..]);
+
+    let allocations = allocations_during(|| {
+        size = packet
+            .write_with_limit(&mut buffer, DEFAULT_UDP_PAYLOAD_SIZE)
+            .unwrap();
+    });
+    assert_eq!(allocations, 0);
+    assert_eq!(size, wire_len);
+}
@@ -1,10 +1,13 @@
 use parser::{
     header::flags::*,
     packet::*,
-    resources::{name::*, query::*, record::*, DnsClass, DnsRecordType},
+    resources::{
+        edns::*, name::*, query::*, record::*, DnsClass, DnsRecordType, QueryClass, QueryType,
+    },
 };
 
 use std::{
+    convert::TryFrom,
     net::{Ipv4Addr, Ipv6Addr},
     str::FromStr,
 };
@@ -45,8 +48,8 @@ fn test_parse_ipv4_dns_query() {
     });
     expect.add_query(DnsQuery {
         name: DnsName::from("google.com"),
-        record_type: DnsRecordType::A,
-        class: DnsClass::IN,
+        record_type: DnsRecordType::A.into(),
+        class: DnsClass::IN.into(),
     });
 
     assert_eq!(expect, DnsPacket::parse(&bytes[..]).unwrap())
@@ -88,8 +91,8 @@ pub fn test_parse_ipv6_dns_query() {
     });
     expect.add_query(DnsQuery {
         name: DnsName::from("google.com"),
-        record_type: DnsRecordType::AAAA,
-        class: DnsClass::IN,
+        record_type: DnsRecordType::AAAA.into(),
+        class: DnsClass::IN.into(),
     });
 
     assert_eq!(expect, DnsPacket::parse(&bytes[..]).unwrap());
@@ -144,10 +147,10 @@ pub fn test_parse_ipv4_dns_response() {
     });
     expect.add_query(DnsQuery {
         name: DnsName::from("google.com"),
-        record_type: DnsRecordType::A,
-        class: DnsClass::IN,
+        record_type: DnsRecordType::A.into(),
+        class: DnsClass::IN.into(),
     });
-    expect.add_response(DnsRecord::A {
+    expect.add_answer(DnsRecord::A {
         name: DnsName::from("google.com"),
         class: DnsClass::IN,
         ttl: 128,
@@ -206,10 +209,10 @@ pub fn test_parse_ipv6_dns_response() {
     });
     expect.add_query(DnsQuery {
         name: DnsName::from("google.com"),
-        record_type: DnsRecordType::AAAA,
-        class: DnsClass::IN,
+        record_type: DnsRecordType::AAAA.into(),
+        class: DnsClass::IN.into(),
     });
-    expect.add_response(DnsRecord::AAAA {
+    expect.add_answer(DnsRecord::AAAA {
         name: DnsName::from("google.com"),
         class: DnsClass::IN,
         ttl: 30,
@@ -261,10 +264,10 @@ pub fn test_parse_dns_response_with_cname() {
     });
     expect.add_query(DnsQuery {
         name: DnsName::from("docs.sbonds.org"),
-        record_type: DnsRecordType::A,
-        class: DnsClass::IN,
+        record_type: DnsRecordType::A.into(),
+        class: DnsClass::IN.into(),
     });
-    expect.add_responses(vec![
+    expect.add_answers(vec![
         DnsRecord::CNAME {
             name: DnsName::from("docs.sbonds.org"),
             class: DnsClass::IN,
@@ -331,10 +334,10 @@ pub fn test_parse_dns_response_with_name_servers_and_additional_records() {
     });
     expect.add_query(DnsQuery {
         name: DnsName::from("a.root-servers.net"),
-        record_type: DnsRecordType::A,
-        class: DnsClass::IN,
+        record_type: DnsRecordType::A.into(),
+        class: DnsClass::IN.into(),
     });
-    expect.add_response(DnsRecord::A {
+    expect.add_answer(DnsRecord::A {
         name: DnsName::from("a.root-servers.net"),
         class: DnsClass::IN,
         ttl: 0x306D2F,
@@ -346,9 +349,7 @@ pub fn test_parse_dns_response_with_name_servers_and_additional_records() {
                 name: DnsName::from("root-servers.net"),
                 class: DnsClass::IN,
                 ttl: 0x162E,
-                name_server: DnsName::from(
-                    format!("{}.root-servers.net", char::from(c).to_string()).as_ref(),
-                ),
+                name_server: DnsName::from(format!("{}.root-servers.net", char::from(c)).as_ref()),
             })
             .collect(),
     );
@@ -455,16 +456,16 @@ fn test_serialize() {
     packet.add_queries(vec![
         DnsQuery {
             name: DnsName::from("a.root-servers.net"),
-            record_type: DnsRecordType::A,
-            class: DnsClass::IN,
+            record_type: DnsRecordType::A.into(),
+            class: DnsClass::IN.into(),
         },
         DnsQuery {
             name: DnsName::from("a.root-servers.net"),
-            record_type: DnsRecordType::AAAA,
-            class: DnsClass::IN,
+            record_type: DnsRecordType::AAAA.into(),
+            class: DnsClass::IN.into(),
         },
     ]);
-    packet.add_responses(vec![
+    packet.add_answers(vec![
         DnsRecord::A {
             name: DnsName::from("a.root-servers.net"),
             class: DnsClass::IN,
@@ -521,3 +522,945 @@ fn test_serialize() {
 
     assert_eq!(expect, packet.serialize().unwrap());
 }
+
+#[test]
+fn test_parse_dns_query_with_opt_record() {
+    #[rustfmt::skip]
+    let bytes: Vec<u8> = vec![
+        0x5A, 0x1F, // Transaction ID: 0x5A1F
+        0x01, 0x20, // Flags
+        0x00, 0x01, // Queries count: 1
+        0x00, 0x00, // Responses count: 0
+        0x00, 0x00, // Authority RRs: 0
+        0x00, 0x01, // Additional RRs: 1
+        0x06, 0x67, 0x6F, 0x6F, 0x67, 0x6C, 0x65, // google
+        0x03, 0x63, 0x6F, 0x6D, // com
+        0x00,       // Null terminated
+        0x00, 0x01, // Record Type: A
+        0x00, 0x01, // Class: IN
+        0x00,       // Name: <Root>
+        0x00, 0x29, // Record Type: OPT
+        0x10, 0x00, // UDP payload size: 4096
+        0x00,       // Extended RCODE: 0
+        0x00,       // EDNS version: 0
+        0x80, 0x00, // Flags: DNSSEC OK
+        0x00, 0x0C, // Data length: 12
+        0x00, 0x0A, // Option code: COOKIE
+        0x00, 0x08, // Option length: 8
+        0x2B, 0x6C, 0x1A, 0x8E, 0x4F, 0x02, 0x91, 0xD3, // Client cookie
+    ];
+
+    let mut expect = DnsPacket::new();
+    expect.header.transaction_id = 0x5A1F;
+    expect.header.set_flags(DnsHeaderFlags {
+        recdesired: true,
+        authenticated: true,
+        ..Default::default()
+    });
+    expect.add_query(DnsQuery {
+        name: DnsName::from("google.com"),
+        record_type: DnsRecordType::A.into(),
+        class: DnsClass::IN.into(),
+    });
+    expect.add_record(DnsRecord::OPT {
+        name: DnsName::root(),
+        payload_size: 4096,
+        extended_rcode: 0,
+        version: 0,
+        dnssec_ok: true,
+        options: vec![EdnsOption {
+            code: 0x0A,
+            data: vec![0x2B, 0x6C, 0x1A, 0x8E, 0x4F, 0x02, 0x91, 0xD3],
+        }],
+    });
+
+    let packet = DnsPacket::parse(&bytes[..]).unwrap();
+    assert_eq!(expect, packet);
+    assert_eq!(packet.max_payload_size(), 4096);
+    assert_eq!(bytes, packet.serialize().unwrap());
+}
+
+fn build_truncation_packet() -> DnsPacket {
+    let mut packet = DnsPacket::new();
+    packet.header.set_flags(DnsHeaderFlags {
+        response: true,
+        ..Default::default()
+    });
+    packet.add_query(DnsQuery {
+        name: DnsName::from("example.com"),
+        record_type: DnsRecordType::A.into(),
+        class: DnsClass::IN.into(),
+    });
+    packet.add_answers(
+        (0..10)
+            .map(|i| DnsRecord::A {
+                name: DnsName::from("example.com"),
+                class: DnsClass::IN,
+                ttl: 300,
+                address: Ipv4Addr::new(10, 0, 0, i),
+            })
+            .chain((0..10).map(|i| DnsRecord::A {
+                name: DnsName::from("www.example.com"),
+                class: DnsClass::IN,
+                ttl: 300,
+                address: Ipv4Addr::new(10, 0, 1, i),
+            }))
+            .collect(),
+    );
+    packet.add_authorities(
+        (b'a'..=b'd')
+            .map(|c| DnsRecord::NS {
+                name: DnsName::from("example.com"),
+                class: DnsClass::IN,
+                ttl: 300,
+                name_server: DnsName::from(format!("{}.iana-servers.net", c as char).as_ref()),
+            })
+            .collect(),
+    );
+    packet.add_records(vec![
+        DnsRecord::A {
+            name: DnsName::from("a.iana-servers.net"),
+            class: DnsClass::IN,
+            ttl: 300,
+            address: Ipv4Addr::new(199, 43, 135, 53),
+        },
+        DnsRecord::OPT {
+            name: DnsName::root(),
+            payload_size: 1232,
+            extended_rcode: 0,
+            version: 0,
+            dnssec_ok: false,
+            options: Vec::new(),
+        },
+    ]);
+    packet
+}
+
+#[test]
+fn test_serialize_with_limit_fits() {
+    let packet = build_truncation_packet();
+    let bytes = packet.serialize().unwrap();
+
+    assert_eq!(bytes, packet.serialize_with_limit(bytes.len()).unwrap());
+}
+
+#[test]
+fn test_serialize_with_limit_drops_additional_and_authorities() {
+    let packet = build_truncation_packet();
+    let full_len = packet.serialize().unwrap().len();
+
+    // Only the glue record (a.iana-servers.net A) is dropped, the OPT record stays
+    let bytes = packet.serialize_with_limit(full_len - 1).unwrap();
+    let truncated = DnsPacket::parse(&bytes[..]).unwrap();
+    assert!(!truncated.header.flags.truncated);
+    assert_eq!(truncated.answers().len(), 20);
+    assert_eq!(truncated.authorities().len(), 4);
+    assert_eq!(truncated.additional_records().len(), 1);
+    assert!(truncated.edns().is_some());
+
+    let answers_len = 12
+        + packet.queries()[0].to_bytes().unwrap().len()
+        + packet
+            .answers()
+            .iter()
+            .map(|r| r.to_bytes().unwrap().len())
+            .sum::<usize>()
+        + packet.edns().unwrap().to_bytes().unwrap().len();
+    let bytes = packet.serialize_with_limit(answers_len).unwrap();
+    let truncated = DnsPacket::parse(&bytes[..]).unwrap();
+    assert!(!truncated.header.flags.truncated);
+    assert_eq!(truncated.answers().len(), 20);
+    assert!(truncated.authorities().is_empty());
+    assert_eq!(truncated.additional_records().len(), 1);
+}
+
+#[test]
+fn test_serialize_with_limit_truncates_whole_rrsets() {
+    let packet = build_truncation_packet();
+
+    let bytes = packet.serialize_with_limit(512).unwrap();
+    assert!(bytes.len() <= 512);
+
+    let truncated = DnsPacket::parse(&bytes[..]).unwrap();
+    assert!(truncated.header.flags.truncated);
+    assert_eq!(truncated.queries(), packet.queries());
+    assert_eq!(truncated.answers(), &packet.answers()[..10]);
+    assert!(truncated.authorities().is_empty());
+    assert!(truncated.edns().is_some());
+
+    let bytes = packet.serialize_with_limit(100).unwrap();
+    let truncated = DnsPacket::parse(&bytes[..]).unwrap();
+    assert!(truncated.header.flags.truncated);
+    assert!(truncated.answers().is_empty());
+}
+
+#[test]
+fn test_serialize_with_limit_drops_oversized_opt() {
+    let mut packet = build_truncation_packet();
+    packet.add_edns_option(EdnsOption {
+        code: 0xFDE9,
+        data: vec![0; 600],
+    });
+
+    // The OPT record alone is over the limit, so only the header and the question are left
+    let bytes = packet.serialize_with_limit(512).unwrap();
+    assert!(bytes.len() <= 512);
+    let truncated = DnsPacket::parse(&bytes[..]).unwrap();
+    assert!(truncated.header.flags.truncated);
+    assert_eq!(truncated.queries(), packet.queries());
+    assert!(truncated.answers().is_empty());
+    assert!(truncated.additional_records().is_empty());
+}
+
+#[test]
+fn test_serialize_fails_past_section_counts() {
+    let mut packet = DnsPacket::new();
+    packet.add_answers(
+        (0..=u16::MAX as u32)
+            .map(|i| DnsRecord::A {
+                name: DnsName::root(),
+                class: DnsClass::IN,
+                ttl: 300,
+                address: Ipv4Addr::from(i),
+            })
+            .collect(),
+    );
+
+    assert!(packet.counts().is_err());
+    assert!(packet.serialize().is_err());
+    assert!(packet.write_to(&mut [0; 512]).is_err());
+}
+
+#[test]
+fn test_parse_any_query() {
+    #[rustfmt::skip]
+    let bytes: Vec<u8> = vec![
+        0x3C, 0x91, // Transaction ID: 0x3C91
+        0x01, 0x00, // Flags
+        0x00, 0x02, // Queries count: 2
+        0x00, 0x00, // Responses count: 0
+        0x00, 0x00, // Authority RRs: 0
+        0x00, 0x00, // Additional RRs: 0
+        0x06, 0x67, 0x6F, 0x6F, 0x67, 0x6C, 0x65, // google
+        0x03, 0x63, 0x6F, 0x6D, // com
+        0x00,       // Null terminated
+        0x00, 0xFF, // Record Type: ANY
+        0x00, 0x01, // Class: IN
+        0xC0, 0x0C, // Compressed offset: 12
+        0x00, 0xFC, // Record Type: AXFR
+        0x00, 0xFF, // Class: ANY
+    ];
+
+    let mut expect = DnsPacket::new();
+    expect.header.transaction_id = 0x3C91;
+    expect.header.set_flags(DnsHeaderFlags {
+        recdesired: true,
+        ..Default::default()
+    });
+    expect.add_queries(vec![
+        DnsQuery {
+            name: DnsName::from("google.com"),
+            record_type: QueryType::ANY,
+            class: DnsClass::IN.into(),
+        },
+        DnsQuery {
+            name: DnsName::from("google.com"),
+            record_type: QueryType::AXFR,
+            class: QueryClass::ANY,
+        },
+    ]);
+
+    assert_eq!(expect, DnsPacket::parse(&bytes[..]).unwrap());
+}
+
+#[test]
+fn test_query_meta_values() {
+    for (value, query_type) in [
+        (0xFB, QueryType::IXFR),
+        (0xFC, QueryType::AXFR),
+        (0xFD, QueryType::MAILB),
+        (0xFE, QueryType::MAILA),
+        (0xFF, QueryType::ANY),
+        (0x1C, QueryType::Record(DnsRecordType::AAAA)),
+    ] {
+        assert_eq!(QueryType::try_from(value), Ok(query_type));
+        assert_eq!(u16::from(query_type), value);
+    }
+    assert_eq!(QueryType::try_from(0xFA), Err(0xFA));
+
+    for (value, class) in [
+        (0xFE, QueryClass::NONE),
+        (0xFF, QueryClass::ANY),
+        (0x03, QueryClass::Class(DnsClass::CH)),
+    ] {
+        assert_eq!(QueryClass::try_from(value), Ok(class));
+        assert_eq!(u16::from(class), value);
+    }
+}
+
+#[test]
+fn test_hinfo_record() {
+    #[rustfmt::skip]
+    let bytes: Vec<u8> = vec![
+        0x00, 0x01, // Transaction ID: 0x0001
+        0x81, 0x80, // Flags
+        0x00, 0x00, // Queries count: 0
+        0x00, 0x01, // Responses count: 1
+        0x00, 0x00, // Authority RRs: 0
+        0x00, 0x00, // Additional RRs: 0
+        0x07, 0x65, 0x78, 0x61, 0x6D, 0x70, 0x6C, 0x65, // example
+        0x03, 0x63, 0x6F, 0x6D, // com
+        0x00,       // Null terminated
+        0x00, 0x0D, // Record Type: HINFO
+        0x00, 0x01, // Class: IN
+        0x00, 0x00, 0x0E, 0xCD, // TTL: 3789
+        0x00, 0x09, // Data length: 9
+        0x07, 0x52, 0x46, 0x43, 0x38, 0x34, 0x38, 0x32, // CPU: "RFC8482"
+        0x00,       // OS: ""
+    ];
+
+    let mut expect = DnsPacket::new();
+    expect.header.transaction_id = 0x0001;
+    expect.header.set_flags(DnsHeaderFlags {
+        response: true,
+        recdesired: true,
+        recavail: true,
+        ..Default::default()
+    });
+    expect.add_answer(DnsRecord::HINFO {
+        name: DnsName::from("example.com"),
+        class: DnsClass::IN,
+        ttl: 3789,
+        cpu: b"RFC8482".to_vec(),
+        os: Vec::new(),
+    });
+
+    assert_eq!(expect, DnsPacket::parse(&bytes[..]).unwrap());
+    assert_eq!(bytes, expect.serialize().unwrap());
+}
+
+#[test]
+fn test_malformed_hinfo_record() {
+    #[rustfmt::skip]
+    let bytes: Vec<u8> = vec![
+        0x00, 0x01, // Transaction ID: 0x0001
+        0x81, 0x80, // Flags
+        0x00, 0x00, // Queries count: 0
+        0x00, 0x01, // Responses count: 1
+        0x00, 0x00, // Authority RRs: 0
+        0x00, 0x00, // Additional RRs: 0
+        0x00,       // Name: <Root>
+        0x00, 0x0D, // Record Type: HINFO
+        0x00, 0x01, // Class: IN
+        0x00, 0x00, 0x0E, 0xCD, // TTL: 3789
+        0x00, 0x04, // Data length: 4
+        0x07, 0x52, 0x46, 0x43, // CPU: 7 bytes declared, 3 given
+        0x00, 0x00, // Bytes past the data length
+        0x00, 0x00, 0x00, 0x00,
+    ];
+
+    assert!(DnsPacket::parse(&bytes[..]).is_err());
+    // The message ends in the middle of the CPU string
+    assert!(DnsPacket::parse(&bytes[..bytes.len() - 6]).is_err());
+}
+
+#[test]
+fn test_malformed_txt_record() {
+    #[rustfmt::skip]
+    let bytes: Vec<u8> = vec![
+        0x00, 0x01, // Transaction ID: 0x0001
+        0x01, 0x00, // Flags
+        0x00, 0x01, // Queries count: 1
+        0x00, 0x00, // Responses count: 0
+        0x00, 0x00, // Authority RRs: 0
+        0x00, 0x01, // Additional RRs: 1
+        0x07, 0x65, 0x78, 0x61, 0x6D, 0x70, 0x6C, 0x65, // example
+        0x03, 0x63, 0x6F, 0x6D, // com
+        0x00,       // Null terminated
+        0x00, 0x01, // Type: A
+        0x00, 0x01, // Class: IN
+        // Additional record
+        0x00,       // Name: <Root>
+        0x00, 0x10, // Type: TXT
+        0x00, 0x01, // Class: IN
+        0x00, 0x00, 0x00, 0x3C, // Time to live: 60
+        0x00, 0x02, // Data length: 2
+        0x04, 0x78, // Text: 4 bytes declared, 1 given
+    ];
+
+    assert!(DnsPacket::parse(&bytes[..]).is_err());
+}
+
+#[test]
+fn test_display() {
+    let mut packet = DnsPacket::new();
+    packet.header.transaction_id = 0x8223;
+    packet.header.set_flags(DnsHeaderFlags {
+        response: true,
+        recdesired: true,
+        recavail: true,
+        ..Default::default()
+    });
+    packet.add_query(DnsQuery {
+        name: DnsName::from("docs.sbonds.org"),
+        record_type: DnsRecordType::A.into(),
+        class: DnsClass::IN.into(),
+    });
+    packet.add_answers(vec![
+        DnsRecord::CNAME {
+            name: DnsName::from("docs.sbonds.org"),
+            class: DnsClass::IN,
+            ttl: 0x1C0F,
+            canonical_name: DnsName::from("ghs.google.com"),
+        },
+        DnsRecord::A {
+            name: DnsName::from("ghs.google.com"),
+            class: DnsClass::IN,
+            ttl: 0x79,
+            address: Ipv4Addr::from_str("172.217.13.115").unwrap(),
+        },
+    ]);
+    packet.add_record(DnsRecord::OPT {
+        name: DnsName::root(),
+        payload_size: 1232,
+        extended_rcode: 0,
+        version: 0,
+        dnssec_ok: true,
+        options: vec![EdnsOption {
+            code: 0x0A,
+            data: vec![0x2B, 0x6C, 0x1A, 0x8E, 0x4F, 0x02, 0x91, 0xD3],
+        }],
+    });
+
+    let expect = "\
+;; ->>HEADER<<- opcode: QUERY, status: NOERROR, id: 33315
+;; flags: qr rd ra; QUERY: 1, ANSWER: 2, AUTHORITY: 0, ADDITIONAL: 1
+
+;; OPT PSEUDOSECTION:
+; EDNS: version: 0, flags: do; udp: 1232
+; COOKIE: 2b6c1a8e4f0291d3
+
+;; QUESTION SECTION:
+;docs.sbonds.org.\t\tIN\tA
+
+;; ANSWER SECTION:
+docs.sbonds.org.\t7183\tIN\tCNAME\tghs.google.com.
+ghs.google.com.\t121\tIN\tA\t172.217.13.115
+";
+
+    assert_eq!(expect, packet.to_string());
+    assert_eq!(".", DnsName::root().to_string());
+}
+
+#[test]
+fn test_dissect() {
+    #[rustfmt::skip]
+    let bytes: Vec<u8> = vec![
+        0xD0, 0xAE, 0x81, 0x80, 0x00, 0x01, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, // Header
+        0x06, 0x67, 0x6F, 0x6F, 0x67, 0x6C, 0x65, 0x03, 0x63, 0x6F, 0x6D, 0x00, // google.com
+        0x00, 0x01, 0x00, 0x01, // A IN
+        0xC0, 0x0C, // Compressed offset: 12
+        0x00, 0x01, 0x00, 0x01, // A IN
+        0x00, 0x00, 0x00, 0x80, // TTL: 128 seconds
+        0x00, 0x04, // Data length: 4
+        0xAC, 0xD9, 0x0D, 0xAE, // Address: 172.217.13.174
+    ];
+
+    let fields = parser::dissect(&bytes);
+    assert_eq!(fields.len(), 3);
+    assert!(fields.iter().all(|f| !f.is_malformed()));
+
+    let header = &fields[0];
+    assert_eq!(header.children[0].name, "Transaction ID");
+    assert_eq!(header.children[0].meaning, "0xd0ae");
+    assert_eq!(
+        header.children[1].children[1].name,
+        ".000 0... .... .... = Opcode"
+    );
+
+    let answer = &fields[2].children[0];
+    assert_eq!(answer.offset, 28);
+    assert_eq!(answer.data, bytes[28..]);
+    assert_eq!(answer.children[0].meaning, "google.com.");
+    assert_eq!(answer.children[0].children[0].name, "Compression pointer");
+
+    let address = answer.children.last().unwrap();
+    assert_eq!((address.offset, address.data.len()), (40, 4));
+    assert_eq!(address.meaning, "172.217.13.174");
+}
+
+#[test]
+fn test_dissect_malformed() {
+    #[rustfmt::skip]
+    let bytes: Vec<u8> = vec![
+        0x00, 0x01, 0x81, 0x80, 0x00, 0x00, 0x00, 0x03, 0x00, 0x00, 0x00, 0x00, // Header
+        0x03, 0x77, 0x77, 0x77, 0x00, // www
+        0x00, 0xFA, 0x00, 0x01, 0x00, 0x00, 0x00, 0x10, // Unknown type 250, IN, TTL 16
+        0x00, 0x02, 0xAB, 0xCD, // Data length: 2
+        0xC0, 0x0C, // Compressed offset: 12
+        0x00, 0x01, 0x00, 0x01, 0x00, 0x00, 0x00, 0x10, // A IN, TTL 16
+        0x00, 0x02, 0x01, 0x02, // Invalid address length
+        0xC0, 0x2B, // Compression pointer to itself
+    ];
+
+    let fields = parser::dissect(&bytes);
+    let answers = &fields[1].children;
+    assert_eq!(answers.len(), 3);
+
+    // Unknown types are skipped over using the data length
+    assert!(!answers[0].is_malformed());
+    assert_eq!(answers[0].children[1].meaning, "Unknown (250)");
+
+    assert!(answers[1].is_malformed());
+    assert_eq!(answers[1].children[0].meaning, "www.");
+
+    assert!(answers[2].is_malformed());
+    let name = &answers[2].children[0];
+    assert_eq!(
+        name.children.last().unwrap().meaning,
+        "Compression pointers form a loop"
+    );
+}
+
+#[test]
+fn test_serialize_derives_counts_from_sections() {
+    let mut packet = build_truncation_packet();
+    packet
+        .answers_mut()
+        .retain(|r| r.get_name() == &DnsName::from("www.example.com"));
+    packet.authorities_mut().remove(0);
+    packet.additional_records_mut().clear();
+
+    let bytes = packet.serialize().unwrap();
+    #[rustfmt::skip]
+    assert_eq!(bytes[4..12], [
+        0x00, 0x01, // Queries count: 1
+        0x00, 0x0A, // Answers count: 10
+        0x00, 0x03, // Authority RRs: 3
+        0x00, 0x00, // Additional RRs: 0
+    ]);
+    assert_eq!(packet, DnsPacket::parse(&bytes[..]).unwrap());
+}
+
+#[test]
+fn test_parse_checks_declared_counts() {
+    #[rustfmt::skip]
+    let bytes: Vec<u8> = vec![
+        0xD0, 0xAE, // Transaction ID: 0xD0AE
+        0x81, 0x80, // Flags
+        0x00, 0x01, // Queries count: 1
+        0x00, 0x02, // Answers count: 2, only one is present
+        0x00, 0x00, // Authority RRs: 0
+        0x00, 0x00, // Additional RRs: 0
+        0x06, 0x67, 0x6F, 0x6F, 0x67, 0x6C, 0x65, // google
+        0x03, 0x63, 0x6F, 0x6D, // com
+        0x00,       // Null terminated
+        0x00, 0x01, // Record Type: A
+        0x00, 0x01, // Class: IN
+        0xC0, 0x0C, // Compressed offset: 12
+        0x00, 0x01, // Record Type: A
+        0x00, 0x01, // Class: IN
+        0x00, 0x00, 0x00, 0x80, // TTL: 128 seconds
+        0x00, 0x04, // Data length: 4
+        0xAC, 0xD9, 0x0D, 0xAE, // Address: 172.217.13.174
+    ];
+    assert!(DnsPacket::parse(&bytes[..]).is_err());
+
+    let mut bytes = bytes;
+    bytes[7] = 0x01;
+    assert!(DnsPacket::parse(&bytes[..]).is_ok());
+
+    // Trailing bytes after the declared entries are ignored
+    let expect = DnsPacket::parse(&bytes[..]).unwrap();
+    bytes.extend_from_slice(&[0x00, 0x00, 0x00]);
+    assert_eq!(expect, DnsPacket::parse(&bytes[..]).unwrap());
+}
+
+#[test]
+fn test_name_case() {
+    let name = DnsName::from("www-1.Example.com");
+    let randomized = name.randomize_case();
+    assert!(name.eq_ignore_case(&randomized));
+    assert!(randomized.eq_ignore_case(&DnsName::from("WWW-1.EXAMPLE.COM")));
+    assert_eq!(name.to_lowercase(), randomized.to_lowercase());
+    assert!(!name.eq_ignore_case(&DnsName::from("www-1.example.org")));
+    assert!(!name.eq_ignore_case(&DnsName::from("www-1.example")));
+}
+
+#[test]
+fn test_name_rebase() {
+    let owner = DnsName::from("Example.com");
+    let target = DnsName::from("example.net");
+    let name = DnsName::from("www.sub.example.COM");
+    assert!(name.is_subdomain_of(&owner));
+    assert!(owner.is_subdomain_of(&owner));
+    assert!(name.is_subdomain_of(&DnsName::root()));
+    assert!(!owner.is_subdomain_of(&name));
+    assert!(!DnsName::from("www.notexample.com").is_subdomain_of(&owner));
+
+    assert_eq!(
+        Some(DnsName::from("www.sub.example.net")),
+        name.rebase(&owner, &target)
+    );
+    assert_eq!(None, owner.rebase(&owner, &target));
+    assert_eq!(
+        None,
+        DnsName::from("www.example.org").rebase(&owner, &target)
+    );
+
+    let prefix = [
+        "a".repeat(63),
+        "a".repeat(63),
+        "a".repeat(63),
+        "a".repeat(40),
+    ];
+    let long = DnsName::from(&*format!("{}.example.com", prefix.join(".")));
+    let longer = DnsName::from("a-much-longer-target.example.net");
+    assert_eq!(None, long.rebase(&owner, &longer));
+    assert!(long.rebase(&owner, &target).is_some());
+}
+
+#[test]
+fn test_is_response_to() {
+    let mut request = DnsPacket::new();
+    request.add_query(DnsQuery {
+        name: DnsName::from("google.com"),
+        record_type: DnsRecordType::A.into(),
+        class: DnsClass::IN.into(),
+    });
+
+    let mut response = request.reply();
+    assert!(!response.is_response_to(&request));
+    response.header.flags.response = true;
+    assert!(response.is_response_to(&request));
+
+    let mut other_id = response.clone();
+    other_id.header.transaction_id = request.header.transaction_id.wrapping_add(1);
+    assert!(!other_id.is_response_to(&request));
+
+    let mut other_type = request.clone();
+    other_type.queries_mut()[0].record_type = DnsRecordType::AAAA.into();
+    let mut other_type = other_type.reply();
+    other_type.header.flags.response = true;
+    assert!(!other_type.is_response_to(&request));
+
+    let mut other_case = response.clone();
+    other_case.queries_mut()[0].name = DnsName::from("GOOGLE.com");
+    assert!(other_case.is_response_to(&request));
+
+    let mut other_name = response.clone();
+    other_name.queries_mut()[0].name = DnsName::from("google.org");
+    assert!(!other_name.is_response_to(&request));
+}
+
+#[test]
+fn test_reply() {
+    let mut request = DnsPacket::new();
+    request.header.transaction_id = 0x5A1F;
+    request.add_query(DnsQuery {
+        name: DnsName::from("google.com"),
+        record_type: DnsRecordType::A.into(),
+        class: DnsClass::IN.into(),
+    });
+    request.add_record(DnsRecord::OPT {
+        name: DnsName::root(),
+        payload_size: 4096,
+        extended_rcode: 0,
+        version: 0,
+        dnssec_ok: true,
+        options: vec![EdnsOption {
+            code: 0x0A,
+            data: vec![0x2B, 0x6C, 0x1A, 0x8E, 0x4F, 0x02, 0x91, 0xD3],
+        }],
+    });
+
+    let response = request.reply();
+    assert_eq!(response.header.transaction_id, 0x5A1F);
+    assert_eq!(response.queries(), request.queries());
+    assert!(response.answers().is_empty());
+    assert_eq!(
+        response.edns(),
+        Some(&DnsRecord::OPT {
+            name: DnsName::root(),
+            payload_size: 512,
+            extended_rcode: 0,
+            version: 0,
+            dnssec_ok: false,
+            options: Vec::new(),
+        })
+    );
+
+    let mut response = response;
+    response.add_edns_option(EdnsOption::extended_error(EXTENDED_ERROR_STALE_ANSWER, ""));
+    match response.edns() {
+        Some(DnsRecord::OPT { options, .. }) => assert_eq!(
+            options,
+            &vec![EdnsOption {
+                code: 15,
+                data: vec![0x00, 0x03],
+            }]
+        ),
+        _ => panic!("The response has no OPT record"),
+    }
+
+    // Requesters that don't use EDNS can't be sent options
+    let mut response = DnsPacket::new();
+    response.add_edns_option(EdnsOption::extended_error(EXTENDED_ERROR_STALE_ANSWER, ""));
+    assert_eq!(response.edns(), None);
+}
+
+#[test]
+fn test_parse_mdns_service_response() {
+    #[rustfmt::skip]
+    let bytes: Vec<u8> = vec![
+        0x00, 0x00, // Transaction ID: 0x0000
+        0x84, 0x00, // Flags: Response, Authoritative
+        0x00, 0x01, // Queries count: 1
+        0x00, 0x02, // Responses count: 2
+        0x00, 0x00, // Authority RRs: 0
+        0x00, 0x01, // Additional RRs: 1
+        // Query
+        0x05, 0x5F, 0x68, 0x74, 0x74, 0x70, // "_http"
+        0x04, 0x5F, 0x74, 0x63, 0x70, // "_tcp"
+        0x05, 0x6C, 0x6F, 0x63, 0x61, 0x6C, // "local"
+        0x00, // Null terminated
+        0x00, 0x0C, // Type: PTR
+        0x80, 0x01, // Class: IN, unicast response requested
+        // Answer
+        0xC0, 0x0C, // Name: _http._tcp.local
+        0x00, 0x0C, // Type: PTR
+        0x00, 0x01, // Class: IN
+        0x00, 0x00, 0x11, 0x94, // Time to live: 4500
+        0x00, 0x05, // Data length: 5
+        0x02, 0x77, 0x65, // "we"
+        0xC0, 0x0C, // Name: _http._tcp.local
+        // Answer
+        0xC0, 0x2E, // Name: we._http._tcp.local
+        0x00, 0x21, // Type: SRV
+        0x80, 0x01, // Class: IN, cache flush
+        0x00, 0x00, 0x00, 0x78, // Time to live: 120
+        0x00, 0x0D, // Data length: 13
+        0x00, 0x00, // Priority: 0
+        0x00, 0x00, // Weight: 0
+        0x1F, 0x90, // Port: 8080
+        0x04, 0x68, 0x6F, 0x73, 0x74, // "host"
+        0xC0, 0x17, // Name: local
+        // Additional record
+        0xC0, 0x2E, // Name: we._http._tcp.local
+        0x00, 0x10, // Type: TXT
+        0x80, 0x01, // Class: IN, cache flush
+        0x00, 0x00, 0x11, 0x94, // Time to live: 4500
+        0x00, 0x0B, // Data length: 11
+        0x06, 0x70, 0x61, 0x74, 0x68, 0x3D, 0x2F, // "path=/"
+        0x03, 0x76, 0x3D, 0x31, // "v=1"
+    ];
+
+    let packet = parser::mdns::MdnsPacket::parse(&bytes[..]).unwrap();
+    assert!(packet.queries[0].unicast_response);
+    assert_eq!(
+        packet.queries[0].query.record_type,
+        DnsRecordType::PTR.into()
+    );
+    assert_eq!(
+        packet.answers[0].record,
+        DnsRecord::PTR {
+            name: DnsName::from("_http._tcp.local"),
+            class: DnsClass::IN,
+            ttl: 4500,
+            pointer: DnsName::from("we._http._tcp.local"),
+        }
+    );
+    assert!(!packet.answers[0].cache_flush);
+    assert_eq!(
+        packet.answers[1].record,
+        DnsRecord::SRV {
+            name: DnsName::from("we._http._tcp.local"),
+            class: DnsClass::IN,
+            ttl: 120,
+            priority: 0,
+            weight: 0,
+            port: 8080,
+            target: DnsName::from("host.local"),
+        }
+    );
+    assert!(packet.answers[1].cache_flush);
+    assert_eq!(
+        packet.additional_records[0].record,
+        DnsRecord::TXT {
+            name: DnsName::from("we._http._tcp.local"),
+            class: DnsClass::IN,
+            ttl: 4500,
+            data: vec![b"path=/".to_vec(), b"v=1".to_vec()],
+        }
+    );
+    assert!(packet.additional_records[0].cache_flush);
+
+    // Plain DNS parsing doesn't know about the mDNS class bits
+    assert!(DnsPacket::parse(&bytes[..]).is_err());
+
+    let serialized = packet.serialize().unwrap();
+    assert_eq!(
+        parser::mdns::MdnsPacket::parse(&serialized[..]).unwrap(),
+        packet
+    );
+}
+
+#[test]
+fn test_record_round_trip() {
+    let records = vec![
+        DnsRecord::PTR {
+            name: DnsName::from("10.1.168.192.in-addr.arpa"),
+            class: DnsClass::IN,
+            ttl: 120,
+            pointer: DnsName::from("host.local"),
+        },
+        DnsRecord::TXT {
+            name: DnsName::from("we._http._tcp.local"),
+            class: DnsClass::IN,
+            ttl: 4500,
+            data: vec![Vec::new()],
+        },
+        DnsRecord::SRV {
+            name: DnsName::from("_sip._udp.example.com"),
+            class: DnsClass::IN,
+            ttl: 3600,
+            priority: 10,
+            weight: 60,
+            port: 5060,
+            target: DnsName::from("sip.example.com"),
+        },
+        DnsRecord::SOA {
+            name: DnsName::from("example.com"),
+            class: DnsClass::IN,
+            ttl: 3600,
+            mname: DnsName::from("ns1.example.com"),
+            rname: DnsName::from("hostmaster.example.com"),
+            serial: 2021010101,
+            refresh: 7200,
+            retry: 3600,
+            expire: 1209600,
+            minimum: 300,
+        },
+        DnsRecord::DNAME {
+            name: DnsName::from("example.com"),
+            class: DnsClass::IN,
+            ttl: 3600,
+            target: DnsName::from("example.net"),
+        },
+    ];
+
+    for record in records {
+        let bytes = record.to_bytes().unwrap();
+        assert_eq!(DnsRecord::parse(&bytes)(&bytes).unwrap().1, record);
+
+        let bytes = record.to_bytes_mdns(true).unwrap();
+        assert_eq!(
+            DnsRecord::parse_mdns(&bytes)(&bytes).unwrap().1,
+            (record, true)
+        );
+    }
+}
+
+#[test]
+fn test_parse_unsupported_record_type() {
+    #[rustfmt::skip]
+    let bytes: Vec<u8> = vec![
+        0x00, // Name: <Root>
+        0x00, 0x0F, // Type: MX
+        0x00, 0x01, // Class: IN
+        0x00, 0x00, 0x00, 0x3C, // Time to live: 60
+        0x00, 0x03, // Data length: 3
+        0x00, 0x0A, 0x00, // Preference: 10, Exchange: <Root>
+    ];
+
+    assert!(DnsRecord::parse(&bytes)(&bytes).is_err());
+}
+
+#[test]
+fn test_parse_unknown_record_type() {
+    #[rustfmt::skip]
+    let bytes: Vec<u8> = vec![
+        0x00, // Name: <Root>
+        0x00, 0x41, // Type: HTTPS
+        0x00, 0x01, // Class: IN
+        0x00, 0x00, 0x00, 0x3C, // Time to live: 60
+        0x00, 0x03, // Data length: 3
+        0x00, 0x01, 0x00, // Priority: 1, Target: <Root>
+    ];
+
+    let record = DnsRecord::Unknown {
+        name: DnsName::root(),
+        record_type: 0x41,
+        class: DnsClass::IN,
+        ttl: 60,
+        data: vec![0x00, 0x01, 0x00],
+    };
+    assert_eq!(DnsRecord::parse(&bytes)(&bytes).unwrap().1, record);
+    assert_eq!(record.to_bytes().unwrap(), bytes);
+    assert_eq!(record.get_type(), None);
+    assert_eq!(record.to_string(), ".\t60\tIN\tTYPE65\t\\# 3 000100");
+}
+
+#[test]
+fn test_parse_unknown_query_type() {
+    let mut packet = DnsPacket::new();
+    packet.add_query(DnsQuery {
+        name: DnsName::from("example.com"),
+        record_type: QueryType::Unknown(0x41),
+        class: DnsClass::IN.into(),
+    });
+    let bytes = packet.serialize().unwrap();
+
+    assert_eq!(DnsPacket::parse(&bytes[..]).unwrap(), packet);
+    assert_eq!(
+        packet.queries()[0].to_string(),
+        ";example.com.\t\tIN\tTYPE65"
+    );
+}
+
+#[test]
+fn test_serialize_fails_past_character_string_size() {
+    let record = DnsRecord::TXT {
+        name: DnsName::from("example.com"),
+        class: DnsClass::IN,
+        ttl: 60,
+        data: vec![vec![b'x'; 256]],
+    };
+    assert!(record.to_bytes().is_err());
+
+    let record = DnsRecord::HINFO {
+        name: DnsName::from("example.com"),
+        class: DnsClass::IN,
+        ttl: 60,
+        cpu: vec![b'x'; 255],
+        os: vec![b'x'; 256],
+    };
+    assert!(record.to_bytes().is_err());
+}
+
+#[test]
+fn test_write_to() {
+    let packet = build_truncation_packet();
+    let serialized = packet.serialize().unwrap();
+    assert_eq!(packet.wire_len(), serialized.len());
+
+    let mut buffer = vec![0; serialized.len()];
+    assert_eq!(packet.write_to(&mut buffer).unwrap(), serialized.len());
+    assert_eq!(buffer, serialized);
+
+    let mut buffer = vec![0; serialized.len() - 1];
+    assert!(matches!(
+        packet.write_to(&mut buffer),
+        Err(cookie_factory::GenError::BufferTooSmall(_))
+    ));
+
+    // The buffer size caps the payload size advertised by the requester
+    let mut buffer = [0; DEFAULT_UDP_PAYLOAD_SIZE];
+    let size = packet.write_with_limit(&mut buffer, 1232).unwrap();
+    assert_eq!(
+        &buffer[..size],
+        &packet
+            .serialize_with_limit(DEFAULT_UDP_PAYLOAD_SIZE)
+            .unwrap()[..]
+    );
+}
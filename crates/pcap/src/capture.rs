@@ -0,0 +1,46 @@
+use super::writer::PcapWriter;
+
+use std::fs::File;
+use std::io::{BufWriter, Result};
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+// Records the traffic of a running server, flushed after every message
+// so the capture can be followed while it's being written
+pub struct Capture {
+    writer: Mutex<PcapWriter<BufWriter<File>>>,
+}
+
+impl Capture {
+    pub fn create(path: impl AsRef<Path>) -> Result<Self> {
+        let file = BufWriter::new(File::create(path)?);
+        Ok(Self {
+            writer: Mutex::new(PcapWriter::new(file)?),
+        })
+    }
+
+    pub fn record(
+        &self,
+        source: SocketAddr,
+        destination: SocketAddr,
+        payload: &[u8],
+    ) -> Result<()> {
+        let mut writer = self.writer.lock().unwrap();
+        writer.write_udp(SystemTime::now(), source, destination, payload)?;
+        writer.flush()
+    }
+
+    // Records a message exchanged over TCP as segments of its connection
+    pub fn record_tcp(
+        &self,
+        source: SocketAddr,
+        destination: SocketAddr,
+        message: &[u8],
+    ) -> Result<()> {
+        let mut writer = self.writer.lock().unwrap();
+        writer.write_tcp(SystemTime::now(), source, destination, message)?;
+        writer.flush()
+    }
+}
@@ -0,0 +1,17 @@
+mod capture;
+mod network;
+mod reader;
+mod writer;
+
+pub use capture::Capture;
+pub use reader::{read_capture, CapturedPacket, Transport};
+pub use writer::PcapWriter;
+
+use std::io::{Error, ErrorKind};
+
+// DNS traffic is recognized by either side using the well known port
+pub const DNS_PORT: u16 = 53;
+
+fn invalid_data(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
@@ -0,0 +1,178 @@
+use std::convert::TryInto;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+// https://www.tcpdump.org/linktypes.html
+const LINKTYPE_NULL: u32 = 0;
+const LINKTYPE_ETHERNET: u32 = 1;
+pub(crate) const LINKTYPE_RAW: u32 = 101;
+const LINKTYPE_LOOP: u32 = 108;
+const LINKTYPE_LINUX_SLL: u32 = 113;
+const LINKTYPE_IPV4: u32 = 228;
+const LINKTYPE_IPV6: u32 = 229;
+const LINKTYPE_LINUX_SLL2: u32 = 276;
+
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const ETHERTYPE_IPV6: u16 = 0x86DD;
+const ETHERTYPE_VLAN: u16 = 0x8100;
+const ETHERTYPE_QINQ: u16 = 0x88A8;
+
+pub(crate) const PROTOCOL_TCP: u8 = 6;
+pub(crate) const PROTOCOL_UDP: u8 = 17;
+
+const TCP_FIN: u8 = 0x01;
+const TCP_SYN: u8 = 0x02;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Segment<'a> {
+    Udp {
+        payload: &'a [u8],
+    },
+    Tcp {
+        sequence: u32,
+        syn: bool,
+        fin: bool,
+        payload: &'a [u8],
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Datagram<'a> {
+    pub source: SocketAddr,
+    pub destination: SocketAddr,
+    pub segment: Segment<'a>,
+}
+
+fn be_u16_at(bytes: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_be_bytes(
+        bytes.get(offset..offset + 2)?.try_into().ok()?,
+    ))
+}
+
+fn be_u32_at(bytes: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(
+        bytes.get(offset..offset + 4)?.try_into().ok()?,
+    ))
+}
+
+// Returns the UDP or TCP datagram carried in a captured frame, if any
+pub(crate) fn decode(link_type: u32, frame: &[u8]) -> Option<Datagram<'_>> {
+    let ip = match link_type {
+        // The 4 bytes address family is in the capturing host's byte order,
+        // so the IP version is used instead
+        LINKTYPE_NULL | LINKTYPE_LOOP => frame.get(4..)?,
+        LINKTYPE_RAW | LINKTYPE_IPV4 | LINKTYPE_IPV6 => frame,
+        LINKTYPE_ETHERNET => {
+            let mut offset = 12;
+            let mut ethertype = be_u16_at(frame, offset)?;
+            while ethertype == ETHERTYPE_VLAN || ethertype == ETHERTYPE_QINQ {
+                offset += 4;
+                ethertype = be_u16_at(frame, offset)?;
+            }
+            if ethertype != ETHERTYPE_IPV4 && ethertype != ETHERTYPE_IPV6 {
+                return None;
+            }
+            frame.get(offset + 2..)?
+        }
+        LINKTYPE_LINUX_SLL => frame.get(16..)?,
+        LINKTYPE_LINUX_SLL2 => frame.get(20..)?,
+        _ => return None,
+    };
+
+    let (source, destination, protocol, payload) = match ip.first()? >> 4 {
+        4 => decode_ipv4(ip)?,
+        6 => decode_ipv6(ip)?,
+        _ => return None,
+    };
+
+    let source_port = be_u16_at(payload, 0)?;
+    let destination_port = be_u16_at(payload, 2)?;
+    let segment = match protocol {
+        PROTOCOL_UDP => {
+            let length = be_u16_at(payload, 4)? as usize;
+            Segment::Udp {
+                payload: payload.get(8..length.max(8).min(payload.len()))?,
+            }
+        }
+        PROTOCOL_TCP => {
+            let header_length = (*payload.get(12)? >> 4) as usize * 4;
+            let flags = *payload.get(13)?;
+            Segment::Tcp {
+                sequence: be_u32_at(payload, 4)?,
+                syn: flags & TCP_SYN != 0,
+                fin: flags & TCP_FIN != 0,
+                payload: payload.get(header_length..)?,
+            }
+        }
+        _ => return None,
+    };
+
+    Some(Datagram {
+        source: SocketAddr::new(source, source_port),
+        destination: SocketAddr::new(destination, destination_port),
+        segment,
+    })
+}
+
+fn decode_ipv4(ip: &[u8]) -> Option<(IpAddr, IpAddr, u8, &[u8])> {
+    let header_length = (ip.first()? & 0x0F) as usize * 4;
+    let total_length = be_u16_at(ip, 2)? as usize;
+    // Fragments can't be decoded on their own
+    let fragment = be_u16_at(ip, 6)?;
+    if fragment & 0x3FFF != 0 {
+        return None;
+    }
+
+    let source: [u8; 4] = ip.get(12..16)?.try_into().ok()?;
+    let destination: [u8; 4] = ip.get(16..20)?.try_into().ok()?;
+    // Ethernet frames may be padded past the end of the IP datagram
+    let end = total_length.min(ip.len());
+
+    Some((
+        IpAddr::V4(Ipv4Addr::from(source)),
+        IpAddr::V4(Ipv4Addr::from(destination)),
+        *ip.get(9)?,
+        ip.get(header_length..end)?,
+    ))
+}
+
+fn decode_ipv6(ip: &[u8]) -> Option<(IpAddr, IpAddr, u8, &[u8])> {
+    let payload_length = be_u16_at(ip, 4)? as usize;
+    let source: [u8; 16] = ip.get(8..24)?.try_into().ok()?;
+    let destination: [u8; 16] = ip.get(24..40)?.try_into().ok()?;
+    let mut payload = ip.get(40..(40 + payload_length).min(ip.len()))?;
+
+    // Hop-by-hop, routing and destination options headers are skipped,
+    // fragments can't be decoded on their own
+    let mut next_header = *ip.get(6)?;
+    while let 0 | 43 | 60 = next_header {
+        next_header = *payload.first()?;
+        let length = (*payload.get(1)? as usize + 1) * 8;
+        payload = payload.get(length..)?;
+    }
+
+    Some((
+        IpAddr::V6(Ipv6Addr::from(source)),
+        IpAddr::V6(Ipv6Addr::from(destination)),
+        next_header,
+        payload,
+    ))
+}
+
+// https://datatracker.ietf.org/doc/html/rfc1071
+pub(crate) fn checksum(chunks: &[&[u8]]) -> u16 {
+    let mut sum: u32 = 0;
+    let mut odd: Option<u8> = None;
+    for byte in chunks.iter().flat_map(|chunk| chunk.iter()) {
+        match odd.take() {
+            Some(high) => sum += u16::from_be_bytes([high, *byte]) as u32,
+            None => odd = Some(*byte),
+        }
+    }
+    if let Some(high) = odd {
+        sum += u16::from_be_bytes([high, 0]) as u32;
+    }
+    while sum > 0xFFFF {
+        sum = (sum & 0xFFFF) + (sum >> 16);
+    }
+    !(sum as u16)
+}
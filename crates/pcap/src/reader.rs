@@ -0,0 +1,360 @@
+use super::network::{self, Datagram, Segment};
+use super::{invalid_data, DNS_PORT};
+use parser::packet::DnsPacket;
+
+use std::collections::{BTreeMap, HashMap};
+use std::convert::TryInto;
+use std::io::{Read, Result};
+use std::net::SocketAddr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// https://datatracker.ietf.org/doc/html/draft-ietf-opsawg-pcap
+const PCAP_MAGIC_MICROS: u32 = 0xA1B2_C3D4;
+const PCAP_MAGIC_NANOS: u32 = 0xA1B2_3C4D;
+const PCAP_HEADER_SIZE: usize = 24;
+const PCAP_RECORD_HEADER_SIZE: usize = 16;
+
+// https://datatracker.ietf.org/doc/html/draft-ietf-opsawg-pcapng
+const PCAPNG_SECTION_HEADER: u32 = 0x0A0D_0D0A;
+const PCAPNG_BYTE_ORDER_MAGIC: u32 = 0x1A2B_3C4D;
+const PCAPNG_INTERFACE_DESCRIPTION: u32 = 0x0000_0001;
+const PCAPNG_SIMPLE_PACKET: u32 = 0x0000_0003;
+const PCAPNG_ENHANCED_PACKET: u32 = 0x0000_0006;
+const PCAPNG_OPTION_END: u16 = 0;
+const PCAPNG_OPTION_TSRESOL: u16 = 9;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transport {
+    Udp,
+    Tcp,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CapturedPacket {
+    pub timestamp: SystemTime,
+    pub source: SocketAddr,
+    pub destination: SocketAddr,
+    pub transport: Transport,
+    pub packet: DnsPacket,
+}
+
+struct Frame<'a> {
+    timestamp: SystemTime,
+    link_type: u32,
+    data: &'a [u8],
+}
+
+#[derive(Clone, Copy)]
+struct Endianness {
+    little: bool,
+}
+
+impl Endianness {
+    fn u16_at(self, bytes: &[u8], offset: usize) -> Result<u16> {
+        let field = bytes
+            .get(offset..offset + 2)
+            .ok_or_else(|| invalid_data("Truncated capture"))?
+            .try_into()
+            .unwrap();
+        Ok(match self.little {
+            true => u16::from_le_bytes(field),
+            false => u16::from_be_bytes(field),
+        })
+    }
+
+    fn u32_at(self, bytes: &[u8], offset: usize) -> Result<u32> {
+        let field = bytes
+            .get(offset..offset + 4)
+            .ok_or_else(|| invalid_data("Truncated capture"))?
+            .try_into()
+            .unwrap();
+        Ok(match self.little {
+            true => u32::from_le_bytes(field),
+            false => u32::from_be_bytes(field),
+        })
+    }
+}
+
+fn slice(bytes: &[u8], start: usize, length: usize) -> Result<&[u8]> {
+    start
+        .checked_add(length)
+        .and_then(|end| bytes.get(start..end))
+        .ok_or_else(|| invalid_data("Truncated capture"))
+}
+
+// Reads every DNS message exchanged over port 53 in a pcap or pcapng capture
+pub fn read_capture(mut input: impl Read) -> Result<Vec<CapturedPacket>> {
+    let mut bytes = Vec::new();
+    input.read_to_end(&mut bytes)?;
+
+    let magic = Endianness { little: true }.u32_at(&bytes, 0)?;
+    let frames = match magic {
+        PCAPNG_SECTION_HEADER => pcapng_frames(&bytes)?,
+        _ => pcap_frames(&bytes)?,
+    };
+
+    let mut streams = HashMap::new();
+    let mut packets = Vec::new();
+    for frame in frames {
+        let datagram = match network::decode(frame.link_type, frame.data) {
+            Some(datagram) => datagram,
+            None => continue,
+        };
+        if datagram.source.port() != DNS_PORT && datagram.destination.port() != DNS_PORT {
+            continue;
+        }
+
+        let Datagram {
+            source,
+            destination,
+            segment,
+        } = datagram;
+        let (transport, messages) = match segment {
+            Segment::Udp { payload } => (Transport::Udp, vec![payload.to_vec()]),
+            Segment::Tcp {
+                sequence,
+                syn,
+                fin,
+                payload,
+            } => {
+                let stream = streams
+                    .entry((source, destination))
+                    .or_insert_with(TcpStream::default);
+                let messages = stream.receive(sequence, syn, payload);
+                if fin {
+                    streams.remove(&(source, destination));
+                }
+                (Transport::Tcp, messages)
+            }
+        };
+
+        // Payloads that aren't valid DNS messages are skipped
+        packets.extend(messages.iter().filter_map(|message| {
+            DnsPacket::parse(message).ok().map(|packet| CapturedPacket {
+                timestamp: frame.timestamp,
+                source,
+                destination,
+                transport,
+                packet,
+            })
+        }));
+    }
+
+    Ok(packets)
+}
+
+fn pcap_frames(bytes: &[u8]) -> Result<Vec<Frame<'_>>> {
+    let (endianness, nanos) = match bytes.get(..4) {
+        Some(magic) => match u32::from_le_bytes(magic.try_into().unwrap()) {
+            PCAP_MAGIC_MICROS => (Endianness { little: true }, false),
+            PCAP_MAGIC_NANOS => (Endianness { little: true }, true),
+            magic if magic.swap_bytes() == PCAP_MAGIC_MICROS => {
+                (Endianness { little: false }, false)
+            }
+            magic if magic.swap_bytes() == PCAP_MAGIC_NANOS => (Endianness { little: false }, true),
+            _ => return Err(invalid_data("Not a pcap or pcapng capture")),
+        },
+        None => return Err(invalid_data("Truncated capture")),
+    };
+
+    // The link type shares its field with the FCS length in the upper bits
+    let link_type = endianness.u32_at(bytes, 20)? & 0x0FFF_FFFF;
+
+    let mut frames = Vec::new();
+    let mut offset = PCAP_HEADER_SIZE;
+    while offset < bytes.len() {
+        let seconds = endianness.u32_at(bytes, offset)?;
+        let fraction = endianness.u32_at(bytes, offset + 4)?;
+        let captured_length = endianness.u32_at(bytes, offset + 8)? as usize;
+        let data = slice(bytes, offset + PCAP_RECORD_HEADER_SIZE, captured_length)?;
+
+        let fraction = match nanos {
+            true => Duration::from_nanos(fraction as u64),
+            false => Duration::from_micros(fraction as u64),
+        };
+        frames.push(Frame {
+            timestamp: UNIX_EPOCH + Duration::from_secs(seconds as u64) + fraction,
+            link_type,
+            data,
+        });
+        offset += PCAP_RECORD_HEADER_SIZE + captured_length;
+    }
+
+    Ok(frames)
+}
+
+struct Interface {
+    link_type: u32,
+    // Timestamp units per second
+    resolution: u64,
+}
+
+fn pcapng_frames(bytes: &[u8]) -> Result<Vec<Frame<'_>>> {
+    let mut endianness = Endianness { little: true };
+    let mut interfaces: Vec<Interface> = Vec::new();
+    let mut frames = Vec::new();
+
+    let mut offset = 0;
+    while offset < bytes.len() {
+        let block_type = endianness.u32_at(bytes, offset)?;
+        if block_type == PCAPNG_SECTION_HEADER {
+            // Every section can use its own byte order and interfaces
+            let magic = Endianness { little: true }.u32_at(bytes, offset + 8)?;
+            endianness = match magic {
+                PCAPNG_BYTE_ORDER_MAGIC => Endianness { little: true },
+                _ if magic.swap_bytes() == PCAPNG_BYTE_ORDER_MAGIC => Endianness { little: false },
+                _ => return Err(invalid_data("Invalid pcapng byte order magic")),
+            };
+            interfaces.clear();
+        }
+
+        let block_length = endianness.u32_at(bytes, offset + 4)? as usize;
+        if block_length < 12 || !block_length.is_multiple_of(4) {
+            return Err(invalid_data("Invalid pcapng block length"));
+        }
+        let block = slice(bytes, offset, block_length)?;
+        // The body sits between the type and length fields and the trailing length
+        let body = &block[8..block_length - 4];
+
+        match block_type {
+            PCAPNG_INTERFACE_DESCRIPTION => interfaces.push(Interface {
+                link_type: endianness.u16_at(body, 0)? as u32,
+                resolution: interface_resolution(endianness, body.get(8..).unwrap_or(&[]))?,
+            }),
+            PCAPNG_ENHANCED_PACKET => {
+                let interface = interfaces
+                    .get(endianness.u32_at(body, 0)? as usize)
+                    .ok_or_else(|| invalid_data("Unknown pcapng interface"))?;
+                let high = endianness.u32_at(body, 4)? as u64;
+                let low = endianness.u32_at(body, 8)? as u64;
+                let captured_length = endianness.u32_at(body, 12)? as usize;
+
+                frames.push(Frame {
+                    timestamp: timestamp((high << 32) | low, interface.resolution),
+                    link_type: interface.link_type,
+                    data: slice(body, 20, captured_length)?,
+                });
+            }
+            PCAPNG_SIMPLE_PACKET => {
+                let interface = interfaces
+                    .first()
+                    .ok_or_else(|| invalid_data("Unknown pcapng interface"))?;
+                let original_length = endianness.u32_at(body, 0)? as usize;
+                let data = &body[4..];
+
+                // Simple packets have no timestamp
+                frames.push(Frame {
+                    timestamp: UNIX_EPOCH,
+                    link_type: interface.link_type,
+                    data: &data[..original_length.min(data.len())],
+                });
+            }
+            _ => {}
+        }
+
+        offset += block_length;
+    }
+
+    Ok(frames)
+}
+
+fn interface_resolution(endianness: Endianness, mut options: &[u8]) -> Result<u64> {
+    // Microseconds unless the if_tsresol option says otherwise
+    let mut resolution = 1_000_000;
+    while options.len() >= 4 {
+        let code = endianness.u16_at(options, 0)?;
+        let length = endianness.u16_at(options, 2)? as usize;
+        if code == PCAPNG_OPTION_END {
+            break;
+        }
+
+        let value = slice(options, 4, length)?;
+        if code == PCAPNG_OPTION_TSRESOL && length == 1 {
+            // The top bit selects a power of 2 instead of a power of 10
+            let exponent = (value[0] & 0x7F) as u32;
+            let base: u64 = if value[0] & 0x80 == 0 { 10 } else { 2 };
+            resolution = base
+                .checked_pow(exponent)
+                .ok_or_else(|| invalid_data("Invalid pcapng timestamp resolution"))?;
+        }
+
+        let padded = 4 + length.div_ceil(4) * 4;
+        options = options.get(padded..).unwrap_or(&[]);
+    }
+
+    Ok(resolution)
+}
+
+fn timestamp(units: u64, resolution: u64) -> SystemTime {
+    let seconds = units / resolution;
+    let nanos = (units % resolution) as u128 * 1_000_000_000 / resolution as u128;
+    UNIX_EPOCH + Duration::from_secs(seconds) + Duration::from_nanos(nanos as u64)
+}
+
+// One direction of a TCP connection, reassembled into length prefixed DNS messages
+// https://datatracker.ietf.org/doc/html/rfc1035#section-4.2.2
+#[derive(Default)]
+struct TcpStream {
+    next_sequence: Option<u32>,
+    pending: BTreeMap<u32, Vec<u8>>,
+    buffer: Vec<u8>,
+}
+
+impl TcpStream {
+    fn receive(&mut self, sequence: u32, syn: bool, payload: &[u8]) -> Vec<Vec<u8>> {
+        if syn {
+            // The SYN consumes a sequence number
+            *self = Self::default();
+            self.next_sequence = Some(sequence.wrapping_add(1));
+            return Vec::new();
+        }
+        if payload.is_empty() {
+            return Vec::new();
+        }
+
+        // Without the handshake, the stream starts at the first segment seen
+        let next = *self.next_sequence.get_or_insert(sequence);
+        if (sequence.wrapping_sub(next) as i32) > 0 {
+            // Ahead of the stream, kept until the gap is filled
+            self.pending.insert(sequence, payload.to_vec());
+            return Vec::new();
+        }
+
+        self.append(sequence, payload);
+        while let Some(next) = self.next_sequence {
+            let ready = self
+                .pending
+                .keys()
+                .copied()
+                .find(|&sequence| (sequence.wrapping_sub(next) as i32) <= 0);
+            match ready {
+                Some(sequence) => {
+                    let payload = self.pending.remove(&sequence).unwrap();
+                    self.append(sequence, &payload);
+                }
+                None => break,
+            }
+        }
+
+        let mut messages = Vec::new();
+        while self.buffer.len() >= 2 {
+            let length = u16::from_be_bytes([self.buffer[0], self.buffer[1]]) as usize;
+            if self.buffer.len() < 2 + length {
+                break;
+            }
+            messages.push(self.buffer[2..2 + length].to_vec());
+            self.buffer.drain(..2 + length);
+        }
+        messages
+    }
+
+    // Appends the part of a segment that wasn't already received
+    fn append(&mut self, sequence: u32, payload: &[u8]) {
+        let next = self.next_sequence.unwrap_or(sequence);
+        let overlap = next.wrapping_sub(sequence) as usize;
+        if overlap < payload.len() {
+            self.buffer.extend_from_slice(&payload[overlap..]);
+            self.next_sequence = Some(sequence.wrapping_add(payload.len() as u32));
+        }
+    }
+}
@@ -0,0 +1,251 @@
+use super::network::{checksum, LINKTYPE_RAW, PROTOCOL_TCP, PROTOCOL_UDP};
+use parser::packet::DnsPacket;
+
+use std::collections::HashMap;
+use std::io::{Error, ErrorKind, Result, Write};
+use std::net::{IpAddr, SocketAddr};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const PCAP_MAGIC_MICROS: u32 = 0xA1B2_C3D4;
+const PCAP_VERSION: (u16, u16) = (2, 4);
+const SNAPLEN: u32 = 65535;
+const HOP_LIMIT: u8 = 64;
+const UDP_HEADER_SIZE: usize = 8;
+const TCP_HEADER_SIZE: usize = 20;
+// Segments are cut so that they fit in an IPv6 packet within the snapshot length
+const MAX_TCP_PAYLOAD: usize = SNAPLEN as usize - 40 - TCP_HEADER_SIZE;
+// Past this many, the sequence numbers of the streams are forgotten and each stream starts over
+// with a new handshake
+const MAX_TCP_STREAMS: usize = 1024;
+
+const TCP_SYN: u8 = 0x02;
+const TCP_PSH: u8 = 0x08;
+const TCP_ACK: u8 = 0x10;
+
+// Writes DNS messages as UDP datagrams or TCP segments in a classic pcap capture without link
+// layer headers
+pub struct PcapWriter<W: Write> {
+    out: W,
+    // Next sequence number of each direction of the TCP streams written
+    streams: HashMap<(SocketAddr, SocketAddr), u32>,
+}
+
+impl<W: Write> PcapWriter<W> {
+    pub fn new(mut out: W) -> Result<Self> {
+        out.write_all(&PCAP_MAGIC_MICROS.to_le_bytes())?;
+        out.write_all(&PCAP_VERSION.0.to_le_bytes())?;
+        out.write_all(&PCAP_VERSION.1.to_le_bytes())?;
+        // Timezone offset and timestamp accuracy, both always 0
+        out.write_all(&[0; 8])?;
+        out.write_all(&SNAPLEN.to_le_bytes())?;
+        out.write_all(&LINKTYPE_RAW.to_le_bytes())?;
+
+        Ok(Self {
+            out,
+            streams: HashMap::new(),
+        })
+    }
+
+    pub fn write_packet(
+        &mut self,
+        timestamp: SystemTime,
+        source: SocketAddr,
+        destination: SocketAddr,
+        packet: &DnsPacket,
+    ) -> Result<()> {
+        let bytes = packet
+            .serialize()
+            .map_err(|e| Error::new(ErrorKind::InvalidInput, e.to_string()))?;
+        self.write_udp(timestamp, source, destination, &bytes)
+    }
+
+    pub fn write_udp(
+        &mut self,
+        timestamp: SystemTime,
+        source: SocketAddr,
+        destination: SocketAddr,
+        payload: &[u8],
+    ) -> Result<()> {
+        let udp_length = UDP_HEADER_SIZE + payload.len();
+        if udp_length > u16::MAX as usize {
+            return Err(Error::new(ErrorKind::InvalidInput, "Payload too large"));
+        }
+
+        let mut udp = Vec::with_capacity(udp_length);
+        udp.extend_from_slice(&source.port().to_be_bytes());
+        udp.extend_from_slice(&destination.port().to_be_bytes());
+        udp.extend_from_slice(&(udp_length as u16).to_be_bytes());
+        udp.extend_from_slice(&[0, 0]);
+        udp.extend_from_slice(payload);
+
+        self.write_ip(timestamp, source, destination, PROTOCOL_UDP, udp)
+    }
+
+    // Writes a message sent over TCP, prefixed by its length, as the next segments of its stream.
+    // The first message of a stream is preceded by a SYN so that readers know where it starts
+    // https://datatracker.ietf.org/doc/html/rfc7766#section-8
+    pub fn write_tcp(
+        &mut self,
+        timestamp: SystemTime,
+        source: SocketAddr,
+        destination: SocketAddr,
+        message: &[u8],
+    ) -> Result<()> {
+        if message.len() > u16::MAX as usize {
+            return Err(Error::new(ErrorKind::InvalidInput, "Message too large"));
+        }
+        let mut stream = (message.len() as u16).to_be_bytes().to_vec();
+        stream.extend_from_slice(message);
+
+        if !self.streams.contains_key(&(source, destination)) {
+            if self.streams.len() >= MAX_TCP_STREAMS {
+                self.streams.clear();
+            }
+            let sequence = 0;
+            self.write_segment(timestamp, source, destination, sequence, TCP_SYN, &[])?;
+            // The SYN consumes a sequence number
+            self.streams
+                .insert((source, destination), sequence.wrapping_add(1));
+        }
+
+        for chunk in stream.chunks(MAX_TCP_PAYLOAD) {
+            let sequence = self.streams[&(source, destination)];
+            let flags = TCP_PSH | TCP_ACK;
+            self.write_segment(timestamp, source, destination, sequence, flags, chunk)?;
+            self.streams.insert(
+                (source, destination),
+                sequence.wrapping_add(chunk.len() as u32),
+            );
+        }
+        Ok(())
+    }
+
+    // https://datatracker.ietf.org/doc/html/rfc9293#section-3.1
+    fn write_segment(
+        &mut self,
+        timestamp: SystemTime,
+        source: SocketAddr,
+        destination: SocketAddr,
+        sequence: u32,
+        flags: u8,
+        payload: &[u8],
+    ) -> Result<()> {
+        // Acknowledges what the other side sent so far
+        let acknowledgment = self
+            .streams
+            .get(&(destination, source))
+            .copied()
+            .unwrap_or(0);
+
+        let mut tcp = Vec::with_capacity(TCP_HEADER_SIZE + payload.len());
+        tcp.extend_from_slice(&source.port().to_be_bytes());
+        tcp.extend_from_slice(&destination.port().to_be_bytes());
+        tcp.extend_from_slice(&sequence.to_be_bytes());
+        tcp.extend_from_slice(&acknowledgment.to_be_bytes());
+        // Header length in words, flags, window, checksum and urgent pointer
+        tcp.extend_from_slice(&[(TCP_HEADER_SIZE as u8 / 4) << 4, flags, 0xFF, 0xFF]);
+        tcp.extend_from_slice(&[0, 0, 0, 0]);
+        tcp.extend_from_slice(payload);
+
+        self.write_ip(timestamp, source, destination, PROTOCOL_TCP, tcp)
+    }
+
+    // Wraps the UDP datagram or TCP segment in an IP packet, filling its checksum
+    fn write_ip(
+        &mut self,
+        timestamp: SystemTime,
+        source: SocketAddr,
+        destination: SocketAddr,
+        protocol: u8,
+        mut transport: Vec<u8>,
+    ) -> Result<()> {
+        let length = transport.len();
+        let datagram = match (source.ip(), destination.ip()) {
+            // https://datatracker.ietf.org/doc/html/rfc791#section-3.1
+            (IpAddr::V4(source), IpAddr::V4(destination)) => {
+                // The total length includes the header, unlike the IPv6 payload length
+                if 20 + length > u16::MAX as usize {
+                    return Err(Error::new(
+                        ErrorKind::InvalidInput,
+                        "Payload too large for IPv4",
+                    ));
+                }
+
+                let pseudo_header = [
+                    &source.octets()[..],
+                    &destination.octets()[..],
+                    &[0, protocol],
+                    &(length as u16).to_be_bytes(),
+                ]
+                .concat();
+                set_checksum(&mut transport, protocol, &pseudo_header);
+
+                let mut header = vec![0x45, 0];
+                header.extend_from_slice(&((20 + length) as u16).to_be_bytes());
+                // Identification, then the don't fragment flag
+                header.extend_from_slice(&[0, 0, 0x40, 0, HOP_LIMIT, protocol, 0, 0]);
+                header.extend_from_slice(&source.octets());
+                header.extend_from_slice(&destination.octets());
+                let sum = checksum(&[&header]);
+                header[10..12].copy_from_slice(&sum.to_be_bytes());
+
+                [header, transport].concat()
+            }
+            // https://datatracker.ietf.org/doc/html/rfc8200#section-3
+            (IpAddr::V6(source), IpAddr::V6(destination)) => {
+                let pseudo_header = [
+                    &source.octets()[..],
+                    &destination.octets()[..],
+                    &(length as u32).to_be_bytes(),
+                    &[0, 0, 0, protocol],
+                ]
+                .concat();
+                set_checksum(&mut transport, protocol, &pseudo_header);
+
+                let mut header = vec![0x60, 0, 0, 0];
+                header.extend_from_slice(&(length as u16).to_be_bytes());
+                header.extend_from_slice(&[protocol, HOP_LIMIT]);
+                header.extend_from_slice(&source.octets());
+                header.extend_from_slice(&destination.octets());
+
+                [header, transport].concat()
+            }
+            _ => {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    "Source and destination use different address families",
+                ))
+            }
+        };
+
+        let since_epoch = timestamp.duration_since(UNIX_EPOCH).unwrap_or_default();
+        self.out
+            .write_all(&(since_epoch.as_secs() as u32).to_le_bytes())?;
+        self.out
+            .write_all(&since_epoch.subsec_micros().to_le_bytes())?;
+        self.out.write_all(&(datagram.len() as u32).to_le_bytes())?;
+        self.out.write_all(&(datagram.len() as u32).to_le_bytes())?;
+        self.out.write_all(&datagram)
+    }
+
+    pub fn flush(&mut self) -> Result<()> {
+        self.out.flush()
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+}
+
+// https://datatracker.ietf.org/doc/html/rfc768
+// https://datatracker.ietf.org/doc/html/rfc9293#section-3.1
+fn set_checksum(transport: &mut [u8], protocol: u8, pseudo_header: &[u8]) {
+    let sum = checksum(&[pseudo_header, transport]);
+    if protocol == PROTOCOL_UDP {
+        // A computed checksum of 0 is sent as all ones, 0 meaning no checksum
+        let sum = if sum == 0 { 0xFFFF } else { sum };
+        transport[6..8].copy_from_slice(&sum.to_be_bytes());
+    } else {
+        transport[16..18].copy_from_slice(&sum.to_be_bytes());
+    }
+}
@@ -0,0 +1,241 @@
+use parser::{
+    header::flags::DnsHeaderFlags,
+    packet::DnsPacket,
+    resources::{name::DnsName, query::DnsQuery, record::DnsRecord, DnsClass, DnsRecordType},
+};
+use pcap::{read_capture, PcapWriter, Transport};
+
+use std::{
+    io::ErrorKind,
+    net::{Ipv4Addr, SocketAddr},
+    time::{Duration, UNIX_EPOCH},
+};
+
+fn query() -> DnsPacket {
+    let mut packet = DnsPacket::new();
+    packet.header.transaction_id = 0x1337;
+    packet.add_query(DnsQuery {
+        name: DnsName::from("example.com"),
+        record_type: DnsRecordType::A.into(),
+        class: DnsClass::IN.into(),
+    });
+    packet
+}
+
+fn response() -> DnsPacket {
+    let mut packet = query().reply();
+    packet.header.set_flags(DnsHeaderFlags {
+        response: true,
+        ..Default::default()
+    });
+    packet.add_answer(DnsRecord::A {
+        name: DnsName::from("example.com"),
+        class: DnsClass::IN,
+        ttl: 300,
+        address: Ipv4Addr::new(93, 184, 216, 34),
+    });
+    packet
+}
+
+#[test]
+fn test_write_read_round_trip() {
+    let client: SocketAddr = "192.0.2.1:40000".parse().unwrap();
+    let server: SocketAddr = "192.0.2.53:53".parse().unwrap();
+    let client_v6: SocketAddr = "[2001:db8::1]:40001".parse().unwrap();
+    let server_v6: SocketAddr = "[2001:db8::53]:53".parse().unwrap();
+    let timestamp = UNIX_EPOCH + Duration::from_micros(1_600_000_000_123_456);
+
+    let mut writer = PcapWriter::new(Vec::new()).unwrap();
+    writer
+        .write_packet(timestamp, client, server, &query())
+        .unwrap();
+    writer
+        .write_packet(timestamp, server, client, &response())
+        .unwrap();
+    writer
+        .write_packet(timestamp, client_v6, server_v6, &query())
+        .unwrap();
+    let bytes = writer.into_inner();
+
+    let packets = read_capture(&bytes[..]).unwrap();
+    assert_eq!(3, packets.len());
+
+    assert_eq!(timestamp, packets[0].timestamp);
+    assert_eq!(Transport::Udp, packets[0].transport);
+    assert_eq!(
+        (client, server),
+        (packets[0].source, packets[0].destination)
+    );
+    assert_eq!(query(), packets[0].packet);
+    assert_eq!(
+        (server, client),
+        (packets[1].source, packets[1].destination)
+    );
+    assert_eq!(response(), packets[1].packet);
+    assert_eq!(
+        (client_v6, server_v6),
+        (packets[2].source, packets[2].destination)
+    );
+    assert_eq!(query(), packets[2].packet);
+}
+
+fn ethernet_tcp(sequence: u32, flags: u8, payload: &[u8]) -> Vec<u8> {
+    #[rustfmt::skip]
+    let mut frame = vec![
+        0x02, 0, 0, 0, 0, 0x02, // Destination MAC
+        0x02, 0, 0, 0, 0, 0x01, // Source MAC
+        0x08, 0x00,             // Ethertype: IPv4
+        0x45, 0x00,             // Version 4, header length 20
+    ];
+    frame.extend_from_slice(&(40 + payload.len() as u16).to_be_bytes());
+    #[rustfmt::skip]
+    frame.extend_from_slice(&[
+        0x00, 0x00, 0x40, 0x00, // Identification, don't fragment
+        0x40, 0x06, 0x00, 0x00, // TTL, protocol TCP, checksum (unchecked)
+        192, 0, 2, 1,           // Source
+        192, 0, 2, 53,          // Destination
+        0x9C, 0x40, 0x00, 0x35, // Ports 40000 -> 53
+    ]);
+    frame.extend_from_slice(&sequence.to_be_bytes());
+    #[rustfmt::skip]
+    frame.extend_from_slice(&[
+        0, 0, 0, 0,             // Acknowledgment number
+        0x50, flags,            // Header length 20, flags
+        0xFF, 0xFF, 0, 0, 0, 0, // Window, checksum, urgent pointer
+    ]);
+    frame.extend_from_slice(payload);
+    frame
+}
+
+fn pcapng_block(block_type: u32, body: &[u8]) -> Vec<u8> {
+    let padded = body.len().div_ceil(4) * 4;
+    let length = (12 + padded) as u32;
+    let mut block = block_type.to_le_bytes().to_vec();
+    block.extend_from_slice(&length.to_le_bytes());
+    block.extend_from_slice(body);
+    block.resize(8 + padded, 0);
+    block.extend_from_slice(&length.to_le_bytes());
+    block
+}
+
+fn enhanced_packet(timestamp: u64, frame: &[u8]) -> Vec<u8> {
+    let mut body = 0u32.to_le_bytes().to_vec();
+    body.extend_from_slice(&((timestamp >> 32) as u32).to_le_bytes());
+    body.extend_from_slice(&(timestamp as u32).to_le_bytes());
+    body.extend_from_slice(&(frame.len() as u32).to_le_bytes());
+    body.extend_from_slice(&(frame.len() as u32).to_le_bytes());
+    body.extend_from_slice(frame);
+    pcapng_block(6, &body)
+}
+
+#[test]
+fn test_read_pcapng_tcp_reassembly() {
+    let message = query().serialize().unwrap();
+    let mut stream = (message.len() as u16).to_be_bytes().to_vec();
+    stream.extend_from_slice(&message);
+    stream.extend_from_slice(&stream.clone());
+    let (first, second) = stream.split_at(10);
+
+    // Section header with the little endian byte order magic, version 1.0 and unknown length
+    let mut capture = pcapng_block(
+        0x0A0D_0D0A,
+        &[
+            0x4D, 0x3C, 0x2B, 0x1A, 1, 0, 0, 0, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+        ],
+    );
+    // Ethernet interface with nanosecond timestamps
+    capture.extend(pcapng_block(
+        1,
+        &[1, 0, 0, 0, 0, 0, 0, 0, 9, 0, 1, 0, 9, 0, 0, 0, 0, 0, 0, 0],
+    ));
+    // The handshake, then the second segment before the first
+    capture.extend(enhanced_packet(
+        1_000_000_000,
+        &ethernet_tcp(999, 0x02, &[]),
+    ));
+    capture.extend(enhanced_packet(
+        2_000_000_000,
+        &ethernet_tcp(1010, 0x18, second),
+    ));
+    capture.extend(enhanced_packet(
+        3_000_000_500,
+        &ethernet_tcp(1000, 0x18, first),
+    ));
+
+    let packets = read_capture(&capture[..]).unwrap();
+    assert_eq!(2, packets.len());
+    for packet in &packets {
+        assert_eq!(Transport::Tcp, packet.transport);
+        assert_eq!(
+            UNIX_EPOCH + Duration::from_nanos(3_000_000_500),
+            packet.timestamp
+        );
+        assert_eq!("192.0.2.53:53".parse(), Ok(packet.destination));
+        assert_eq!(query(), packet.packet);
+    }
+}
+
+#[test]
+fn test_write_read_tcp_round_trip() {
+    let client: SocketAddr = "192.0.2.1:40000".parse().unwrap();
+    let server: SocketAddr = "192.0.2.53:53".parse().unwrap();
+    let client_v6: SocketAddr = "[2001:db8::1]:40001".parse().unwrap();
+    let server_v6: SocketAddr = "[2001:db8::53]:53".parse().unwrap();
+    let timestamp = UNIX_EPOCH + Duration::from_micros(1_600_000_000_123_456);
+    let query = query().serialize().unwrap();
+    let response = response().serialize().unwrap();
+
+    // Two pipelined queries on the same connection, then a query over IPv6
+    let mut writer = PcapWriter::new(Vec::new()).unwrap();
+    writer.write_tcp(timestamp, client, server, &query).unwrap();
+    writer.write_tcp(timestamp, client, server, &query).unwrap();
+    writer
+        .write_tcp(timestamp, server, client, &response)
+        .unwrap();
+    writer
+        .write_tcp(timestamp, client_v6, server_v6, &query)
+        .unwrap();
+    let bytes = writer.into_inner();
+
+    let packets = read_capture(&bytes[..]).unwrap();
+    assert_eq!(4, packets.len());
+    assert!(packets.iter().all(|p| p.transport == Transport::Tcp));
+    for packet in &packets[..2] {
+        assert_eq!((client, server), (packet.source, packet.destination));
+        assert_eq!(self::query(), packet.packet);
+    }
+    assert_eq!(
+        (server, client),
+        (packets[2].source, packets[2].destination)
+    );
+    assert_eq!(self::response(), packets[2].packet);
+    assert_eq!(
+        (client_v6, server_v6),
+        (packets[3].source, packets[3].destination)
+    );
+}
+
+#[test]
+fn test_write_udp_past_ipv4_total_length() {
+    let mut writer = PcapWriter::new(Vec::new()).unwrap();
+    // Fits in a UDP datagram, but not in an IPv4 packet along with its header
+    let payload = vec![0; u16::MAX as usize - 8];
+
+    let client = SocketAddr::from(([192, 0, 2, 1], 53000));
+    let server = SocketAddr::from(([192, 0, 2, 53], 53));
+    let error = writer
+        .write_udp(UNIX_EPOCH, client, server, &payload)
+        .unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::InvalidInput);
+
+    let client = SocketAddr::from(([0x2001, 0xdb8, 0, 0, 0, 0, 0, 1], 53000));
+    let server = SocketAddr::from(([0x2001, 0xdb8, 0, 0, 0, 0, 0, 53], 53));
+    assert!(writer
+        .write_udp(UNIX_EPOCH, client, server, &payload)
+        .is_ok());
+}
+
+#[test]
+fn test_read_invalid_capture() {
+    assert!(read_capture(&b"not a capture file"[..]).is_err());
+}
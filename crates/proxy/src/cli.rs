@@ -1,5 +1,5 @@
 use clap::{AppSettings, Clap};
-use std::net::IpAddr;
+use std::{net::IpAddr, path::PathBuf};
 
 #[derive(Clap)]
 #[clap(version = "1.0", author = "Jonathan Bouchard <dev.drakota@gmail.com>")]
@@ -11,9 +11,55 @@ pub struct Opts {
     #[clap(long("fp"), default_value = "53")]
     pub forward_port: u16,
 
+    /// Milliseconds the forward server has to answer, doubled after each retry
+    #[clap(long, default_value = "1500")]
+    pub forward_timeout: u64,
+
+    /// Attempts after the first one when the forward server didn't answer
+    #[clap(long, default_value = "2")]
+    pub forward_retries: u32,
+
     #[clap(short, long, default_value = "53")]
     pub port: u16,
 
     #[clap(short, long)]
     pub verbose: bool,
+
+    /// Queries being forwarded at most, the queries past it are answered with SERVFAIL
+    #[clap(long, default_value = "100")]
+    pub max_in_flight: usize,
+
+    /// Threads answering the queries received
+    #[clap(long, default_value = "100")]
+    pub workers: usize,
+
+    /// Queries waiting for a worker at most, the datagrams past it are dropped while TCP
+    /// connections are not read further until there is room
+    #[clap(long, default_value = "256")]
+    pub queue_size: usize,
+
+    /// Seconds a TCP connection is kept open with no query received nor pending
+    #[clap(long, default_value = "10")]
+    pub tcp_idle_timeout: u64,
+
+    /// TCP connections open at most, the connections past it are closed right away
+    #[clap(long, default_value = "100")]
+    pub max_tcp_connections: usize,
+
+    /// Queries of a TCP connection answered at once at most, the connection not being read
+    /// further until one of them is answered
+    #[clap(long, default_value = "16")]
+    pub max_tcp_pending: usize,
+
+    /// File the traffic is recorded to as a pcap capture
+    #[clap(long)]
+    pub capture: Option<PathBuf>,
+
+    /// File the queries and responses are logged to as dnstap messages
+    #[clap(long)]
+    pub dnstap: Option<PathBuf>,
+
+    /// Unix socket of a dnstap collector the messages are logged to instead of a file
+    #[clap(long)]
+    pub dnstap_socket: Option<PathBuf>,
 }
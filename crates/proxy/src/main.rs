@@ -2,86 +2,203 @@ mod cli;
 
 use clap::Clap;
 use cli::Opts;
-use parser::{header::flags::DnsHeaderFlags, packet::DnsPacket, resources::query::DnsQuery};
-use std::{io::Result, net::UdpSocket};
+use common::{attempt_timeout, receive_response, InFlight, Taps};
+use dnstap::{MessageType, SocketProtocol};
+use parser::{
+    header::flags::{DnsHeaderFlags, ReplyCode},
+    packet::{DnsPacket, DEFAULT_UDP_PAYLOAD_SIZE},
+    resources::query::DnsQuery,
+};
+use std::{
+    io::{Error, ErrorKind, Result},
+    net::{SocketAddr, TcpListener, UdpSocket},
+    sync::{atomic::AtomicUsize, Arc},
+    thread,
+    time::Duration,
+};
+use tcp::TcpConfig;
+use workers::WorkerPool;
 
-fn forward_query(opts: &Opts, query: &DnsQuery) -> Result<DnsPacket> {
+struct Proxy {
+    opts: Opts,
+    socket: UdpSocket,
+    taps: Taps,
+    in_flight: AtomicUsize,
+}
+
+// Sends the query again when the forward server didn't answer in time
+fn forward_query(opts: &Opts, taps: &Taps, query: &DnsQuery) -> Result<DnsPacket> {
+    let base = Duration::from_millis(opts.forward_timeout);
+    let mut attempt = 0;
+    loop {
+        let result = forward_once(opts, taps, query, attempt_timeout(base, attempt));
+        match result {
+            Err(_) if attempt < opts.forward_retries => attempt += 1,
+            result => return result,
+        }
+        if opts.verbose {
+            println!("Retrying {:?} after a failed attempt", query.name);
+        }
+    }
+}
+
+fn forward_once(
+    opts: &Opts,
+    taps: &Taps,
+    query: &DnsQuery,
+    timeout: Duration,
+) -> Result<DnsPacket> {
     // 0 as the port means that the OS will pick a port for us
     let socket = UdpSocket::bind(("0.0.0.0", 0))?;
 
     let mut packet = DnsPacket::new();
     packet.add_query(query.to_owned());
 
-    let bytes = match packet.serialize() {
-        Ok(b) => b,
-        Err(_e) => todo!(),
-    };
+    let mut bytes = [0; DEFAULT_UDP_PAYLOAD_SIZE];
+    let size = packet.write_to(&mut bytes).map_err(|e| {
+        Error::new(
+            ErrorKind::InvalidInput,
+            format!("Error serializing query: {}", e),
+        )
+    })?;
+
+    let server = SocketAddr::new(opts.forward_address, opts.forward_port);
+    socket.send_to(&bytes[..size], server)?;
+    let local = socket.local_addr()?;
+    taps.query(
+        SocketProtocol::Udp,
+        MessageType::ForwarderQuery,
+        local,
+        server,
+        &bytes[..size],
+    );
 
-    socket.send_to(&bytes[..], (opts.forward_address, opts.forward_port))?;
+    receive_response(&socket, server, &packet, timeout, |bytes| {
+        taps.response(
+            SocketProtocol::Udp,
+            MessageType::ForwarderResponse,
+            local,
+            server,
+            bytes,
+        )
+    })
+}
+
+// Answers a message received from a client over either transport
+fn answer(
+    proxy: &Proxy,
+    protocol: SocketProtocol,
+    message: &[u8],
+    src: SocketAddr,
+    local: SocketAddr,
+) -> Option<Vec<u8>> {
+    let Proxy { opts, taps, .. } = proxy;
+    common::answer(
+        taps,
+        protocol,
+        message,
+        src,
+        local,
+        opts.verbose,
+        |request| {
+            let mut response = request.reply();
+            response.header.set_flags(DnsHeaderFlags {
+                response: true,
+                recdesired: true,
+                recavail: true,
+                ..Default::default()
+            });
 
-    let mut buffer = [0; 512];
-    let (size, _) = socket.recv_from(&mut buffer)?;
+            for query in request.queries() {
+                let result = match InFlight::acquire(&proxy.in_flight, opts.max_in_flight) {
+                    Some(_slot) => forward_query(opts, taps, query),
+                    None => Err(Error::new(
+                        ErrorKind::WouldBlock,
+                        "Too many queries in flight",
+                    )),
+                };
+                match result {
+                    Ok(res) => {
+                        response.add_answers(res.answers().to_owned());
+                        response.add_records(res.additional_records().to_owned());
+                        response.add_authorities(res.authorities().to_owned());
+                    }
+                    Err(_) => response.header.flags.rcode = ReplyCode::ServerFailure,
+                }
+            }
+            response
+        },
+    )
+}
 
-    match DnsPacket::parse(&buffer[..size]) {
-        Ok(packet) => Ok(packet),
-        Err(_e) => todo!(),
+fn handle_datagram(proxy: &Proxy, datagram: &[u8], src: SocketAddr) -> Result<()> {
+    let local = proxy.socket.local_addr()?;
+    if let Some(bytes) = answer(proxy, SocketProtocol::Udp, datagram, src, local) {
+        proxy.socket.send_to(&bytes, src)?;
     }
+    Ok(())
+}
+
+// Serves the queries received over TCP from another thread
+fn listen_tcp(proxy: &Arc<Proxy>, pool: &WorkerPool) -> Result<()> {
+    let listener = TcpListener::bind(("0.0.0.0", proxy.opts.port))?;
+    let local = listener.local_addr()?;
+    let config = TcpConfig {
+        idle_timeout: Duration::from_secs(proxy.opts.tcp_idle_timeout),
+        max_connections: proxy.opts.max_tcp_connections,
+        max_pending: proxy.opts.max_tcp_pending,
+    };
+    let proxy = Arc::clone(proxy);
+    let handler = Arc::new(move |message: &[u8], src| {
+        answer(&proxy, SocketProtocol::Tcp, message, src, local)
+    });
+    let pool = pool.clone();
+    thread::spawn(move || {
+        if let Err(e) = tcp::serve(listener, config, handler, pool) {
+            println!("Error listening over TCP: {}", e);
+        }
+    });
+    Ok(())
 }
 
 fn main() -> Result<()> {
     let opts = Opts::parse();
     let socket = UdpSocket::bind(("0.0.0.0", opts.port))?;
+    let taps = Taps::open(
+        opts.capture.as_deref(),
+        opts.dnstap.as_deref(),
+        opts.dnstap_socket.as_deref(),
+    )?;
     println!(
         "Server listening on port {} and proxing requests to {}",
         opts.port, opts.forward_address
     );
 
+    // Datagrams are answered from the listening socket by a fixed set of workers, and dropped
+    // when too many of them are waiting, the clients retrying
+    let pool = WorkerPool::new(opts.workers, opts.queue_size)?;
+    let proxy = Arc::new(Proxy {
+        opts,
+        socket,
+        taps,
+        in_flight: AtomicUsize::new(0),
+    });
+    listen_tcp(&proxy, &pool)?;
     loop {
         let mut buffer = [0; 512];
-
-        let (size, src) = socket.recv_from(&mut buffer)?;
-        if opts.verbose {
+        let (size, src) = proxy.socket.recv_from(&mut buffer)?;
+        if proxy.opts.verbose {
             println!("{} bytes received from {}", size, src);
         }
 
-        let request = match DnsPacket::parse(&buffer[..size]) {
-            Ok(packet) => packet,
-            Err(e) => {
-                println!("Error parsing packet: {:?}", e);
-                continue;
+        let handle = Arc::clone(&proxy);
+        let queued = pool.try_execute(move || {
+            if let Err(e) = handle_datagram(&handle, &buffer[..size], src) {
+                println!("Error answering {}: {}", src, e);
             }
-        };
-        if opts.verbose {
-            println!("Received DNS request:\n{:?}", &request);
-        }
-
-        let mut response = request.clone();
-        response.header.set_flags(DnsHeaderFlags {
-            response: true,
-            recdesired: true,
-            recavail: true,
-            ..Default::default()
         });
-
-        for query in request.queries() {
-            if let Ok(res) = forward_query(&opts, query) {
-                response.add_responses(res.responses().to_owned());
-                response.add_records(res.additional_records().to_owned());
-                response.add_authorities(res.authorities().to_owned());
-            }
-        }
-
-        let bytes = match response.serialize() {
-            Ok(data) => data,
-            Err(e) => {
-                println!("Error serializing packet: {}", e);
-                continue;
-            }
-        };
-
-        socket.send_to(&bytes[..], src)?;
-        if opts.verbose {
-            println!("Sent DNS response:\n{:?}", &response);
+        if !queued && proxy.opts.verbose {
+            println!("Dropping the datagram from {}, too many are waiting", src);
         }
     }
 }
@@ -0,0 +1,1055 @@
+use parser::header::flags::ReplyCode;
+use parser::packet::DnsPacket;
+use parser::resources::name::DnsName;
+use parser::resources::record::DnsRecord;
+use parser::resources::{DnsClass, DnsRecordType, QueryClass, QueryType};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::{self, Display, Formatter};
+use std::mem;
+use std::net::IpAddr;
+use std::time::{Duration, Instant, SystemTime};
+
+// Upper bound of the time a name is remembered not to exist
+// https://datatracker.ietf.org/doc/html/rfc2308#section-5
+const MAX_NEGATIVE_TTL: u32 = 3 * 60 * 60;
+
+pub const DEFAULT_MAX_BYTES: usize = 64 * 1024 * 1024;
+pub const DEFAULT_MAX_ENTRIES: usize = 100_000;
+// Expired entries are kept this long to be served when their authorities can't be reached
+// https://datatracker.ietf.org/doc/html/rfc8767#section-5
+pub const DEFAULT_MAX_STALE: Duration = Duration::from_secs(24 * 60 * 60);
+
+// Popular entries are refreshed once they are in this last share of their TTL, in percent
+const PREFETCH_WINDOW: u32 = 10;
+
+// Share of the limits held by entries that were hit since they were cached, in percent
+const PROTECTED_SHARE: usize = 80;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CacheKey {
+    pub name: DnsName,
+    // None stands for every type of the name, as denied by an NXDOMAIN
+    pub record_type: Option<DnsRecordType>,
+    pub class: DnsClass,
+}
+
+impl CacheKey {
+    pub fn new(name: &DnsName, record_type: DnsRecordType, class: DnsClass) -> Self {
+        Self {
+            name: name.to_lowercase(),
+            record_type: Some(record_type),
+            class,
+        }
+    }
+
+    fn name(name: &DnsName, class: DnsClass) -> Self {
+        Self {
+            name: name.to_lowercase(),
+            record_type: None,
+            class,
+        }
+    }
+
+    fn of(record: &DnsRecord) -> Option<Self> {
+        // OPT is a property of the message, never of the data, and the records of unknown types
+        // are never asked for
+        let class = record.get_class()?;
+        Some(Self::new(record.get_name(), record.get_type()?, class))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CachedAnswer {
+    Records(Vec<DnsRecord>),
+    // The name exists without records of this type, the SOA of the zone tells for how long
+    NoData(DnsRecord),
+    NxDomain(DnsRecord),
+}
+
+#[derive(Debug, Clone)]
+enum CacheData {
+    Records(Vec<DnsRecord>),
+    NoData(DnsRecord),
+    NxDomain(DnsRecord),
+}
+
+impl From<CachedAnswer> for CacheData {
+    fn from(answer: CachedAnswer) -> Self {
+        match answer {
+            CachedAnswer::Records(records) => CacheData::Records(records),
+            CachedAnswer::NoData(soa) => CacheData::NoData(soa),
+            CachedAnswer::NxDomain(soa) => CacheData::NxDomain(soa),
+        }
+    }
+}
+
+impl CacheData {
+    fn heap_size(&self) -> usize {
+        match self {
+            CacheData::Records(records) => {
+                records.capacity() * mem::size_of::<DnsRecord>()
+                    + records.iter().map(|r| r.heap_size()).sum::<usize>()
+            }
+            CacheData::NoData(soa) | CacheData::NxDomain(soa) => soa.heap_size(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Segment {
+    Probation,
+    Protected,
+}
+
+// A whole RRset, or the denial of one, expiring at once
+#[derive(Debug, Clone)]
+struct CacheEntry {
+    data: CacheData,
+    expires: Instant,
+    // The TTL it was cached with
+    lifetime: Duration,
+    hits: u32,
+    size: usize,
+    segment: Segment,
+    // Position in the recency order of its segment
+    tick: u64,
+    // Tick it was stored at, telling it apart in the removal order
+    stored: u64,
+    // When it may be refreshed again while stale
+    next_refresh: Option<Instant>,
+}
+
+// Memory held for an entry, the key being stored in the map, the recency order and the removal
+// order
+fn entry_size(key: &CacheKey, data: &CacheData) -> usize {
+    3 * (mem::size_of::<CacheKey>() + key.name.heap_size())
+        + mem::size_of::<u64>()
+        + mem::size_of::<(Instant, u64)>()
+        + mem::size_of::<CacheEntry>()
+        + data.heap_size()
+}
+
+impl CacheEntry {
+    fn is_expired(&self, now: Instant) -> bool {
+        self.expires <= now
+    }
+
+    fn is_stale(&self, now: Instant, max_stale: Duration) -> bool {
+        self.is_expired(now) && now < self.removal(max_stale)
+    }
+
+    // When it is past its stale window
+    fn removal(&self, max_stale: Duration) -> Instant {
+        self.expires + max_stale
+    }
+
+    // The records with the TTL they have left
+    fn answer(&self, now: Instant) -> CachedAnswer {
+        let remaining = self.expires.saturating_duration_since(now).as_secs() as u32;
+        self.answer_with_ttl(remaining)
+    }
+
+    fn answer_with_ttl(&self, ttl: u32) -> CachedAnswer {
+        let with_ttl = |record: &DnsRecord| {
+            let mut record = record.clone();
+            record.set_ttl(ttl);
+            record
+        };
+
+        match &self.data {
+            CacheData::Records(records) => {
+                CachedAnswer::Records(records.iter().map(with_ttl).collect())
+            }
+            CacheData::NoData(soa) => CachedAnswer::NoData(with_ttl(soa)),
+            CacheData::NxDomain(soa) => CachedAnswer::NxDomain(with_ttl(soa)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CacheLimits {
+    pub max_bytes: usize,
+    pub max_entries: usize,
+    pub max_stale: Duration,
+}
+
+impl Default for CacheLimits {
+    fn default() -> Self {
+        Self {
+            max_bytes: DEFAULT_MAX_BYTES,
+            max_entries: DEFAULT_MAX_ENTRIES,
+            max_stale: DEFAULT_MAX_STALE,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CacheStats {
+    pub entries: usize,
+    pub bytes: usize,
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+    pub evicted_bytes: u64,
+    pub expirations: u64,
+}
+
+impl Display for CacheStats {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} RRsets cached in {} bytes, {} hits, {} misses, {} evicted ({} bytes), {} expired",
+            self.entries,
+            self.bytes,
+            self.hits,
+            self.misses,
+            self.evictions,
+            self.evicted_bytes,
+            self.expirations
+        )
+    }
+}
+
+// An entry with the time it expires at, so it can outlive the process
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SnapshotEntry {
+    pub key: CacheKey,
+    pub answer: CachedAnswer,
+    pub expires: SystemTime,
+    pub protected: bool,
+}
+
+// Segmented LRU: entries start on probation and are protected once hit again, so a scan of
+// names that are only asked once only evicts other entries on probation
+#[derive(Debug, Default)]
+pub struct Cache {
+    entries: HashMap<CacheKey, CacheEntry>,
+    // Least recently used first
+    probation: BTreeMap<u64, CacheKey>,
+    protected: BTreeMap<u64, CacheKey>,
+    // Entries by the time they are past their stale window, so that removing them doesn't go
+    // over the whole cache
+    removals: BTreeMap<(Instant, u64), CacheKey>,
+    tick: u64,
+    bytes: usize,
+    protected_bytes: usize,
+    limits: CacheLimits,
+    stats: CacheStats,
+}
+
+impl Cache {
+    pub fn new(limits: CacheLimits) -> Self {
+        Self {
+            limits,
+            ..Default::default()
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            entries: self.len(),
+            bytes: self.bytes(),
+            ..self.stats
+        }
+    }
+
+    fn next_tick(&mut self) -> u64 {
+        self.tick += 1;
+        self.tick
+    }
+
+    fn order(&mut self, segment: Segment) -> &mut BTreeMap<u64, CacheKey> {
+        match segment {
+            Segment::Probation => &mut self.probation,
+            Segment::Protected => &mut self.protected,
+        }
+    }
+
+    fn remove(&mut self, key: &CacheKey) -> Option<CacheEntry> {
+        let entry = self.entries.remove(key)?;
+        self.order(entry.segment).remove(&entry.tick);
+        let removal = entry.removal(self.limits.max_stale);
+        self.removals.remove(&(removal, entry.stored));
+        self.bytes -= entry.size;
+        if entry.segment == Segment::Protected {
+            self.protected_bytes -= entry.size;
+        }
+        Some(entry)
+    }
+
+    // Moves an entry that was hit to the most recently used end of the protected segment
+    fn touch(&mut self, key: &CacheKey) {
+        let tick = self.next_tick();
+        let entry = match self.entries.get_mut(key) {
+            Some(entry) => entry,
+            None => return,
+        };
+        let (previous, segment) = (entry.tick, entry.segment);
+        entry.hits = entry.hits.saturating_add(1);
+        entry.tick = tick;
+        entry.segment = Segment::Protected;
+        if segment == Segment::Probation {
+            self.protected_bytes += entry.size;
+        }
+
+        self.order(segment).remove(&previous);
+        self.protected.insert(tick, key.clone());
+        self.demote();
+    }
+
+    // Keeps the protected segment within its share, what it pushes out gets another chance
+    // on probation
+    fn demote(&mut self) {
+        let max_entries = self.limits.max_entries * PROTECTED_SHARE / 100;
+        let max_bytes = self.limits.max_bytes * PROTECTED_SHARE / 100;
+        while self.protected.len() > max_entries || self.protected_bytes > max_bytes {
+            let key = match self.protected.pop_first() {
+                Some((_, key)) => key,
+                None => break,
+            };
+            let tick = self.next_tick();
+            if let Some(entry) = self.entries.get_mut(&key) {
+                entry.tick = tick;
+                entry.segment = Segment::Probation;
+                self.protected_bytes -= entry.size;
+            }
+            self.probation.insert(tick, key);
+        }
+    }
+
+    // Evicts the least recently used entries, those on probation first
+    fn evict(&mut self) {
+        while self.entries.len() > self.limits.max_entries || self.bytes > self.limits.max_bytes {
+            let oldest = self
+                .probation
+                .values()
+                .next()
+                .or_else(|| self.protected.values().next());
+            let key = match oldest {
+                Some(key) => key.clone(),
+                None => break,
+            };
+            if let Some(entry) = self.remove(&key) {
+                self.stats.evictions += 1;
+                self.stats.evicted_bytes += entry.size as u64;
+            }
+        }
+    }
+
+    fn lookup(&mut self, key: &CacheKey, now: Instant) -> Option<CachedAnswer> {
+        let answer = match self.entries.get(key) {
+            Some(entry) if !entry.is_expired(now) => entry.answer(now),
+            _ => return None,
+        };
+        self.touch(key);
+        Some(answer)
+    }
+
+    pub fn get(&mut self, key: &CacheKey, now: Instant) -> Option<CachedAnswer> {
+        // A name that doesn't exist has no records of any type
+        let answer = self
+            .lookup(&CacheKey::name(&key.name, key.class), now)
+            .or_else(|| self.lookup(key, now));
+        match answer {
+            Some(_) => self.stats.hits += 1,
+            None => self.stats.misses += 1,
+        }
+        answer
+    }
+
+    // Whether an entry was hit often enough, and is close enough to expiring, to be refreshed
+    // before it does
+    pub fn should_prefetch(&self, key: &CacheKey, now: Instant, min_hits: u32) -> bool {
+        match self.entries.get(key) {
+            Some(entry) if !entry.is_expired(now) && entry.hits >= min_hits => {
+                entry.expires - now <= entry.lifetime * PREFETCH_WINDOW / 100
+            }
+            _ => false,
+        }
+    }
+
+    // An expired answer that is still within the stale window, with the given TTL
+    pub fn get_stale(&self, key: &CacheKey, now: Instant, ttl: u32) -> Option<CachedAnswer> {
+        let max_stale = self.limits.max_stale;
+        [CacheKey::name(&key.name, key.class), key.clone()]
+            .iter()
+            .filter_map(|key| self.entries.get(key))
+            .find(|entry| entry.is_stale(now, max_stale))
+            .map(|entry| entry.answer_with_ttl(ttl))
+    }
+
+    // Whether the stale answer to `key` is due for a refresh, which isn't due again before
+    // `interval` went by
+    pub fn refresh_due(&mut self, key: &CacheKey, now: Instant, interval: Duration) -> bool {
+        let max_stale = self.limits.max_stale;
+        let stale = [CacheKey::name(&key.name, key.class), key.clone()]
+            .iter()
+            .find(|key| {
+                self.entries
+                    .get(key)
+                    .is_some_and(|entry| entry.is_stale(now, max_stale))
+            })
+            .cloned();
+        let entry = match stale.and_then(|key| self.entries.get_mut(&key)) {
+            Some(entry) => entry,
+            None => return false,
+        };
+        if entry.next_refresh.is_some_and(|next| now < next) {
+            return false;
+        }
+        entry.next_refresh = Some(now + interval);
+        true
+    }
+
+    fn get_records(&mut self, key: &CacheKey, now: Instant) -> Option<Vec<DnsRecord>> {
+        match self.lookup(key, now) {
+            Some(CachedAnswer::Records(records)) => Some(records),
+            _ => None,
+        }
+    }
+
+    fn insert_entry(&mut self, key: CacheKey, data: CacheData, ttl: u32, now: Instant) {
+        if ttl == 0 {
+            return;
+        }
+
+        // An RRset that is refreshed keeps its place
+        let segment = self
+            .remove(&key)
+            .map_or(Segment::Probation, |entry| entry.segment);
+        self.store(key, data, Duration::from_secs(ttl as u64), now, segment);
+    }
+
+    fn store(
+        &mut self,
+        key: CacheKey,
+        data: CacheData,
+        lifetime: Duration,
+        now: Instant,
+        segment: Segment,
+    ) {
+        let size = entry_size(&key, &data);
+        if size > self.limits.max_bytes {
+            return;
+        }
+
+        let tick = self.next_tick();
+        let entry = CacheEntry {
+            data,
+            expires: now + lifetime,
+            lifetime,
+            hits: 0,
+            size,
+            segment,
+            tick,
+            stored: tick,
+            next_refresh: None,
+        };
+        self.order(segment).insert(tick, key.clone());
+        self.removals
+            .insert((entry.removal(self.limits.max_stale), tick), key.clone());
+        self.bytes += size;
+        if segment == Segment::Protected {
+            self.protected_bytes += size;
+        }
+        self.entries.insert(key, entry);
+
+        self.demote();
+        self.evict();
+    }
+
+    // Records are grouped in RRsets, each replacing what was cached for it
+    pub fn insert(&mut self, records: &[DnsRecord], now: Instant) {
+        let mut rrsets: HashMap<CacheKey, Vec<DnsRecord>> = HashMap::new();
+        for record in records {
+            if let Some(key) = CacheKey::of(record) {
+                let rrset = rrsets.entry(key).or_default();
+                if !rrset.contains(record) {
+                    rrset.push(record.clone());
+                }
+            }
+        }
+
+        for (key, records) in rrsets {
+            // The name exists after all
+            self.remove(&CacheKey::name(&key.name, key.class));
+
+            let ttl = records
+                .iter()
+                .filter_map(|r| r.get_ttl())
+                .min()
+                .unwrap_or(0);
+            self.insert_entry(key, CacheData::Records(records), ttl, now);
+        }
+    }
+
+    // Caches the answers of a response, the delegation it holds with the glue of its name
+    // servers, or the denial it holds
+    pub fn insert_response(&mut self, response: &DnsPacket, now: Instant) {
+        self.insert(response.answers(), now);
+        self.insert_negative(response, now);
+
+        let delegation: Vec<DnsRecord> = response
+            .authorities()
+            .iter()
+            .filter(|r| r.get_type() == Some(DnsRecordType::NS))
+            .cloned()
+            .collect();
+        let name_servers: Vec<DnsName> = delegation
+            .iter()
+            .filter_map(|r| match r {
+                DnsRecord::NS { name_server, .. } => Some(name_server.to_lowercase()),
+                _ => None,
+            })
+            .collect();
+        let glue: Vec<DnsRecord> = response
+            .additional_records()
+            .iter()
+            .filter(|r| r.get_address().is_some())
+            .filter(|r| name_servers.contains(&r.get_name().to_lowercase()))
+            .cloned()
+            .collect();
+
+        self.insert(&delegation, now);
+        self.insert(&glue, now);
+    }
+
+    // https://datatracker.ietf.org/doc/html/rfc2308#section-5
+    fn insert_negative(&mut self, response: &DnsPacket, now: Instant) {
+        let query = match response.queries().first() {
+            Some(query) if response.answers().is_empty() => query,
+            _ => return,
+        };
+        let (record_type, class) = match (query.record_type, query.class) {
+            (QueryType::Record(record_type), QueryClass::Class(class)) => (record_type, class),
+            _ => return,
+        };
+        let soa = match response
+            .authorities()
+            .iter()
+            .find(|r| r.get_type() == Some(DnsRecordType::SOA))
+        {
+            Some(soa) => soa,
+            None => return,
+        };
+
+        // The SOA TTL is itself bounded by its MINIMUM field
+        let ttl = match soa {
+            DnsRecord::SOA { ttl, minimum, .. } => (*ttl).min(*minimum).min(MAX_NEGATIVE_TTL),
+            _ => return,
+        };
+        match response.header.flags.rcode {
+            ReplyCode::NameError => self.insert_entry(
+                CacheKey::name(&query.name, class),
+                CacheData::NxDomain(soa.clone()),
+                ttl,
+                now,
+            ),
+            ReplyCode::NoError => self.insert_entry(
+                CacheKey::new(&query.name, record_type, class),
+                CacheData::NoData(soa.clone()),
+                ttl,
+                now,
+            ),
+            _ => {}
+        }
+    }
+
+    // The closest enclosing zone whose delegation is cached, with the addresses of its name servers
+    pub fn closest_name_servers(
+        &mut self,
+        name: &DnsName,
+        class: DnsClass,
+        now: Instant,
+    ) -> Option<(DnsName, Vec<IpAddr>)> {
+        let labels = name.labels();
+        for start in 0..labels.len() {
+            let zone = DnsName::from(labels[start..].to_vec());
+            let key = CacheKey::new(&zone, DnsRecordType::NS, class);
+            let name_servers = match self.get_records(&key, now) {
+                Some(name_servers) => name_servers,
+                None => continue,
+            };
+
+            let addresses: Vec<IpAddr> = name_servers
+                .iter()
+                .filter_map(|ns| match ns {
+                    DnsRecord::NS { name_server, .. } => Some(name_server),
+                    _ => None,
+                })
+                .flat_map(|name_server| {
+                    [DnsRecordType::A, DnsRecordType::AAAA]
+                        .iter()
+                        .filter_map(|&t| {
+                            self.get_records(&CacheKey::new(name_server, t, class), now)
+                        })
+                        .flatten()
+                        .collect::<Vec<_>>()
+                })
+                .filter_map(|r| r.get_address())
+                .collect();
+            if !addresses.is_empty() {
+                return Some((zone, addresses));
+            }
+        }
+        None
+    }
+
+    // The live entries, least recently used first so that restoring them keeps their order
+    pub fn snapshot(&self, now: Instant, wall: SystemTime) -> Vec<SnapshotEntry> {
+        self.probation
+            .values()
+            .chain(self.protected.values())
+            .filter_map(|key| {
+                let entry = self.entries.get(key)?;
+                if entry.is_expired(now) {
+                    return None;
+                }
+                Some(SnapshotEntry {
+                    key: key.clone(),
+                    answer: entry.answer(now),
+                    expires: wall + (entry.expires - now),
+                    protected: entry.segment == Segment::Protected,
+                })
+            })
+            .collect()
+    }
+
+    // Returns the number of entries restored, those that expired in the meantime are dropped
+    pub fn restore(
+        &mut self,
+        entries: Vec<SnapshotEntry>,
+        now: Instant,
+        wall: SystemTime,
+    ) -> usize {
+        let mut restored = 0;
+        for entry in entries {
+            let remaining = match entry.expires.duration_since(wall) {
+                Ok(remaining) if remaining > Duration::from_secs(0) => remaining,
+                _ => continue,
+            };
+            let segment = if entry.protected {
+                Segment::Protected
+            } else {
+                Segment::Probation
+            };
+            self.remove(&entry.key);
+            self.store(entry.key, entry.answer.into(), remaining, now, segment);
+            restored += 1;
+        }
+        self.demote();
+        self.evict();
+        restored
+    }
+
+    // Removes the entries that are past their stale window, oldest first
+    pub fn remove_expired(&mut self, now: Instant) {
+        while let Some(entry) = self.removals.first_entry() {
+            if entry.key().0 > now {
+                break;
+            }
+            let key = entry.remove();
+            self.remove(&key);
+            self.stats.expirations += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parser::resources::query::DnsQuery;
+    use std::net::Ipv4Addr;
+
+    fn a(name: &str, ttl: u32, address: [u8; 4]) -> DnsRecord {
+        DnsRecord::A {
+            name: DnsName::from(name),
+            class: DnsClass::IN,
+            ttl,
+            address: Ipv4Addr::from(address),
+        }
+    }
+
+    fn ns(zone: &str, ttl: u32, name_server: &str) -> DnsRecord {
+        DnsRecord::NS {
+            name: DnsName::from(zone),
+            class: DnsClass::IN,
+            ttl,
+            name_server: DnsName::from(name_server),
+        }
+    }
+
+    fn soa(zone: &str, ttl: u32, minimum: u32) -> DnsRecord {
+        DnsRecord::SOA {
+            name: DnsName::from(zone),
+            class: DnsClass::IN,
+            ttl,
+            mname: DnsName::from("ns1.example.com"),
+            rname: DnsName::from("hostmaster.example.com"),
+            serial: 2021010101,
+            refresh: 7200,
+            retry: 3600,
+            expire: 1209600,
+            minimum,
+        }
+    }
+
+    fn negative_response(name: &str, record_type: DnsRecordType, rcode: ReplyCode) -> DnsPacket {
+        let mut response = DnsPacket::new();
+        response.add_query(DnsQuery {
+            name: DnsName::from(name),
+            record_type: record_type.into(),
+            class: DnsClass::IN.into(),
+        });
+        response.header.flags.rcode = rcode;
+        response.add_authority(soa("example.com", 3600, 300));
+        response
+    }
+
+    #[test]
+    fn test_rrset_expiry_and_ttl_decrement() {
+        let now = Instant::now();
+        let mut cache = Cache::new(CacheLimits::default());
+        cache.insert(
+            &[
+                a("example.com", 300, [192, 0, 2, 1]),
+                a("Example.com", 60, [192, 0, 2, 2]),
+            ],
+            now,
+        );
+
+        let key = CacheKey::new(
+            &DnsName::from("EXAMPLE.com"),
+            DnsRecordType::A,
+            DnsClass::IN,
+        );
+        let records = cache
+            .get_records(&key, now + Duration::from_secs(20))
+            .unwrap();
+        assert_eq!(2, records.len());
+        assert!(records.iter().all(|r| r.get_ttl() == Some(40)));
+
+        assert_eq!(None, cache.get(&key, now + Duration::from_secs(60)));
+        cache.remove_expired(now + Duration::from_secs(60) + DEFAULT_MAX_STALE);
+        assert_eq!(0, cache.len());
+    }
+
+    #[test]
+    fn test_remove_expired_in_expiry_order() {
+        let now = Instant::now();
+        let mut cache = Cache::new(CacheLimits {
+            max_stale: Duration::from_secs(0),
+            ..CacheLimits::default()
+        });
+        cache.insert(&[a("short.example.com", 60, [192, 0, 2, 1])], now);
+        cache.insert(&[a("long.example.com", 600, [192, 0, 2, 2])], now);
+        // Refreshing an RRset moves it in the removal order
+        cache.insert(&[a("refreshed.example.com", 60, [192, 0, 2, 3])], now);
+        cache.insert(&[a("refreshed.example.com", 900, [192, 0, 2, 3])], now);
+        assert_eq!(3, cache.removals.len());
+
+        cache.remove_expired(now + Duration::from_secs(60));
+        assert_eq!(2, cache.len());
+        assert_eq!(1, cache.stats().expirations);
+
+        cache.remove_expired(now + Duration::from_secs(600));
+        assert_eq!(1, cache.len());
+        let key = CacheKey::new(
+            &DnsName::from("refreshed.example.com"),
+            DnsRecordType::A,
+            DnsClass::IN,
+        );
+        assert!(cache.get(&key, now + Duration::from_secs(600)).is_some());
+
+        cache.remove_expired(now + Duration::from_secs(900));
+        assert_eq!(0, cache.len());
+        assert!(cache.removals.is_empty());
+    }
+
+    #[test]
+    fn test_stats() {
+        let now = Instant::now();
+        let mut cache = Cache::new(CacheLimits::default());
+        cache.insert(&[a("www.example.com", 60, [192, 0, 2, 1])], now);
+        let key = CacheKey::new(
+            &DnsName::from("www.example.com"),
+            DnsRecordType::A,
+            DnsClass::IN,
+        );
+        let missing = CacheKey::new(
+            &DnsName::from("missing.example.com"),
+            DnsRecordType::A,
+            DnsClass::IN,
+        );
+        cache.get(&key, now);
+        cache.get(&missing, now);
+
+        let stats = cache.stats();
+        assert_eq!(1, stats.entries);
+        assert_eq!(cache.bytes(), stats.bytes);
+        assert_eq!((1, 1), (stats.hits, stats.misses));
+        assert!(stats.to_string().starts_with("1 RRsets cached in "));
+    }
+
+    #[test]
+    fn test_closest_name_servers() {
+        let now = Instant::now();
+        let mut cache = Cache::new(CacheLimits::default());
+
+        let mut referral = DnsPacket::new();
+        referral.add_authority(ns("com", 3600, "a.gtld-servers.net"));
+        referral.add_record(a("a.gtld-servers.net", 3600, [192, 5, 6, 30]));
+        // Glue for a name server that isn't part of the delegation is ignored
+        referral.add_record(a("unrelated.example", 3600, [203, 0, 113, 1]));
+        cache.insert_response(&referral, now);
+
+        assert_eq!(
+            Some((DnsName::from("com"), vec![IpAddr::from([192, 5, 6, 30])])),
+            cache.closest_name_servers(&DnsName::from("www.example.com"), DnsClass::IN, now)
+        );
+        assert!(cache
+            .closest_name_servers(&DnsName::from("example.org"), DnsClass::IN, now)
+            .is_none());
+        assert_eq!(2, cache.len());
+    }
+
+    #[test]
+    fn test_negative_caching() {
+        let now = Instant::now();
+        let mut cache = Cache::new(CacheLimits::default());
+        cache.insert_response(
+            &negative_response(
+                "missing.example.com",
+                DnsRecordType::A,
+                ReplyCode::NameError,
+            ),
+            now,
+        );
+        cache.insert_response(
+            &negative_response("www.example.com", DnsRecordType::AAAA, ReplyCode::NoError),
+            now,
+        );
+
+        // NXDOMAIN covers every type, for the SOA MINIMUM as it is below the SOA TTL
+        let later = now + Duration::from_secs(100);
+        let missing = CacheKey::new(
+            &DnsName::from("missing.example.com"),
+            DnsRecordType::TXT,
+            DnsClass::IN,
+        );
+        assert_eq!(
+            Some(CachedAnswer::NxDomain(soa("example.com", 200, 300))),
+            cache.get(&missing, later)
+        );
+
+        // NODATA only covers the type that was asked
+        let www = DnsName::from("www.example.com");
+        assert_eq!(
+            Some(CachedAnswer::NoData(soa("example.com", 200, 300))),
+            cache.get(
+                &CacheKey::new(&www, DnsRecordType::AAAA, DnsClass::IN),
+                later
+            )
+        );
+        assert_eq!(
+            None,
+            cache.get(&CacheKey::new(&www, DnsRecordType::A, DnsClass::IN), later)
+        );
+        assert_eq!(None, cache.get(&missing, now + Duration::from_secs(300)));
+    }
+
+    #[test]
+    fn test_scan_resistance() {
+        let now = Instant::now();
+        let mut cache = Cache::new(CacheLimits {
+            max_bytes: DEFAULT_MAX_BYTES,
+            max_entries: 10,
+            ..CacheLimits::default()
+        });
+        let hot = CacheKey::new(
+            &DnsName::from("hot.example.com"),
+            DnsRecordType::A,
+            DnsClass::IN,
+        );
+        cache.insert(&[a("hot.example.com", 300, [192, 0, 2, 1])], now);
+        assert!(cache.get(&hot, now).is_some());
+
+        // A scan of names asked once only pushes out other names asked once
+        for i in 0..100 {
+            let name = format!("{}.random.example.com", i);
+            cache.insert(&[a(&name, 300, [192, 0, 2, 2])], now);
+        }
+        assert_eq!(10, cache.len());
+        assert!(cache.get(&hot, now).is_some());
+        assert_eq!(91, cache.stats().evictions);
+    }
+
+    #[test]
+    fn test_byte_limit() {
+        let now = Instant::now();
+        let records = [a("z.example.com", 300, [192, 0, 2, 1])];
+        let mut cache = Cache::new(CacheLimits::default());
+        cache.insert(&records, now);
+        let size = cache.bytes();
+        assert!(size > mem::size_of::<DnsRecord>());
+
+        let mut cache = Cache::new(CacheLimits {
+            max_bytes: 3 * size,
+            ..CacheLimits::default()
+        });
+        for name in &[
+            "a.example.com",
+            "b.example.com",
+            "c.example.com",
+            "d.example.com",
+        ] {
+            cache.insert(&[a(name, 300, [192, 0, 2, 1])], now);
+        }
+        assert_eq!(3, cache.len());
+        assert!(cache.bytes() <= 3 * size);
+        assert_eq!(1, cache.stats().evictions);
+        assert_eq!(size as u64, cache.stats().evicted_bytes);
+
+        // The least recently used name went first
+        let first = CacheKey::new(
+            &DnsName::from("a.example.com"),
+            DnsRecordType::A,
+            DnsClass::IN,
+        );
+        assert_eq!(None, cache.get(&first, now));
+    }
+
+    #[test]
+    fn test_snapshot_and_restore() {
+        let now = Instant::now();
+        let wall = SystemTime::now();
+        let mut cache = Cache::new(CacheLimits::default());
+        cache.insert(
+            &[
+                a("short.example.com", 10, [192, 0, 2, 1]),
+                a("long.example.com", 300, [192, 0, 2, 2]),
+            ],
+            now,
+        );
+        let long = CacheKey::new(
+            &DnsName::from("long.example.com"),
+            DnsRecordType::A,
+            DnsClass::IN,
+        );
+        cache.get(&long, now);
+        let entries = cache.snapshot(now, wall);
+        assert_eq!(2, entries.len());
+        assert!(entries.iter().any(|e| e.key == long && e.protected));
+
+        // A minute later, after a restart
+        let later = Instant::now();
+        let mut restored = Cache::new(CacheLimits::default());
+        assert_eq!(
+            1,
+            restored.restore(entries, later, wall + Duration::from_secs(60))
+        );
+        assert_eq!(
+            Some(240),
+            restored.get_records(&long, later).unwrap()[0].get_ttl()
+        );
+    }
+
+    #[test]
+    fn test_stale_answers() {
+        let now = Instant::now();
+        let mut cache = Cache::new(CacheLimits {
+            max_stale: Duration::from_secs(3600),
+            ..CacheLimits::default()
+        });
+        cache.insert(&[a("www.example.com", 60, [192, 0, 2, 1])], now);
+        let key = CacheKey::new(
+            &DnsName::from("www.example.com"),
+            DnsRecordType::A,
+            DnsClass::IN,
+        );
+        assert_eq!(None, cache.get_stale(&key, now, 30));
+
+        let expired = now + Duration::from_secs(120);
+        assert_eq!(None, cache.get(&key, expired));
+        assert_eq!(
+            Some(CachedAnswer::Records(vec![a(
+                "www.example.com",
+                30,
+                [192, 0, 2, 1]
+            )])),
+            cache.get_stale(&key, expired, 30)
+        );
+        cache.remove_expired(expired);
+        assert_eq!(1, cache.len());
+
+        let past_window = now + Duration::from_secs(60 + 3600);
+        assert_eq!(None, cache.get_stale(&key, past_window, 30));
+        cache.remove_expired(past_window);
+        assert_eq!(0, cache.len());
+    }
+
+    #[test]
+    fn test_stale_refresh_interval() {
+        let now = Instant::now();
+        let mut cache = Cache::new(CacheLimits::default());
+        cache.insert(&[a("www.example.com", 60, [192, 0, 2, 1])], now);
+        let key = CacheKey::new(
+            &DnsName::from("www.example.com"),
+            DnsRecordType::A,
+            DnsClass::IN,
+        );
+        let interval = Duration::from_secs(30);
+        assert!(!cache.refresh_due(&key, now, interval));
+
+        let expired = now + Duration::from_secs(60);
+        assert!(cache.refresh_due(&key, expired, interval));
+        assert!(!cache.refresh_due(&key, expired + Duration::from_secs(29), interval));
+        assert!(cache.refresh_due(&key, expired + interval, interval));
+    }
+
+    #[test]
+    fn test_prefetch() {
+        let now = Instant::now();
+        let mut cache = Cache::new(CacheLimits::default());
+        cache.insert(
+            &[
+                a("popular.example.com", 100, [192, 0, 2, 1]),
+                a("rare.example.com", 100, [192, 0, 2, 2]),
+            ],
+            now,
+        );
+        let popular = CacheKey::new(
+            &DnsName::from("popular.example.com"),
+            DnsRecordType::A,
+            DnsClass::IN,
+        );
+        let rare = CacheKey::new(
+            &DnsName::from("rare.example.com"),
+            DnsRecordType::A,
+            DnsClass::IN,
+        );
+        for _ in 0..3 {
+            cache.get(&popular, now);
+        }
+        cache.get(&rare, now);
+
+        let early = now + Duration::from_secs(50);
+        assert!(!cache.should_prefetch(&popular, early, 3));
+        let late = now + Duration::from_secs(95);
+        assert!(cache.should_prefetch(&popular, late, 3));
+        assert!(!cache.should_prefetch(&rare, late, 3));
+        assert!(!cache.should_prefetch(&popular, now + Duration::from_secs(100), 3));
+
+        // The refreshed RRset has to become popular again
+        cache.insert(&[a("popular.example.com", 100, [192, 0, 2, 1])], late);
+        assert!(!cache.should_prefetch(&popular, late + Duration::from_secs(95), 3));
+    }
+}
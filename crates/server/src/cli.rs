@@ -1,4 +1,5 @@
 use clap::{AppSettings, Clap};
+use std::path::PathBuf;
 
 #[derive(Clap)]
 #[clap(version = "1.0", author = "Jonathan Bouchard <dev.drakota@gmail.com>")]
@@ -9,4 +10,120 @@ pub struct Opts {
 
     #[clap(short, long)]
     pub verbose: bool,
+
+    /// Recursions in flight at most, the queries past it are answered with SERVFAIL
+    #[clap(long, default_value = "100")]
+    pub max_recursions: usize,
+
+    /// Milliseconds a name server has to answer, doubled after each round over the name servers
+    #[clap(long, default_value = "1500")]
+    pub upstream_timeout: u64,
+
+    /// Rounds over the name servers of a zone after the first one, when none of them answered
+    #[clap(long, default_value = "2")]
+    pub upstream_retries: u32,
+
+    /// Queries sent to name servers at most to answer a client query, which is answered with
+    /// SERVFAIL past it
+    #[clap(long, default_value = "64")]
+    pub max_upstream_queries: u32,
+
+    /// Referrals followed at most to answer a client query, counting the ones resolving name
+    /// servers
+    #[clap(long, default_value = "16")]
+    pub max_delegation_depth: usize,
+
+    /// Name servers whose address is resolved at most for a referral that came without glue
+    #[clap(long, default_value = "3")]
+    pub max_ns_per_referral: usize,
+
+    /// Seconds the resolution of a client query may take at most
+    #[clap(long, default_value = "10")]
+    pub resolution_timeout: u64,
+
+    /// Sends the full names to the servers above their zone, instead of only their next label
+    #[clap(long)]
+    pub no_qname_minimisation: bool,
+
+    /// Fails the queries whose minimised names are refused, instead of sending the full names
+    #[clap(long)]
+    pub strict_qname_minimisation: bool,
+
+    /// Sends names to name servers as they were asked, instead of randomizing their case
+    #[clap(long)]
+    pub no_case_randomization: bool,
+
+    /// Maximum memory held by the cache, in bytes
+    #[clap(long, default_value = "67108864")]
+    pub cache_size: usize,
+
+    /// Maximum number of RRsets held by the cache
+    #[clap(long, default_value = "100000")]
+    pub cache_entries: usize,
+
+    /// Seconds expired answers are kept for, to be served when their authorities can't be reached
+    #[clap(long, default_value = "86400")]
+    pub stale_window: u64,
+
+    /// TTL of the expired answers served
+    #[clap(long, default_value = "30")]
+    pub stale_ttl: u32,
+
+    /// Hits that make a cache entry popular enough to be refreshed before it expires
+    #[clap(long, default_value = "3")]
+    pub prefetch_hits: u32,
+
+    /// Prefetches started per second at most, 0 disabling them
+    #[clap(long, default_value = "10")]
+    pub prefetch_rate: u32,
+
+    /// Threads resolving prefetches and refreshing stale answers
+    #[clap(long, default_value = "2")]
+    pub prefetch_workers: usize,
+
+    /// File the cache is saved to periodically and on shutdown, and restored from on startup
+    #[clap(long)]
+    pub cache_snapshot: Option<PathBuf>,
+
+    /// Seconds between two saves of the cache snapshot
+    #[clap(long, default_value = "300")]
+    pub snapshot_interval: u64,
+
+    /// Threads answering the queries received
+    #[clap(long, default_value = "100")]
+    pub workers: usize,
+
+    /// Queries waiting for a worker at most, the datagrams past it are dropped while TCP
+    /// connections are not read further until there is room
+    #[clap(long, default_value = "256")]
+    pub queue_size: usize,
+
+    /// Seconds between two reports of the cache statistics, 0 disabling them
+    #[clap(long, default_value = "0")]
+    pub stats_interval: u64,
+
+    /// Seconds a TCP connection is kept open with no query received nor pending
+    #[clap(long, default_value = "10")]
+    pub tcp_idle_timeout: u64,
+
+    /// TCP connections open at most, the connections past it are closed right away
+    #[clap(long, default_value = "100")]
+    pub max_tcp_connections: usize,
+
+    /// Queries of a TCP connection answered at once at most, the connection not being read
+    /// further until one of them is answered
+    #[clap(long, default_value = "16")]
+    pub max_tcp_pending: usize,
+
+    /// File the traffic is recorded to as a pcap capture
+    #[clap(long)]
+    pub capture: Option<PathBuf>,
+
+    /// File the queries and responses are logged to as dnstap messages
+    #[clap(long)]
+    pub dnstap: Option<PathBuf>,
+
+    /// Unix socket of a dnstap collector the messages are logged to instead of a file
+    #[clap(long)]
+    pub dnstap_socket: Option<PathBuf>,
 }
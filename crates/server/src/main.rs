@@ -1,147 +1,258 @@
+mod cache;
 mod cli;
+mod resolver;
 mod root_servers;
-mod traits;
+mod snapshot;
 
+use cache::{Cache, CacheLimits};
 use clap::Clap;
 use cli::Opts;
+use common::Taps;
+use dnstap::SocketProtocol;
 use parser::header::flags::{DnsHeaderFlags, ReplyCode};
 use parser::packet::DnsPacket;
 use parser::resources::query::DnsQuery;
 use parser::resources::record::DnsRecord;
-use parser::resources::{DnsClass, DnsRecordType};
-use std::io::Result;
-use std::net::{IpAddr, UdpSocket};
-use traits::RandomElement;
+use parser::resources::{DnsClass, QueryClass, QueryType};
+use resolver::{Resolver, ResolverConfig};
+use std::io::{Error, ErrorKind, Result};
+use std::net::{SocketAddr, TcpListener, UdpSocket};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use std::{process, thread};
+use tcp::TcpConfig;
+use workers::WorkerPool;
 
 use crate::root_servers::get_root_servers;
 
-fn lookup(query: &DnsQuery, server: (IpAddr, u16)) -> Result<DnsPacket> {
-    // 0 as the port means that the OS will pick a port for us
-    let socket = UdpSocket::bind(("0.0.0.0", 0))?;
+// TTL of the synthesized HINFO record answering ANY queries
+const ANY_RESPONSE_TTL: u32 = 3789;
 
-    let mut packet = DnsPacket::new();
-    packet.add_query(query.clone());
-
-    let bytes = match packet.serialize() {
-        Ok(b) => b,
-        Err(_e) => todo!(),
-    };
-
-    socket.send_to(&bytes[..], server)?;
-
-    let mut buffer = [0; 512];
-    let (size, _) = socket.recv_from(&mut buffer)?;
-
-    match DnsPacket::parse(&buffer[..size]) {
-        Ok(packet) => Ok(packet),
-        Err(_e) => todo!(),
+// Minimal response to ANY queries so the server can't be used for amplification
+// https://datatracker.ietf.org/doc/html/rfc8482#section-4.2
+fn any_response(query: &DnsQuery) -> DnsRecord {
+    DnsRecord::HINFO {
+        name: query.name.clone(),
+        class: match query.class {
+            QueryClass::Class(class) => class,
+            _ => DnsClass::IN,
+        },
+        ttl: ANY_RESPONSE_TTL,
+        cpu: b"RFC8482".to_vec(),
+        os: Vec::new(),
     }
 }
 
-fn recursive_lookup(
-    opts: &Opts,
-    root_servers: &[DnsRecord],
-    query: &DnsQuery,
-) -> Result<DnsPacket> {
-    let mut server = root_servers.get_random_element().unwrap();
+// Builds the response to a request, resolving its queries
+fn handle_request(resolver: &Arc<Resolver>, request: &DnsPacket) -> DnsPacket {
+    let mut response = request.reply();
+    response.header.set_flags(DnsHeaderFlags {
+        response: true,
+        recdesired: true,
+        recavail: true,
+        ..Default::default()
+    });
 
-    loop {
-        if opts.verbose {
-            println!(
-                "Trying to resolve {:?} using {:?}",
-                query.name,
-                server.get_name()
-            );
+    for query in request.queries() {
+        match query.record_type {
+            QueryType::Record(_) => match resolver.resolve(query) {
+                Ok(res) => {
+                    if res.header.flags.rcode != ReplyCode::NoError {
+                        response.header.flags.rcode = res.header.flags.rcode;
+                    }
+                    response.add_answers(res.answers().to_owned());
+                    for record in res.additional_records() {
+                        match record {
+                            // Extended errors of the resolution, e.g. stale answers
+                            DnsRecord::OPT { options, .. } => {
+                                for option in options {
+                                    response.add_edns_option(option.clone());
+                                }
+                            }
+                            _ => response.add_record(record.clone()),
+                        }
+                    }
+                    response.add_authorities(res.authorities().to_owned());
+                }
+                Err(_) => response.header.flags.rcode = ReplyCode::ServerFailure,
+            },
+            QueryType::ANY => response.add_answer(any_response(query)),
+            // Zone transfers and mailbox queries are not supported by a recursive server, nor are
+            // the types it can't cache
+            QueryType::IXFR
+            | QueryType::AXFR
+            | QueryType::MAILA
+            | QueryType::MAILB
+            | QueryType::Unknown(_) => {
+                response.header.flags.rcode = ReplyCode::NotImplemented;
+            }
         }
+    }
+    response
+}
 
-        let response = lookup(query, (server.get_address().unwrap(), 53))?;
+// Answers a message received from a client over either transport
+fn answer(
+    resolver: &Arc<Resolver>,
+    protocol: SocketProtocol,
+    message: &[u8],
+    src: SocketAddr,
+    local: SocketAddr,
+    verbose: bool,
+) -> Option<Vec<u8>> {
+    common::answer(
+        resolver.taps(),
+        protocol,
+        message,
+        src,
+        local,
+        verbose,
+        |request| handle_request(resolver, request),
+    )
+}
 
-        // We found the address we were looking for
-        if !response.responses().is_empty() && response.header.flags.rcode == ReplyCode::NoError {
-            return Ok(response);
-        }
+fn handle_datagram(
+    socket: &UdpSocket,
+    resolver: &Arc<Resolver>,
+    datagram: &[u8],
+    src: SocketAddr,
+    verbose: bool,
+) -> Result<()> {
+    let local = socket.local_addr()?;
+    if let Some(bytes) = answer(resolver, SocketProtocol::Udp, datagram, src, local, verbose) {
+        socket.send_to(&bytes, src)?;
+    }
+    Ok(())
+}
 
-        if let Some(next_server) = response.additional_records().get_random_element() {
-            server = next_server;
-            continue;
+// Serves the queries received over TCP from another thread
+fn listen_tcp(resolver: &Arc<Resolver>, opts: &Opts, pool: &WorkerPool) -> Result<()> {
+    let listener = TcpListener::bind(("0.0.0.0", opts.port))?;
+    let local = listener.local_addr()?;
+    let config = TcpConfig {
+        idle_timeout: Duration::from_secs(opts.tcp_idle_timeout),
+        max_connections: opts.max_tcp_connections,
+        max_pending: opts.max_tcp_pending,
+    };
+    let resolver = Arc::clone(resolver);
+    let verbose = opts.verbose;
+    let handler = Arc::new(move |message: &[u8], src| {
+        answer(&resolver, SocketProtocol::Tcp, message, src, local, verbose)
+    });
+    let pool = pool.clone();
+    thread::spawn(move || {
+        if let Err(e) = tcp::serve(listener, config, handler, pool) {
+            println!("Error listening over TCP: {}", e);
         }
+    });
+    Ok(())
+}
 
-        let unresolved_ns = match response.authorities().get_random_element() {
-            Some(ns) => ns,
-            None => return Ok(response),
-        };
+// Saves the cache periodically, and once more on shutdown
+fn save_snapshots(resolver: &Arc<Resolver>, path: PathBuf, interval: Duration) -> Result<()> {
+    let save = move |resolver: &Resolver| match resolver.save_cache(&path) {
+        Ok(saved) => println!("Saved {} RRsets to {}", saved, path.display()),
+        Err(e) => println!("Error saving cache snapshot: {}", e),
+    };
+    let periodic = Arc::clone(resolver);
+    let on_shutdown = save.clone();
+    thread::spawn(move || loop {
+        thread::sleep(interval);
+        save(&periodic);
+    });
 
-        let recursive_response = recursive_lookup(
-            opts,
-            root_servers,
-            &DnsQuery {
-                name: unresolved_ns.get_name().to_owned(),
-                class: DnsClass::IN,
-                record_type: DnsRecordType::A,
-            },
-        )?;
+    let resolver = Arc::clone(resolver);
+    ctrlc::set_handler(move || {
+        on_shutdown(&resolver);
+        process::exit(0);
+    })
+    .map_err(|e| Error::other(e.to_string()))
+}
 
-        if let Some(next_server) = recursive_response.additional_records().get_random_element() {
-            server = next_server;
-        } else {
-            return Ok(response);
-        }
-    }
+// Reports the cache statistics periodically
+fn report_stats(resolver: &Arc<Resolver>, interval: Duration) {
+    let resolver = Arc::clone(resolver);
+    thread::spawn(move || loop {
+        thread::sleep(interval);
+        println!("{}", resolver.cache_stats());
+    });
 }
 
 fn main() -> Result<()> {
-    let root_servers = get_root_servers();
     let opts = Opts::parse();
     let socket = UdpSocket::bind(("0.0.0.0", opts.port))?;
+    let taps = Taps::open(
+        opts.capture.as_deref(),
+        opts.dnstap.as_deref(),
+        opts.dnstap_socket.as_deref(),
+    )?;
+    let cache = Cache::new(CacheLimits {
+        max_bytes: opts.cache_size,
+        max_entries: opts.cache_entries,
+        max_stale: Duration::from_secs(opts.stale_window),
+    });
+    let config = ResolverConfig {
+        stale_ttl: opts.stale_ttl,
+        prefetch_hits: opts.prefetch_hits,
+        prefetch_rate: opts.prefetch_rate,
+        prefetch_workers: opts.prefetch_workers,
+        max_recursions: opts.max_recursions,
+        upstream_timeout: Duration::from_millis(opts.upstream_timeout),
+        upstream_retries: opts.upstream_retries,
+        max_upstream_queries: opts.max_upstream_queries,
+        max_delegation_depth: opts.max_delegation_depth,
+        max_ns_per_referral: opts.max_ns_per_referral,
+        resolution_timeout: Duration::from_secs(opts.resolution_timeout),
+        qname_minimisation: !opts.no_qname_minimisation,
+        strict_qname_minimisation: opts.strict_qname_minimisation,
+        case_randomization: !opts.no_case_randomization,
+        verbose: opts.verbose,
+    };
+    let resolver = Arc::new(Resolver::new(
+        get_root_servers().to_vec(),
+        cache,
+        taps,
+        config,
+    ));
+    resolver.spawn_prefetch_workers();
+    if let Some(path) = &opts.cache_snapshot {
+        match resolver.load_cache(path) {
+            Ok(restored) => println!("Restored {} RRsets from {}", restored, path.display()),
+            // Nothing was saved yet
+            Err(e) if e.kind() == ErrorKind::NotFound => {}
+            Err(e) => println!("Error loading cache snapshot: {}", e),
+        }
+        let interval = Duration::from_secs(opts.snapshot_interval);
+        save_snapshots(&resolver, path.clone(), interval)?;
+    }
+    let pool = WorkerPool::new(opts.workers, opts.queue_size)?;
+    if opts.stats_interval > 0 {
+        report_stats(&resolver, Duration::from_secs(opts.stats_interval));
+    }
+    listen_tcp(&resolver, &opts, &pool)?;
     println!("Server listening on port {}", opts.port);
 
+    // Datagrams are answered from the listening socket by a fixed set of workers, and dropped
+    // when too many of them are waiting, the clients retrying
+    let socket = Arc::new(socket);
     loop {
         let mut buffer = [0; 512];
-
         let (size, src) = socket.recv_from(&mut buffer)?;
         if opts.verbose {
             println!("{} bytes received from {}", size, src);
         }
 
-        let request = match DnsPacket::parse(&buffer[..size]) {
-            Ok(packet) => packet,
-            Err(e) => {
-                println!("Error parsing packet: {:?}", e);
-                continue;
+        let socket = Arc::clone(&socket);
+        let resolver = Arc::clone(&resolver);
+        let verbose = opts.verbose;
+        let queued = pool.try_execute(move || {
+            if let Err(e) = handle_datagram(&socket, &resolver, &buffer[..size], src, verbose) {
+                println!("Error answering {}: {}", src, e);
             }
-        };
-
-        if opts.verbose {
-            println!("Received DNS request:\n{:?}", &request);
-        }
-
-        let mut response = request.clone();
-        response.header.set_flags(DnsHeaderFlags {
-            response: true,
-            recdesired: true,
-            recavail: true,
-            ..Default::default()
         });
-
-        for query in request.queries() {
-            if let Ok(res) = recursive_lookup(&opts, &root_servers.to_vec(), &query) {
-                response.add_responses(res.responses().to_owned());
-                response.add_records(res.additional_records().to_owned());
-                response.add_authorities(res.authorities().to_owned());
-            }
-        }
-
-        let bytes = match response.serialize() {
-            Ok(data) => data,
-            Err(e) => {
-                println!("Error serializing packet: {}", e);
-                continue;
-            }
-        };
-
-        socket.send_to(&bytes[..], src)?;
-        if opts.verbose {
-            println!("Sent DNS response:\n{:?}", &response);
+        if !queued && opts.verbose {
+            println!("Dropping the datagram from {}, too many are waiting", src);
         }
     }
 }
@@ -0,0 +1,1329 @@
+use crate::cache::{Cache, CacheKey, CacheStats, CachedAnswer};
+use crate::snapshot;
+use common::{attempt_timeout, receive_response, InFlight, Taps};
+use dnstap::{MessageType, SocketProtocol};
+use parser::header::flags::ReplyCode;
+use parser::packet::{DnsPacket, DEFAULT_UDP_PAYLOAD_SIZE};
+use parser::resources::edns::{EdnsOption, EXTENDED_ERROR_STALE_ANSWER};
+use parser::resources::name::DnsName;
+use parser::resources::query::DnsQuery;
+use parser::resources::record::DnsRecord;
+use parser::resources::{DnsClass, DnsRecordType, QueryClass, QueryType};
+use rand::seq::SliceRandom;
+use std::cell::Cell;
+use std::collections::{HashMap, HashSet};
+use std::io::{Error, ErrorKind, Result};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::path::Path;
+use std::sync::atomic::AtomicUsize;
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+// Stale answers are refreshed at most this often while their authorities can't be reached
+// https://datatracker.ietf.org/doc/html/rfc8767#section-5
+const STALE_RETRY_INTERVAL: Duration = Duration::from_secs(30);
+
+// At most this many minimised queries are sent for a name before asking for the full name, the
+// labels being added one by one for the first ones and then in bigger steps
+// https://datatracker.ietf.org/doc/html/rfc9156#section-2.3
+const MAX_MINIMISE_COUNT: usize = 10;
+const MINIMISE_ONE_LAB: usize = 4;
+
+// Aliases followed at most from the name asked for, as a chain could go on through many zones
+const MAX_ALIAS_CHAIN: usize = 8;
+
+// Prefetches waiting for a worker, past which they are dropped
+const PREFETCH_QUEUE_SIZE: usize = 64;
+
+// A name server is sent names as asked once it didn't echo their case this many times in a row,
+// as a single response could have been spoofed, and until this long went by since the last time
+const MAX_CASE_MISMATCHES: u32 = 3;
+const CASE_FALLBACK_TTL: Duration = Duration::from_secs(60 * 60);
+// Name servers whose mismatches are remembered at most
+const MAX_CASE_SERVERS: usize = 1024;
+
+pub const DEFAULT_STALE_TTL: u32 = 30;
+pub const DEFAULT_PREFETCH_HITS: u32 = 3;
+pub const DEFAULT_PREFETCH_RATE: u32 = 10;
+pub const DEFAULT_PREFETCH_WORKERS: usize = 2;
+pub const DEFAULT_MAX_RECURSIONS: usize = 100;
+pub const DEFAULT_UPSTREAM_TIMEOUT: Duration = Duration::from_millis(1500);
+pub const DEFAULT_UPSTREAM_RETRIES: u32 = 2;
+pub const DEFAULT_MAX_UPSTREAM_QUERIES: u32 = 64;
+pub const DEFAULT_MAX_DELEGATION_DEPTH: usize = 16;
+pub const DEFAULT_MAX_NS_PER_REFERRAL: usize = 3;
+pub const DEFAULT_RESOLUTION_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone)]
+pub struct ResolverConfig {
+    // TTL of the answers served past their expiry
+    pub stale_ttl: u32,
+    // Hits that make an entry popular enough to be refreshed before it expires
+    pub prefetch_hits: u32,
+    // Prefetches started per second at most, 0 disabling them
+    pub prefetch_rate: u32,
+    pub prefetch_workers: usize,
+    // Recursions in flight at most, including the ones resolving name servers
+    pub max_recursions: usize,
+    // How long a name server has to answer during the first round over the candidates
+    pub upstream_timeout: Duration,
+    // Rounds over the candidates after the first one, each doubling the timeout
+    pub upstream_retries: u32,
+    // Queries sent to name servers at most to answer a client query, including the ones
+    // resolving aliases and name servers
+    pub max_upstream_queries: u32,
+    // Referrals followed at most from the root, counting the ones resolving name servers
+    pub max_delegation_depth: usize,
+    // Names of name servers resolved at most for a referral that came without glue
+    pub max_ns_per_referral: usize,
+    // How long the resolution of a client query may take at most
+    pub resolution_timeout: Duration,
+    // Whether only the next label of the name is sent to the servers above its zone
+    pub qname_minimisation: bool,
+    // Whether the failures of minimised queries are final, instead of sending the full name
+    pub strict_qname_minimisation: bool,
+    // Whether the case of the names sent to name servers is randomized, then checked in responses
+    pub case_randomization: bool,
+    pub verbose: bool,
+}
+
+impl Default for ResolverConfig {
+    fn default() -> Self {
+        Self {
+            stale_ttl: DEFAULT_STALE_TTL,
+            prefetch_hits: DEFAULT_PREFETCH_HITS,
+            prefetch_rate: DEFAULT_PREFETCH_RATE,
+            prefetch_workers: DEFAULT_PREFETCH_WORKERS,
+            max_recursions: DEFAULT_MAX_RECURSIONS,
+            upstream_timeout: DEFAULT_UPSTREAM_TIMEOUT,
+            upstream_retries: DEFAULT_UPSTREAM_RETRIES,
+            max_upstream_queries: DEFAULT_MAX_UPSTREAM_QUERIES,
+            max_delegation_depth: DEFAULT_MAX_DELEGATION_DEPTH,
+            max_ns_per_referral: DEFAULT_MAX_NS_PER_REFERRAL,
+            resolution_timeout: DEFAULT_RESOLUTION_TIMEOUT,
+            qname_minimisation: true,
+            strict_qname_minimisation: false,
+            case_randomization: true,
+            verbose: false,
+        }
+    }
+}
+
+// Token bucket refilled with `rate` tokens per second, holding a second worth of them
+struct RateLimiter {
+    rate: f64,
+    tokens: f64,
+    last: Instant,
+}
+
+impl RateLimiter {
+    fn new(rate: u32) -> Self {
+        Self {
+            rate: rate as f64,
+            tokens: rate as f64,
+            last: Instant::now(),
+        }
+    }
+
+    fn try_acquire(&mut self, now: Instant) -> bool {
+        let elapsed = now.saturating_duration_since(self.last).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.rate.max(1.0));
+        self.last = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+// Name servers that didn't echo the case of the names sent to them, each forgotten once its
+// mismatches are older than CASE_FALLBACK_TTL
+struct CaseMismatches {
+    servers: HashMap<IpAddr, (u32, Instant)>,
+    capacity: usize,
+}
+
+impl CaseMismatches {
+    fn new(capacity: usize) -> Self {
+        Self {
+            servers: HashMap::new(),
+            capacity,
+        }
+    }
+
+    // Whether the server is sent names as asked
+    fn is_insensitive(&self, server: IpAddr, now: Instant) -> bool {
+        match self.servers.get(&server) {
+            Some(&(count, expires)) => count >= MAX_CASE_MISMATCHES && now < expires,
+            None => false,
+        }
+    }
+
+    fn mismatch(&mut self, server: IpAddr, now: Instant) {
+        if !self.servers.contains_key(&server) && self.servers.len() >= self.capacity {
+            self.servers.retain(|_, &mut (_, expires)| now < expires);
+        }
+        if !self.servers.contains_key(&server) && self.servers.len() >= self.capacity {
+            // The server that would be forgotten first makes room otherwise
+            let oldest = self
+                .servers
+                .iter()
+                .min_by_key(|(_, &(_, expires))| expires)
+                .map(|(&server, _)| server);
+            if let Some(oldest) = oldest {
+                self.servers.remove(&oldest);
+            }
+        }
+
+        let (count, expires) = self.servers.entry(server).or_insert((0, now));
+        if *expires <= now {
+            *count = 0;
+        }
+        *count = count.saturating_add(1);
+        *expires = now + CASE_FALLBACK_TTL;
+    }
+
+    // The server echoed the case, so the mismatches it had weren't in a row
+    fn matched(&mut self, server: IpAddr) {
+        self.servers.remove(&server);
+    }
+}
+
+// Connecting a UDP socket only picks a route, so nothing is sent to the address of the root server
+fn has_ipv6_route() -> bool {
+    let root = SocketAddr::new(
+        IpAddr::V6(Ipv6Addr::new(0x2001, 0x503, 0xba3e, 0, 0, 0, 2, 0x30)),
+        53,
+    );
+    UdpSocket::bind((Ipv6Addr::UNSPECIFIED, 0))
+        .and_then(|socket| socket.connect(root))
+        .is_ok()
+}
+
+type Prefetch = (DnsQuery, CacheKey);
+
+// Work a client query may cause, shared by the lookups of the aliases and name servers it needs,
+// so that circular delegations end and that referrals to many names can't be used to flood a
+// victim with queries (NXNS attack)
+// https://www.usenix.org/conference/usenixsecurity20/presentation/afek
+struct Budget {
+    queries: Cell<u32>,
+    deadline: Instant,
+}
+
+impl Budget {
+    fn new(config: &ResolverConfig) -> Self {
+        Self {
+            queries: Cell::new(config.max_upstream_queries),
+            deadline: Instant::now() + config.resolution_timeout,
+        }
+    }
+
+    // Takes a query from the budget, returning how long it may wait for its answer
+    fn spend(&self, now: Instant) -> Result<Duration> {
+        let remaining = self.deadline.saturating_duration_since(now);
+        if self.queries.get() == 0 || remaining.is_zero() {
+            return Err(Error::other("Query budget exceeded"));
+        }
+        self.queries.set(self.queries.get() - 1);
+        Ok(remaining)
+    }
+}
+
+pub struct Resolver {
+    root_servers: Vec<DnsRecord>,
+    cache: Mutex<Cache>,
+    taps: Taps,
+    config: ResolverConfig,
+    // Entries queued or being refreshed by the workers, stale or prefetched
+    refreshing: Mutex<HashSet<CacheKey>>,
+    prefetches: SyncSender<Prefetch>,
+    prefetch_queue: Arc<Mutex<Receiver<Prefetch>>>,
+    prefetch_rate: Mutex<RateLimiter>,
+    in_flight: AtomicUsize,
+    // Name servers that don't echo the case of the names, which are sent to them as asked
+    case_mismatches: Mutex<CaseMismatches>,
+    // Whether name servers can be reached over IPv6, their IPv6 addresses being skipped otherwise
+    ipv6: bool,
+}
+
+fn cache_key(query: &DnsQuery) -> Option<CacheKey> {
+    match (query.record_type, query.class) {
+        (QueryType::Record(record_type), QueryClass::Class(class)) => {
+            Some(CacheKey::new(&query.name, record_type, class))
+        }
+        _ => None,
+    }
+}
+
+fn cached_response(query: &DnsQuery, cached: CachedAnswer) -> DnsPacket {
+    let mut response = DnsPacket::new();
+    response.add_query(query.clone());
+    // Negative answers carry the SOA of the zone that denied the name
+    // https://datatracker.ietf.org/doc/html/rfc2308#section-6
+    match cached {
+        CachedAnswer::Records(answers) => response.add_answers(answers),
+        CachedAnswer::NoData(soa) => response.add_authority(soa),
+        CachedAnswer::NxDomain(soa) => {
+            response.header.flags.rcode = ReplyCode::NameError;
+            response.add_authority(soa);
+        }
+    }
+    response
+}
+
+// The name or the type doesn't exist, the SOA in the authority section tells so
+fn is_negative(response: &DnsPacket) -> bool {
+    response.header.flags.rcode == ReplyCode::NameError
+        || response
+            .authorities()
+            .iter()
+            .any(|r| r.get_type() == Some(DnsRecordType::SOA))
+}
+
+fn has_rrset(response: &DnsPacket, name: &DnsName, record_type: DnsRecordType) -> bool {
+    response
+        .answers()
+        .iter()
+        .any(|r| r.get_type() == Some(record_type) && r.get_name().eq_ignore_case(name))
+}
+
+// The next link of a chain of aliases from `name`: the DNAME of one of its ancestors along with
+// the CNAME synthesized from it, or its own CNAME
+// https://datatracker.ietf.org/doc/html/rfc6672#section-3.2
+fn follow_alias(
+    answers: &[DnsRecord],
+    name: &DnsName,
+    class: DnsClass,
+) -> Option<(Vec<DnsRecord>, DnsName)> {
+    let dname = answers.iter().find_map(|record| match record {
+        DnsRecord::DNAME {
+            name: owner,
+            ttl,
+            target,
+            ..
+        } => {
+            let canonical_name = name.rebase(owner, target)?;
+            let synthesized = DnsRecord::CNAME {
+                name: name.clone(),
+                class,
+                ttl: *ttl,
+                canonical_name: canonical_name.clone(),
+            };
+            Some((vec![record.clone(), synthesized], canonical_name))
+        }
+        _ => None,
+    });
+    dname.or_else(|| {
+        answers.iter().find_map(|record| match record {
+            DnsRecord::CNAME {
+                name: owner,
+                canonical_name,
+                ..
+            } if owner.eq_ignore_case(name) => Some((vec![record.clone()], canonical_name.clone())),
+            _ => None,
+        })
+    })
+}
+
+// Drops the records of the names the server isn't authoritative for, so that it can't poison the
+// cache with the data of other zones
+// https://datatracker.ietf.org/doc/html/rfc2181#section-5.4.1
+fn scrub(response: &mut DnsPacket, zone: &DnsName) {
+    let in_bailiwick = |r: &DnsRecord| {
+        r.get_type() == Some(DnsRecordType::OPT) || r.get_name().is_subdomain_of(zone)
+    };
+    response.answers_mut().retain(in_bailiwick);
+    response.authorities_mut().retain(in_bailiwick);
+    response.additional_records_mut().retain(in_bailiwick);
+}
+
+// A delegation from the zone of a server to a zone below it, closer to the name asked for
+// https://datatracker.ietf.org/doc/html/rfc1034#section-4.3.2
+#[derive(Debug, PartialEq)]
+struct Referral {
+    zone: DnsName,
+    name_servers: Vec<DnsName>,
+    // Addresses of the name servers given along with the delegation
+    glue: Vec<IpAddr>,
+}
+
+fn referral(response: &DnsPacket, zone: &DnsName, name: &DnsName) -> Option<Referral> {
+    let cut = response.authorities().iter().find_map(|r| match r {
+        DnsRecord::NS { name: cut, .. }
+            if cut.labels().len() > zone.labels().len()
+                && cut.is_subdomain_of(zone)
+                && name.is_subdomain_of(cut) =>
+        {
+            Some(cut.clone())
+        }
+        _ => None,
+    })?;
+    let name_servers: Vec<DnsName> = response
+        .authorities()
+        .iter()
+        .filter_map(|r| match r {
+            DnsRecord::NS {
+                name, name_server, ..
+            } if name.eq_ignore_case(&cut) => Some(name_server.clone()),
+            _ => None,
+        })
+        .collect();
+    // Only the addresses of the name servers of the delegation are trusted, and only within the
+    // zone of the server that gave them
+    let glue = response
+        .additional_records()
+        .iter()
+        .filter(|r| r.get_name().is_subdomain_of(zone))
+        .filter(|r| {
+            name_servers
+                .iter()
+                .any(|ns| ns.eq_ignore_case(r.get_name()))
+        })
+        .filter_map(|r| r.get_address())
+        .collect();
+
+    Some(Referral {
+        zone: cut,
+        name_servers,
+        glue,
+    })
+}
+
+// The name asked instead of `name` to the servers of a zone, given the labels of it known not to
+// lead to another zone cut and the number of minimised queries already sent
+fn minimised_name(name: &DnsName, known: usize, iterations: usize) -> DnsName {
+    let labels = name.labels();
+    let remaining = labels.len().saturating_sub(known);
+    let step = if iterations < MINIMISE_ONE_LAB {
+        1
+    } else if iterations < MAX_MINIMISE_COUNT {
+        remaining.div_ceil(MAX_MINIMISE_COUNT - iterations)
+    } else {
+        remaining
+    };
+    let count = (known + step).min(labels.len());
+    DnsName::from(labels[labels.len() - count..].to_vec())
+}
+
+fn is_failure(result: &Result<DnsPacket>) -> bool {
+    match result {
+        Ok(response) => response.header.flags.rcode == ReplyCode::ServerFailure,
+        Err(_) => true,
+    }
+}
+
+// The name server couldn't answer, so another one should be asked
+// https://datatracker.ietf.org/doc/html/rfc1035#section-7.2
+fn is_lame(result: &Result<DnsPacket>) -> bool {
+    match result {
+        Ok(response) => response.header.flags.rcode == ReplyCode::Refused || is_failure(result),
+        Err(_) => true,
+    }
+}
+
+impl Resolver {
+    pub fn new(
+        root_servers: Vec<DnsRecord>,
+        cache: Cache,
+        taps: Taps,
+        config: ResolverConfig,
+    ) -> Self {
+        let (prefetches, prefetch_queue) = mpsc::sync_channel(PREFETCH_QUEUE_SIZE);
+        Self {
+            root_servers,
+            cache: Mutex::new(cache),
+            taps,
+            prefetch_rate: Mutex::new(RateLimiter::new(config.prefetch_rate)),
+            config,
+            refreshing: Mutex::new(HashSet::new()),
+            prefetches,
+            prefetch_queue: Arc::new(Mutex::new(prefetch_queue)),
+            in_flight: AtomicUsize::new(0),
+            case_mismatches: Mutex::new(CaseMismatches::new(MAX_CASE_SERVERS)),
+            ipv6: has_ipv6_route(),
+        }
+    }
+
+    // Workers resolving the popular entries that are about to expire, and the stale ones
+    pub fn spawn_prefetch_workers(self: &Arc<Self>) {
+        for _ in 0..self.config.prefetch_workers {
+            let resolver = Arc::clone(self);
+            thread::spawn(move || loop {
+                let next = resolver.prefetch_queue.lock().unwrap().recv();
+                let (query, key) = match next {
+                    Ok(prefetch) => prefetch,
+                    Err(_) => break,
+                };
+                if resolver.config.verbose {
+                    println!("Refreshing {:?}", query.name);
+                }
+                // Failures are left to the next lookup
+                let _ = resolver.recursive_lookup(&query, &Budget::new(&resolver.config), 0);
+                resolver.refreshing.lock().unwrap().remove(&key);
+            });
+        }
+    }
+
+    fn prefetch(&self, query: &DnsQuery, key: &CacheKey) {
+        if self.refreshing.lock().unwrap().contains(key) {
+            return;
+        }
+        let allowed = self
+            .prefetch_rate
+            .lock()
+            .unwrap()
+            .try_acquire(Instant::now());
+        if allowed {
+            self.refresh(query, key);
+        }
+    }
+
+    // Queues a lookup for the workers unless one is already pending for the entry, the lookup
+    // being dropped when the queue is full
+    fn refresh(&self, query: &DnsQuery, key: &CacheKey) {
+        if !self.refreshing.lock().unwrap().insert(key.clone()) {
+            return;
+        }
+        if self
+            .prefetches
+            .try_send((query.clone(), key.clone()))
+            .is_err()
+        {
+            self.refreshing.lock().unwrap().remove(key);
+        }
+    }
+
+    pub fn taps(&self) -> &Taps {
+        &self.taps
+    }
+
+    pub fn cache_stats(&self) -> CacheStats {
+        self.cache.lock().unwrap().stats()
+    }
+
+    // Returns the number of RRsets saved
+    pub fn save_cache(&self, path: &Path) -> Result<usize> {
+        let entries = self
+            .cache
+            .lock()
+            .unwrap()
+            .snapshot(Instant::now(), SystemTime::now());
+        snapshot::save(path, &entries)?;
+        Ok(entries.len())
+    }
+
+    // Returns the number of RRsets restored, those that expired since the save are dropped
+    pub fn load_cache(&self, path: &Path) -> Result<usize> {
+        let entries = snapshot::load(path)?;
+        let mut cache = self.cache.lock().unwrap();
+        Ok(cache.restore(entries, Instant::now(), SystemTime::now()))
+    }
+
+    // Exceeding the budget of the query is a failure, answered with SERVFAIL
+    pub fn resolve(self: &Arc<Self>, query: &DnsQuery) -> Result<DnsPacket> {
+        self.resolve_within(query, &Budget::new(&self.config), 0)
+    }
+
+    fn resolve_within(
+        self: &Arc<Self>,
+        query: &DnsQuery,
+        budget: &Budget,
+        depth: usize,
+    ) -> Result<DnsPacket> {
+        let key = cache_key(query);
+        if let Some(key) = &key {
+            let (cached, prefetch) = {
+                let now = Instant::now();
+                let mut cache = self.cache.lock().unwrap();
+                let cached = cache.get(key, now);
+                (
+                    cached,
+                    cache.should_prefetch(key, now, self.config.prefetch_hits),
+                )
+            };
+            if let Some(cached) = cached {
+                if self.config.verbose {
+                    println!("Answering {:?} from the cache", query.name);
+                }
+                if prefetch {
+                    self.prefetch(query, key);
+                }
+                return Ok(cached_response(query, cached));
+            }
+        }
+
+        let result = match InFlight::acquire(&self.in_flight, self.config.max_recursions) {
+            Some(_slot) => self.recursive_lookup(query, budget, depth),
+            None => Err(Error::new(
+                ErrorKind::WouldBlock,
+                "Too many recursions in flight",
+            )),
+        };
+        if !is_failure(&result) {
+            return result;
+        }
+
+        // The authorities can't be reached, an expired answer is better than none
+        // https://datatracker.ietf.org/doc/html/rfc8767#section-4
+        let key = match key {
+            Some(key) => key,
+            None => return result,
+        };
+        let stale =
+            self.cache
+                .lock()
+                .unwrap()
+                .get_stale(&key, Instant::now(), self.config.stale_ttl);
+        match stale {
+            Some(stale) => {
+                if self.config.verbose {
+                    println!("Answering {:?} with a stale answer", query.name);
+                }
+                self.refresh_stale(query, &key);
+
+                let mut response = cached_response(query, stale);
+                response.add_record(DnsRecord::OPT {
+                    name: DnsName::root(),
+                    payload_size: DEFAULT_UDP_PAYLOAD_SIZE as u16,
+                    extended_rcode: 0,
+                    version: 0,
+                    dnssec_ok: false,
+                    options: vec![EdnsOption::extended_error(EXTENDED_ERROR_STALE_ANSWER, "")],
+                });
+                Ok(response)
+            }
+            None => result,
+        }
+    }
+
+    // Queues a lookup refreshing a stale answer, retried at most every STALE_RETRY_INTERVAL
+    // while it is served
+    fn refresh_stale(&self, query: &DnsQuery, key: &CacheKey) {
+        let due = self
+            .cache
+            .lock()
+            .unwrap()
+            .refresh_due(key, Instant::now(), STALE_RETRY_INTERVAL);
+        if due {
+            self.refresh(query, key);
+        }
+    }
+
+    // Asks the name servers in turn until one of them answers, going over all of them again
+    // with a longer timeout once they all failed
+    // https://datatracker.ietf.org/doc/html/rfc1035#section-7.2
+    fn lookup_any(
+        &self,
+        query: &DnsQuery,
+        zone: &DnsName,
+        servers: &[IpAddr],
+        budget: &Budget,
+    ) -> Result<DnsPacket> {
+        let mut servers = self.reachable(servers);
+        servers.shuffle(&mut rand::thread_rng());
+
+        let mut result = Err(Error::new(ErrorKind::NotFound, "No name server to ask"));
+        for round in 0..=self.config.upstream_retries {
+            let timeout = attempt_timeout(self.config.upstream_timeout, round);
+            for &server in &servers {
+                if self.config.verbose {
+                    println!("Trying to resolve {:?} using {}", query.name, server);
+                }
+                let remaining = budget.spend(Instant::now())?;
+                result = self.lookup(query, zone, (server, 53), timeout.min(remaining));
+                if !is_lame(&result) {
+                    return result;
+                }
+                if self.config.verbose {
+                    println!("{} failed to answer {:?}", server, query.name);
+                }
+            }
+        }
+        result
+    }
+
+    // The case of the name is randomized so that spoofed responses also have to guess it. The
+    // name servers that don't echo it are asked again with the name as is, and from then on
+    // once they didn't echo it repeatedly
+    // https://datatracker.ietf.org/doc/html/draft-vixie-dnsext-dns0x20-00#section-5
+    fn lookup(
+        &self,
+        query: &DnsQuery,
+        zone: &DnsName,
+        server: (IpAddr, u16),
+        timeout: Duration,
+    ) -> Result<DnsPacket> {
+        let server = SocketAddr::from(server);
+        let randomize = self.config.case_randomization
+            && !self
+                .case_mismatches
+                .lock()
+                .unwrap()
+                .is_insensitive(server.ip(), Instant::now());
+        let sent = if randomize {
+            DnsQuery {
+                name: query.name.randomize_case(),
+                ..query.clone()
+            }
+        } else {
+            query.clone()
+        };
+
+        let mut response = self.exchange(&sent, server, timeout)?;
+        if randomize {
+            let mut mismatches = self.case_mismatches.lock().unwrap();
+            if response.queries().iter().any(|q| q.name != sent.name) {
+                if self.config.verbose {
+                    println!("{} doesn't preserve the case of names", server);
+                }
+                mismatches.mismatch(server.ip(), Instant::now());
+                drop(mismatches);
+                response = self.exchange(query, server, timeout)?;
+            } else {
+                mismatches.matched(server.ip());
+            }
+        }
+        scrub(&mut response, zone);
+
+        let now = Instant::now();
+        let mut cache = self.cache.lock().unwrap();
+        cache.remove_expired(now);
+        cache.insert_response(&response, now);
+        if self.config.verbose {
+            println!("{}", cache.stats());
+        }
+        Ok(response)
+    }
+
+    fn exchange(
+        &self,
+        query: &DnsQuery,
+        server: SocketAddr,
+        timeout: Duration,
+    ) -> Result<DnsPacket> {
+        // 0 as the port means that the OS will pick a port for us, the socket being of the
+        // family of the server
+        let local: IpAddr = match server {
+            SocketAddr::V4(_) => Ipv4Addr::UNSPECIFIED.into(),
+            SocketAddr::V6(_) => Ipv6Addr::UNSPECIFIED.into(),
+        };
+        let socket = UdpSocket::bind((local, 0))?;
+
+        let mut packet = DnsPacket::new();
+        packet.add_query(query.clone());
+
+        let mut bytes = [0; DEFAULT_UDP_PAYLOAD_SIZE];
+        let size = packet.write_to(&mut bytes).map_err(|e| {
+            Error::new(
+                ErrorKind::InvalidInput,
+                format!("Error serializing query: {}", e),
+            )
+        })?;
+
+        socket.send_to(&bytes[..size], server)?;
+        let local = socket.local_addr()?;
+        self.taps.query(
+            SocketProtocol::Udp,
+            MessageType::ResolverQuery,
+            local,
+            server,
+            &bytes[..size],
+        );
+
+        receive_response(&socket, server, &packet, timeout, |bytes| {
+            self.taps.response(
+                SocketProtocol::Udp,
+                MessageType::ResolverResponse,
+                local,
+                server,
+                bytes,
+            )
+        })
+    }
+
+    // The addresses of name servers that can be asked from the families the host has a route for
+    fn reachable(&self, servers: &[IpAddr]) -> Vec<IpAddr> {
+        servers
+            .iter()
+            .filter(|server| server.is_ipv4() || self.ipv6)
+            .copied()
+            .collect()
+    }
+
+    // Starts from the closest zone cut whose name servers are cached, or from the root
+    fn first_servers(&self, query: &DnsQuery) -> (DnsName, Vec<IpAddr>) {
+        let class = match query.class {
+            QueryClass::Class(class) => class,
+            _ => DnsClass::IN,
+        };
+        let cached =
+            self.cache
+                .lock()
+                .unwrap()
+                .closest_name_servers(&query.name, class, Instant::now());
+
+        // Name servers that can't be reached are no closer than the root
+        let cached = cached.filter(|(_, addresses)| !self.reachable(addresses).is_empty());
+        cached.unwrap_or_else(|| {
+            let addresses = self
+                .root_servers
+                .iter()
+                .filter_map(|server| server.get_address())
+                .collect();
+            (DnsName::root(), addresses)
+        })
+    }
+
+    // Follows the aliases of the answer until the RRset asked for, so that the response holds
+    // the whole chain followed by the final answer
+    // https://datatracker.ietf.org/doc/html/rfc1034#section-4.3.2
+    fn recursive_lookup(
+        self: &Arc<Self>,
+        query: &DnsQuery,
+        budget: &Budget,
+        depth: usize,
+    ) -> Result<DnsPacket> {
+        let mut response = self.iterative_lookup(query, budget, depth)?;
+        let (record_type, class) = match (query.record_type, query.class) {
+            (QueryType::Record(record_type), QueryClass::Class(class)) => (record_type, class),
+            _ => return Ok(response),
+        };
+
+        let mut chain = Vec::new();
+        let mut name = query.name.clone();
+        let mut seen = vec![name.to_lowercase()];
+        loop {
+            // A response often holds several links of the chain, when they are in the same zone
+            let links = chain.len();
+            while !has_rrset(&response, &name, record_type) {
+                let (records, target) = match follow_alias(response.answers(), &name, class) {
+                    Some(link) => link,
+                    None => break,
+                };
+                let target_key = target.to_lowercase();
+                if seen.contains(&target_key) || seen.len() > MAX_ALIAS_CHAIN {
+                    return Err(Error::new(
+                        ErrorKind::InvalidData,
+                        format!("Alias chain of {:?} loops or is too long", query.name),
+                    ));
+                }
+                seen.push(target_key);
+                chain.extend(records);
+                name = target;
+            }
+
+            // The rcode and the SOA of a negative answer are about the last name of the chain
+            // https://datatracker.ietf.org/doc/html/rfc6604#section-2
+            if chain.len() == links || has_rrset(&response, &name, record_type) {
+                break;
+            }
+            if is_negative(&response) {
+                break;
+            }
+            if self.config.verbose {
+                println!("Following the alias of {:?} to {:?}", query.name, name);
+            }
+            let target = DnsQuery {
+                name: name.clone(),
+                ..query.clone()
+            };
+            response = self.lookup_alias_target(&target, budget, depth)?;
+        }
+        if chain.is_empty() {
+            return Ok(response);
+        }
+
+        let mut assembled = DnsPacket::new();
+        assembled.header = response.header.clone();
+        assembled.add_query(query.clone());
+        assembled.add_answers(chain);
+        // The aliases the authorities synthesized themselves are already in the chain
+        assembled.add_answers(
+            response
+                .answers()
+                .iter()
+                .filter(|r| {
+                    !matches!(
+                        r.get_type(),
+                        Some(DnsRecordType::CNAME) | Some(DnsRecordType::DNAME)
+                    )
+                })
+                .cloned()
+                .collect(),
+        );
+        assembled.add_authorities(response.authorities().to_owned());
+        assembled.add_records(response.additional_records().to_owned());
+        Ok(assembled)
+    }
+
+    // The targets of aliases are often cached already, as many names point to the same hosts
+    fn lookup_alias_target(
+        self: &Arc<Self>,
+        query: &DnsQuery,
+        budget: &Budget,
+        depth: usize,
+    ) -> Result<DnsPacket> {
+        if let Some(key) = cache_key(query) {
+            if let Some(cached) = self.cache.lock().unwrap().get(&key, Instant::now()) {
+                return Ok(cached_response(query, cached));
+            }
+        }
+        self.iterative_lookup(query, budget, depth)
+    }
+
+    // Asks the servers of each zone cut in turn, from the closest one known down to the zone of
+    // the name. Unless disabled, each of them is only asked for the next label of the name, so
+    // that the servers above the zone of the name don't learn it
+    // https://datatracker.ietf.org/doc/html/rfc9156#section-3
+    fn iterative_lookup(
+        self: &Arc<Self>,
+        query: &DnsQuery,
+        budget: &Budget,
+        mut depth: usize,
+    ) -> Result<DnsPacket> {
+        let (mut zone, mut servers) = self.first_servers(query);
+        let mut minimise = self.config.qname_minimisation;
+        // Labels of the name that are known not to lead to another zone cut
+        let mut known = zone.labels().len();
+        let mut iterations = 0;
+
+        loop {
+            let child = minimised_name(&query.name, known, iterations);
+            if minimise && child.labels().len() < query.name.labels().len() {
+                iterations += 1;
+                let minimised = DnsQuery {
+                    name: child,
+                    // https://datatracker.ietf.org/doc/html/rfc9156#section-2.1
+                    record_type: DnsRecordType::A.into(),
+                    class: query.class,
+                };
+                match self.lookup_any(&minimised, &zone, &servers, budget) {
+                    Ok(response) if response.header.flags.rcode == ReplyCode::NoError => {
+                        match referral(&response, &zone, &query.name) {
+                            Some(referral) => {
+                                depth += 1;
+                                servers = self.descend(&zone, &referral, budget, depth)?;
+                                zone = referral.zone;
+                                known = zone.labels().len();
+                            }
+                            // Not a zone cut, the next label is asked to the same servers
+                            None => known = minimised.name.labels().len(),
+                        }
+                    }
+                    // Nothing exists below a name that doesn't exist
+                    // https://datatracker.ietf.org/doc/html/rfc8020#section-2
+                    Ok(response) if self.config.strict_qname_minimisation => return Ok(response),
+                    Err(e) if self.config.strict_qname_minimisation => return Err(e),
+                    // Some servers fail on the names between zone cuts, they are asked the full
+                    // name instead
+                    // https://datatracker.ietf.org/doc/html/rfc9156#section-2.3
+                    _ => {
+                        if self.config.verbose {
+                            println!("Asking {:?} for the full name {:?}", zone, query.name);
+                        }
+                        minimise = false;
+                    }
+                }
+                continue;
+            }
+
+            let response = self.lookup_any(query, &zone, &servers, budget)?;
+
+            // We found the address we were looking for
+            if !response.answers().is_empty() && response.header.flags.rcode == ReplyCode::NoError {
+                return Ok(response);
+            }
+
+            if is_negative(&response) {
+                return Ok(response);
+            }
+
+            let referral = match referral(&response, &zone, &query.name) {
+                Some(referral) => referral,
+                // A delegation that doesn't lead down towards the name is lame
+                None if response
+                    .authorities()
+                    .iter()
+                    .any(|r| r.get_type() == Some(DnsRecordType::NS)) =>
+                {
+                    return Err(Error::new(
+                        ErrorKind::InvalidData,
+                        format!("Lame delegation from {:?}", zone),
+                    ))
+                }
+                None => return Ok(response),
+            };
+            depth += 1;
+            servers = self.descend(&zone, &referral, budget, depth)?;
+            zone = referral.zone;
+            known = zone.labels().len();
+        }
+    }
+
+    // Follows a referral down to the zone it delegates to, returning the addresses of the name
+    // servers of that zone
+    fn descend(
+        self: &Arc<Self>,
+        zone: &DnsName,
+        referral: &Referral,
+        budget: &Budget,
+        depth: usize,
+    ) -> Result<Vec<IpAddr>> {
+        if self.config.verbose {
+            println!("Referred from {:?} to {:?}", zone, referral.zone);
+        }
+        if depth > self.config.max_delegation_depth {
+            return Err(Error::other(format!(
+                "Delegations to {:?} are too deep",
+                referral.zone
+            )));
+        }
+
+        // Glue of a family that can't be used is as good as none
+        if self.reachable(&referral.glue).is_empty() {
+            self.resolve_name_servers(referral, budget, depth)
+        } else {
+            Ok(referral.glue.clone())
+        }
+    }
+
+    // Resolves the names of the name servers of a delegation that came without glue, until one
+    // of them has an address. Only a few of them are tried, as each one may need many queries
+    fn resolve_name_servers(
+        self: &Arc<Self>,
+        referral: &Referral,
+        budget: &Budget,
+        depth: usize,
+    ) -> Result<Vec<IpAddr>> {
+        let mut name_servers = referral.name_servers.clone();
+        name_servers.shuffle(&mut rand::thread_rng());
+        name_servers.truncate(self.config.max_ns_per_referral);
+
+        for name_server in name_servers {
+            let query = DnsQuery {
+                name: name_server,
+                class: DnsClass::IN.into(),
+                record_type: DnsRecordType::A.into(),
+            };
+            let response = match self.resolve_within(&query, budget, depth) {
+                Ok(response) => response,
+                Err(_) => continue,
+            };
+            let addresses: Vec<IpAddr> = response
+                .answers()
+                .iter()
+                .filter_map(|r| r.get_address())
+                .collect();
+            if !addresses.is_empty() {
+                return Ok(addresses);
+            }
+        }
+        Err(Error::new(
+            ErrorKind::NotFound,
+            format!("No address for the name servers of {:?}", referral.zone),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rate_limiter() {
+        let mut limiter = RateLimiter::new(2);
+        let now = limiter.last;
+        assert!(limiter.try_acquire(now));
+        assert!(limiter.try_acquire(now));
+        assert!(!limiter.try_acquire(now));
+        assert!(limiter.try_acquire(now + Duration::from_millis(500)));
+        assert!(!limiter.try_acquire(now + Duration::from_millis(500)));
+
+        let mut disabled = RateLimiter::new(0);
+        assert!(!disabled.try_acquire(now + Duration::from_secs(60)));
+    }
+
+    // Answers the queries it receives with their name lowercased, as some authorities do
+    fn lowercasing_server() -> SocketAddr {
+        let server = UdpSocket::bind(("127.0.0.1", 0)).unwrap();
+        let address = server.local_addr().unwrap();
+        thread::spawn(move || loop {
+            let mut buffer = [0; 512];
+            let (size, src) = server.recv_from(&mut buffer).unwrap();
+            let request = DnsPacket::parse(&buffer[..size]).unwrap();
+            let mut response = request.reply();
+            response.header.flags.response = true;
+            for query in response.queries_mut() {
+                query.name = query.name.to_lowercase();
+            }
+            let size = response.write_to(&mut buffer).unwrap();
+            server.send_to(&buffer[..size], src).unwrap();
+        });
+        address
+    }
+
+    #[test]
+    fn test_case_randomization_fallback() {
+        let server = lowercasing_server();
+        let resolver = Resolver::new(
+            Vec::new(),
+            Cache::new(Default::default()),
+            Taps {
+                capture: None,
+                dnstap: None,
+            },
+            ResolverConfig::default(),
+        );
+        let query = DnsQuery {
+            name: DnsName::from("www.case-randomization.example.com"),
+            record_type: DnsRecordType::A.into(),
+            class: DnsClass::IN.into(),
+        };
+
+        // The server is sent names as asked only once it didn't echo their case repeatedly
+        let timeout = Duration::from_secs(1);
+        for _ in 0..MAX_CASE_MISMATCHES {
+            let is_insensitive = resolver
+                .case_mismatches
+                .lock()
+                .unwrap()
+                .is_insensitive(server.ip(), Instant::now());
+            assert!(!is_insensitive);
+            let response = resolver
+                .lookup(
+                    &query,
+                    &DnsName::root(),
+                    (server.ip(), server.port()),
+                    timeout,
+                )
+                .unwrap();
+            assert_eq!(query.name, response.queries()[0].name);
+        }
+        assert!(resolver
+            .case_mismatches
+            .lock()
+            .unwrap()
+            .is_insensitive(server.ip(), Instant::now()));
+    }
+
+    #[test]
+    fn test_case_mismatches() {
+        let now = Instant::now();
+        let mut mismatches = CaseMismatches::new(2);
+        let [first, second, third] = [1, 2, 3].map(|i| IpAddr::from([192, 0, 2, i]));
+        for _ in 0..MAX_CASE_MISMATCHES {
+            mismatches.mismatch(first, now);
+        }
+        assert!(mismatches.is_insensitive(first, now));
+        assert!(!mismatches.is_insensitive(first, now + CASE_FALLBACK_TTL));
+
+        // A response echoing the case starts over
+        mismatches.mismatch(second, now);
+        mismatches.matched(second);
+        for _ in 1..MAX_CASE_MISMATCHES {
+            mismatches.mismatch(second, now);
+        }
+        assert!(!mismatches.is_insensitive(second, now));
+
+        // The server that would be forgotten first makes room for another one
+        let later = now + Duration::from_secs(1);
+        mismatches.mismatch(second, later);
+        mismatches.mismatch(third, later);
+        assert_eq!(2, mismatches.servers.len());
+        assert!(!mismatches.is_insensitive(first, now));
+        assert!(mismatches.is_insensitive(second, later));
+    }
+
+    #[test]
+    fn test_reachable() {
+        let mut resolver = Resolver::new(
+            Vec::new(),
+            Cache::new(Default::default()),
+            Taps {
+                capture: None,
+                dnstap: None,
+            },
+            ResolverConfig::default(),
+        );
+        let v4 = IpAddr::from([192, 0, 2, 1]);
+        let v6 = IpAddr::from(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1));
+
+        resolver.ipv6 = false;
+        assert_eq!(vec![v4], resolver.reachable(&[v6, v4]));
+        assert!(resolver.reachable(&[v6]).is_empty());
+
+        resolver.ipv6 = true;
+        assert_eq!(vec![v6, v4], resolver.reachable(&[v6, v4]));
+    }
+
+    #[test]
+    fn test_follow_alias() {
+        let cname = DnsRecord::CNAME {
+            name: DnsName::from("www.example.com"),
+            class: DnsClass::IN,
+            ttl: 300,
+            canonical_name: DnsName::from("www.example.org"),
+        };
+        let dname = DnsRecord::DNAME {
+            name: DnsName::from("example.org"),
+            class: DnsClass::IN,
+            ttl: 600,
+            target: DnsName::from("example.net"),
+        };
+        let answers = vec![cname.clone(), dname.clone()];
+
+        let name = DnsName::from("WWW.example.com");
+        let (records, target) = follow_alias(&answers, &name, DnsClass::IN).unwrap();
+        assert_eq!(vec![cname], records);
+        assert_eq!(DnsName::from("www.example.org"), target);
+
+        let (records, target) = follow_alias(&answers, &target, DnsClass::IN).unwrap();
+        assert_eq!(DnsName::from("www.example.net"), target);
+        assert_eq!(
+            vec![
+                dname,
+                DnsRecord::CNAME {
+                    name: DnsName::from("www.example.org"),
+                    class: DnsClass::IN,
+                    ttl: 600,
+                    canonical_name: target.clone(),
+                }
+            ],
+            records
+        );
+
+        assert!(follow_alias(&answers, &target, DnsClass::IN).is_none());
+        assert!(follow_alias(&answers, &DnsName::from("example.org"), DnsClass::IN).is_none());
+    }
+
+    fn ns(zone: &str, name_server: &str) -> DnsRecord {
+        DnsRecord::NS {
+            name: DnsName::from(zone),
+            class: DnsClass::IN,
+            ttl: 3600,
+            name_server: DnsName::from(name_server),
+        }
+    }
+
+    fn a(name: &str, address: [u8; 4]) -> DnsRecord {
+        DnsRecord::A {
+            name: DnsName::from(name),
+            class: DnsClass::IN,
+            ttl: 3600,
+            address: address.into(),
+        }
+    }
+
+    #[test]
+    fn test_referral() {
+        let zone = DnsName::from("com");
+        let name = DnsName::from("www.example.com");
+
+        let mut response = DnsPacket::new();
+        response.add_authority(ns("example.com", "ns1.example.com"));
+        response.add_authority(ns("example.com", "ns.example.net"));
+        // Neither towards the name nor below the zone of the server
+        response.add_authority(ns("other.com", "ns1.other.com"));
+        response.add_authority(ns("example.org", "ns1.example.org"));
+        response.add_record(a("ns1.example.com", [192, 0, 2, 1]));
+        // Out of the zone of the server, or not one of the name servers of the delegation
+        response.add_record(a("ns.example.net", [203, 0, 113, 1]));
+        response.add_record(a("ns1.other.com", [198, 51, 100, 1]));
+        // Not an address
+        response.add_record(DnsRecord::CNAME {
+            name: DnsName::from("ns1.example.com"),
+            class: DnsClass::IN,
+            ttl: 3600,
+            canonical_name: DnsName::from("host.example.com"),
+        });
+
+        assert_eq!(
+            Some(Referral {
+                zone: DnsName::from("example.com"),
+                name_servers: vec![
+                    DnsName::from("ns1.example.com"),
+                    DnsName::from("ns.example.net")
+                ],
+                glue: vec![IpAddr::from([192, 0, 2, 1])],
+            }),
+            referral(&response, &zone, &name)
+        );
+
+        // A delegation back up to the zone of the server, or across to another zone
+        let mut upward = DnsPacket::new();
+        upward.add_authority(ns("com", "a.gtld-servers.net"));
+        upward.add_authority(ns("example.org", "ns1.example.org"));
+        assert_eq!(None, referral(&upward, &zone, &name));
+    }
+
+    #[test]
+    fn test_scrub() {
+        let mut response = DnsPacket::new();
+        response.add_answer(a("www.example.com", [192, 0, 2, 1]));
+        response.add_answer(a("www.example.net", [192, 0, 2, 2]));
+        response.add_authority(ns("example.com", "ns1.example.com"));
+        response.add_authority(ns("com", "a.gtld-servers.net"));
+        response.add_record(a("ns1.example.com", [192, 0, 2, 53]));
+        response.add_record(a("a.gtld-servers.net", [192, 5, 6, 30]));
+
+        scrub(&mut response, &DnsName::from("Example.com"));
+        assert_eq!(
+            &vec![a("www.example.com", [192, 0, 2, 1])],
+            response.answers()
+        );
+        assert_eq!(
+            &vec![ns("example.com", "ns1.example.com")],
+            response.authorities()
+        );
+        assert_eq!(
+            &vec![a("ns1.example.com", [192, 0, 2, 53])],
+            response.additional_records()
+        );
+    }
+
+    #[test]
+    fn test_budget() {
+        let budget = Budget::new(&ResolverConfig {
+            max_upstream_queries: 2,
+            resolution_timeout: Duration::from_secs(10),
+            ..Default::default()
+        });
+        let now = Instant::now();
+        assert!(budget.spend(now).unwrap() <= Duration::from_secs(10));
+        assert!(budget.spend(now + Duration::from_secs(9)).unwrap() <= Duration::from_secs(1));
+        assert!(budget.spend(now).is_err());
+
+        let budget = Budget::new(&ResolverConfig::default());
+        assert!(budget.spend(now + Duration::from_secs(11)).is_err());
+        assert_eq!(DEFAULT_MAX_UPSTREAM_QUERIES, budget.queries.get());
+    }
+
+    #[test]
+    fn test_minimised_name() {
+        let name = DnsName::from("www.example.com");
+        assert_eq!(DnsName::from("com"), minimised_name(&name, 0, 0));
+        assert_eq!(DnsName::from("example.com"), minimised_name(&name, 1, 1));
+        assert_eq!(name, minimised_name(&name, 2, 2));
+        assert_eq!(name, minimised_name(&name, 3, 3));
+
+        // Past the first labels, the rest of the name is split into the remaining iterations
+        let labels: Vec<String> = (0..20).map(|i| format!("l{}", i)).collect();
+        let long = DnsName::from(&*labels.join("."));
+        let mut known = 0;
+        let mut iterations = 0;
+        while known < long.labels().len() {
+            let child = minimised_name(&long, known, iterations);
+            assert!(child.labels().len() > known);
+            assert!(long.is_subdomain_of(&child));
+            known = child.labels().len();
+            iterations += 1;
+        }
+        assert_eq!(MAX_MINIMISE_COUNT, iterations);
+        assert_eq!(long, minimised_name(&long, 4, MAX_MINIMISE_COUNT));
+    }
+}
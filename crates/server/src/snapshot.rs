@@ -0,0 +1,191 @@
+use crate::cache::{CacheKey, CachedAnswer, SnapshotEntry};
+use parser::resources::query::DnsQuery;
+use parser::resources::record::DnsRecord;
+use parser::resources::{QueryClass, QueryType};
+use std::convert::TryInto;
+use std::fs;
+use std::io::{Error, ErrorKind, Result};
+use std::path::Path;
+use std::time::{Duration, UNIX_EPOCH};
+
+// A snapshot is the magic and version, followed by the entries until the end of the file:
+// kind (u8), protected (u8), expiry in seconds since the epoch (u64), the key as a question
+// (ANY standing for a whole name), the record count (u16) and the records in wire format
+const MAGIC: &[u8; 4] = b"DNSC";
+const VERSION: u8 = 1;
+
+const KIND_RECORDS: u8 = 0;
+const KIND_NO_DATA: u8 = 1;
+const KIND_NX_DOMAIN: u8 = 2;
+
+fn invalid_data(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
+
+fn encode(entries: &[SnapshotEntry]) -> Result<Vec<u8>> {
+    let to_error = |e| Error::other(format!("{}", e));
+
+    let mut out = MAGIC.to_vec();
+    out.push(VERSION);
+    for entry in entries {
+        let (kind, records) = match &entry.answer {
+            CachedAnswer::Records(records) => (KIND_RECORDS, records.as_slice()),
+            CachedAnswer::NoData(soa) => (KIND_NO_DATA, std::slice::from_ref(soa)),
+            CachedAnswer::NxDomain(soa) => (KIND_NX_DOMAIN, std::slice::from_ref(soa)),
+        };
+        let expires = entry
+            .expires
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let question = DnsQuery {
+            name: entry.key.name.clone(),
+            record_type: entry
+                .key
+                .record_type
+                .map_or(QueryType::ANY, QueryType::Record),
+            class: entry.key.class.into(),
+        };
+
+        out.push(kind);
+        out.push(entry.protected as u8);
+        out.extend_from_slice(&expires.to_be_bytes());
+        out.extend_from_slice(&question.to_bytes().map_err(to_error)?);
+        out.extend_from_slice(&(records.len() as u16).to_be_bytes());
+        for record in records {
+            out.extend_from_slice(&record.to_bytes().map_err(to_error)?);
+        }
+    }
+    Ok(out)
+}
+
+fn decode(bytes: &[u8]) -> Result<Vec<SnapshotEntry>> {
+    if bytes.get(..MAGIC.len()) != Some(&MAGIC[..]) {
+        return Err(invalid_data("Not a cache snapshot"));
+    }
+    if bytes.get(MAGIC.len()) != Some(&VERSION) {
+        return Err(invalid_data("Unsupported cache snapshot version"));
+    }
+
+    let truncated = || invalid_data("Truncated cache snapshot");
+    let mut i = &bytes[MAGIC.len() + 1..];
+    let mut entries = Vec::new();
+    while !i.is_empty() {
+        let header = i.get(..10).ok_or_else(truncated)?;
+        let (kind, protected) = (header[0], header[1] != 0);
+        let expires = u64::from_be_bytes(header[2..10].try_into().unwrap());
+
+        let (rest, question) =
+            DnsQuery::parse(bytes)(&i[10..]).map_err(|_| invalid_data("Invalid cached name"))?;
+        let count = rest.get(..2).ok_or_else(truncated)?;
+        let count = u16::from_be_bytes([count[0], count[1]]);
+        i = &rest[2..];
+
+        let mut records = Vec::new();
+        for _ in 0..count {
+            let (rest, record) =
+                DnsRecord::parse(bytes)(i).map_err(|_| invalid_data("Invalid cached record"))?;
+            records.push(record);
+            i = rest;
+        }
+
+        let record_type = match question.record_type {
+            QueryType::Record(record_type) => Some(record_type),
+            QueryType::ANY => None,
+            _ => return Err(invalid_data("Invalid cached type")),
+        };
+        let class = match question.class {
+            QueryClass::Class(class) => class,
+            _ => return Err(invalid_data("Invalid cached class")),
+        };
+        let answer = match (kind, records.len()) {
+            (KIND_RECORDS, n) if n > 0 => CachedAnswer::Records(records),
+            (KIND_NO_DATA, 1) => CachedAnswer::NoData(records.remove(0)),
+            (KIND_NX_DOMAIN, 1) => CachedAnswer::NxDomain(records.remove(0)),
+            _ => return Err(invalid_data("Invalid cache entry")),
+        };
+
+        entries.push(SnapshotEntry {
+            key: CacheKey {
+                name: question.name.to_lowercase(),
+                record_type,
+                class,
+            },
+            answer,
+            expires: UNIX_EPOCH + Duration::from_secs(expires),
+            protected,
+        });
+    }
+    Ok(entries)
+}
+
+// Written next to the snapshot first, so that a crash while saving keeps the previous one
+pub fn save(path: &Path, entries: &[SnapshotEntry]) -> Result<()> {
+    let temporary = path.with_extension("tmp");
+    fs::write(&temporary, encode(entries)?)?;
+    fs::rename(&temporary, path)
+}
+
+pub fn load(path: &Path) -> Result<Vec<SnapshotEntry>> {
+    decode(&fs::read(path)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parser::resources::name::DnsName;
+    use parser::resources::{DnsClass, DnsRecordType};
+    use std::net::Ipv4Addr;
+
+    #[test]
+    fn test_round_trip() {
+        let expires = UNIX_EPOCH + Duration::from_secs(1_600_000_000);
+        let soa = DnsRecord::SOA {
+            name: DnsName::from("example.com"),
+            class: DnsClass::IN,
+            ttl: 300,
+            mname: DnsName::from("ns1.example.com"),
+            rname: DnsName::from("hostmaster.example.com"),
+            serial: 1,
+            refresh: 7200,
+            retry: 3600,
+            expire: 1209600,
+            minimum: 300,
+        };
+        let entries = vec![
+            SnapshotEntry {
+                key: CacheKey::new(
+                    &DnsName::from("www.example.com"),
+                    DnsRecordType::A,
+                    DnsClass::IN,
+                ),
+                answer: CachedAnswer::Records(vec![DnsRecord::A {
+                    name: DnsName::from("www.example.com"),
+                    class: DnsClass::IN,
+                    ttl: 60,
+                    address: Ipv4Addr::new(192, 0, 2, 1),
+                }]),
+                expires,
+                protected: true,
+            },
+            SnapshotEntry {
+                key: CacheKey {
+                    name: DnsName::from("missing.example.com"),
+                    record_type: None,
+                    class: DnsClass::IN,
+                },
+                answer: CachedAnswer::NxDomain(soa),
+                expires,
+                protected: false,
+            },
+        ];
+
+        let bytes = encode(&entries).unwrap();
+        assert_eq!(entries, decode(&bytes).unwrap());
+
+        let mut future = bytes.clone();
+        future[MAGIC.len()] = VERSION + 1;
+        assert!(decode(&future).is_err());
+        assert!(decode(&bytes[..bytes.len() - 1]).is_err());
+    }
+}
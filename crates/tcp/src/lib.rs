@@ -0,0 +1,189 @@
+use std::io::{ErrorKind, Read, Result, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::Duration;
+use workers::WorkerPool;
+
+// Messages are prefixed by their length on two bytes
+// https://datatracker.ietf.org/doc/html/rfc1035#section-4.2.2
+pub const MAX_MESSAGE_SIZE: usize = u16::MAX as usize;
+
+#[derive(Debug, Clone, Copy)]
+pub struct TcpConfig {
+    // How long a connection may stay idle, and how long a client has to read a response
+    pub idle_timeout: Duration,
+    pub max_connections: usize,
+    // Queries of a connection answered at once at most
+    pub max_pending: usize,
+}
+
+// Answers a message received from a client, None leaving it unanswered
+pub type Handler = dyn Fn(&[u8], SocketAddr) -> Option<Vec<u8>> + Send + Sync;
+
+// A connection among the ones open, freed when dropped
+struct Connection(Arc<AtomicUsize>);
+
+impl Connection {
+    fn open(counter: &Arc<AtomicUsize>, max: usize) -> Option<Self> {
+        counter
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| {
+                if n < max {
+                    Some(n + 1)
+                } else {
+                    None
+                }
+            })
+            .ok()
+            .map(|_| Self(Arc::clone(counter)))
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+// Accepts connections until the listener fails, each one being read on its own thread while its
+// queries are answered by the workers of `pool`. The connections past the limit are closed right
+// away
+// https://datatracker.ietf.org/doc/html/rfc7766#section-6.2.2
+pub fn serve(
+    listener: TcpListener,
+    config: TcpConfig,
+    handler: Arc<Handler>,
+    pool: WorkerPool,
+) -> Result<()> {
+    let open = Arc::new(AtomicUsize::new(0));
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                println!("Error accepting connection: {}", e);
+                continue;
+            }
+        };
+        let connection = match Connection::open(&open, config.max_connections) {
+            Some(connection) => connection,
+            None => continue,
+        };
+
+        // The connection is closed when no thread can be spawned to read it
+        let handler = Arc::clone(&handler);
+        let pool = pool.clone();
+        let spawned = thread::Builder::new().spawn(move || {
+            let _connection = connection;
+            if let Err(e) = handle_connection(stream, config, handler, &pool) {
+                println!("Error on connection: {}", e);
+            }
+        });
+        if let Err(e) = spawned {
+            println!("Error spawning a thread for a connection: {}", e);
+        }
+    }
+    Ok(())
+}
+
+// Queries of a connection being answered, which the connection isn't read past
+struct Pending {
+    count: Mutex<usize>,
+    freed: Condvar,
+}
+
+impl Pending {
+    fn new() -> Self {
+        Self {
+            count: Mutex::new(0),
+            freed: Condvar::new(),
+        }
+    }
+
+    // Waits until fewer than `max` queries are being answered
+    fn acquire(pending: &Arc<Self>, max: usize) -> PendingQuery {
+        let mut count = pending.count.lock().unwrap();
+        while *count >= max.max(1) {
+            count = pending.freed.wait(count).unwrap();
+        }
+        *count += 1;
+        PendingQuery(Arc::clone(pending))
+    }
+
+    fn is_empty(&self) -> bool {
+        *self.count.lock().unwrap() == 0
+    }
+}
+
+// A query being answered, freed when dropped
+struct PendingQuery(Arc<Pending>);
+
+impl Drop for PendingQuery {
+    fn drop(&mut self) {
+        *self.0.count.lock().unwrap() -= 1;
+        self.0.freed.notify_one();
+    }
+}
+
+// Reads the pipelined queries of a connection, so that responses are sent as soon as they are
+// ready, possibly out of order. The connection is closed once the client closes it, when it
+// stayed idle with no query pending, or when it didn't send a whole message or read its responses
+// in time
+// https://datatracker.ietf.org/doc/html/rfc7766#section-6.2.1.1
+fn handle_connection(
+    stream: TcpStream,
+    config: TcpConfig,
+    handler: Arc<Handler>,
+    pool: &WorkerPool,
+) -> Result<()> {
+    let src = stream.peer_addr()?;
+    stream.set_read_timeout(Some(config.idle_timeout))?;
+    stream.set_write_timeout(Some(config.idle_timeout))?;
+    let writer = Arc::new(Mutex::new(stream.try_clone()?));
+    let pending = Arc::new(Pending::new());
+    let mut reader = stream;
+
+    loop {
+        // Only a timeout between two messages leaves the connection open, as the stream can't be
+        // followed anymore once part of a message was read
+        let mut length = [0; 2];
+        match reader.read(&mut length) {
+            Ok(0) => break,
+            Ok(1) => reader.read_exact(&mut length[1..])?,
+            Ok(_) => {}
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                if !pending.is_empty() {
+                    continue;
+                }
+                break;
+            }
+            Err(e) => return Err(e),
+        }
+        let mut message = vec![0; u16::from_be_bytes(length) as usize];
+        reader.read_exact(&mut message)?;
+
+        let query = Pending::acquire(&pending, config.max_pending);
+        let handler = Arc::clone(&handler);
+        let writer = Arc::clone(&writer);
+        let queued = pool.execute(move || {
+            let _query = query;
+            if let Some(response) = handler(&message, src) {
+                // The length and the message are written at once so that they share a segment
+                // https://datatracker.ietf.org/doc/html/rfc7766#section-8
+                let mut bytes = (response.len() as u16).to_be_bytes().to_vec();
+                bytes.extend_from_slice(&response);
+                let mut writer = writer.lock().unwrap();
+                if let Err(e) = writer.write_all(&bytes) {
+                    // The client isn't reading its responses, or only part of one was sent
+                    println!("Error answering {}: {}", src, e);
+                    let _ = writer.shutdown(Shutdown::Both);
+                }
+            }
+        });
+        if !queued {
+            break;
+        }
+    }
+    Ok(())
+}
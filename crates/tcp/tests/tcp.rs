@@ -0,0 +1,150 @@
+use std::io::{ErrorKind, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+use tcp::{Handler, TcpConfig};
+use workers::WorkerPool;
+
+// Messages starting with 1 are answered after this long, the other ones right away
+const SLOW_ANSWER: Duration = Duration::from_millis(300);
+
+fn listen(config: TcpConfig) -> SocketAddr {
+    let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+    let address = listener.local_addr().unwrap();
+    let handler: Arc<Handler> = Arc::new(|message: &[u8], _| {
+        if message.first() == Some(&1) {
+            thread::sleep(SLOW_ANSWER);
+        }
+        Some(message.to_vec())
+    });
+    let pool = WorkerPool::new(4, 16).unwrap();
+    thread::spawn(move || tcp::serve(listener, config, handler, pool));
+    address
+}
+
+fn config() -> TcpConfig {
+    TcpConfig {
+        idle_timeout: Duration::from_secs(5),
+        max_connections: 10,
+        max_pending: 10,
+    }
+}
+
+fn connect(address: SocketAddr) -> TcpStream {
+    let stream = TcpStream::connect(address).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    stream
+}
+
+fn send(stream: &mut TcpStream, message: &[u8]) {
+    let mut bytes = (message.len() as u16).to_be_bytes().to_vec();
+    bytes.extend_from_slice(message);
+    stream.write_all(&bytes).unwrap();
+}
+
+fn receive(stream: &mut TcpStream) -> Vec<u8> {
+    let mut length = [0; 2];
+    stream.read_exact(&mut length).unwrap();
+    let mut message = vec![0; u16::from_be_bytes(length) as usize];
+    stream.read_exact(&mut message).unwrap();
+    message
+}
+
+// The connection was closed by the listener, possibly resetting it
+fn is_closed(stream: &mut TcpStream) -> bool {
+    match stream.read(&mut [0; 1]) {
+        Ok(0) => true,
+        Err(e) => e.kind() == ErrorKind::ConnectionReset,
+        Ok(_) => false,
+    }
+}
+
+#[test]
+fn test_pipelined_queries_are_answered_out_of_order() {
+    let address = listen(config());
+    let mut stream = connect(address);
+    send(&mut stream, &[1, 0xAA]);
+    send(&mut stream, &[2, 0xBB]);
+
+    assert_eq!(receive(&mut stream), [2, 0xBB]);
+    assert_eq!(receive(&mut stream), [1, 0xAA]);
+}
+
+#[test]
+fn test_pending_queries_are_capped_per_connection() {
+    let address = listen(TcpConfig {
+        max_pending: 1,
+        ..config()
+    });
+    let mut stream = connect(address);
+    send(&mut stream, &[1, 0xAA]);
+    send(&mut stream, &[2, 0xBB]);
+
+    // The second query isn't read before the first one is answered
+    assert_eq!(receive(&mut stream), [1, 0xAA]);
+    assert_eq!(receive(&mut stream), [2, 0xBB]);
+}
+
+#[test]
+fn test_idle_connection_is_closed() {
+    let address = listen(TcpConfig {
+        idle_timeout: Duration::from_millis(200),
+        ..config()
+    });
+    let mut stream = connect(address);
+    send(&mut stream, &[2, 0xBB]);
+    assert_eq!(receive(&mut stream), [2, 0xBB]);
+
+    let start = Instant::now();
+    assert!(is_closed(&mut stream));
+    assert!(start.elapsed() < Duration::from_secs(5));
+}
+
+#[test]
+fn test_idle_connection_is_kept_while_queries_are_pending() {
+    let address = listen(TcpConfig {
+        idle_timeout: Duration::from_millis(100),
+        ..config()
+    });
+    let mut stream = connect(address);
+    send(&mut stream, &[1, 0xAA]);
+
+    assert_eq!(receive(&mut stream), [1, 0xAA]);
+    assert!(is_closed(&mut stream));
+}
+
+#[test]
+fn test_partial_length_closes_the_connection() {
+    let address = listen(TcpConfig {
+        idle_timeout: Duration::from_millis(200),
+        ..config()
+    });
+    let mut stream = connect(address);
+    send(&mut stream, &[1, 0xAA]);
+    stream.write_all(&[0]).unwrap();
+
+    // The query that was read is still answered
+    assert_eq!(receive(&mut stream), [1, 0xAA]);
+    assert!(is_closed(&mut stream));
+}
+
+#[test]
+fn test_connections_past_the_cap_are_closed() {
+    let address = listen(TcpConfig {
+        max_connections: 1,
+        ..config()
+    });
+    let mut first = connect(address);
+    send(&mut first, &[2, 0xBB]);
+    assert_eq!(receive(&mut first), [2, 0xBB]);
+
+    let mut second = connect(address);
+    assert!(is_closed(&mut second));
+
+    // The first connection is still served
+    send(&mut first, &[2, 0xCC]);
+    assert_eq!(receive(&mut first), [2, 0xCC]);
+}
@@ -0,0 +1,98 @@
+use std::io::{Error, ErrorKind, Result};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+type Job = Box<dyn FnOnce() + Send>;
+
+// Fixed set of threads running the jobs of a bounded queue, so that a burst of queries can't
+// spawn threads without bound. The threads stop once every handle to the pool is dropped
+#[derive(Clone)]
+pub struct WorkerPool {
+    jobs: SyncSender<Job>,
+}
+
+impl WorkerPool {
+    // Spawns `workers` threads, the queue holding at most `queue_size` jobs waiting for them
+    pub fn new(workers: usize, queue_size: usize) -> Result<Self> {
+        if workers == 0 {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "A pool needs at least one worker",
+            ));
+        }
+
+        let (jobs, queue) = mpsc::sync_channel(queue_size);
+        let queue = Arc::new(Mutex::new(queue));
+        for _ in 0..workers {
+            let queue = Arc::clone(&queue);
+            thread::Builder::new().spawn(move || run(&queue))?;
+        }
+        Ok(Self { jobs })
+    }
+
+    // Queues the job unless the queue is full, in which case it is dropped and false returned
+    pub fn try_execute<F: FnOnce() + Send + 'static>(&self, job: F) -> bool {
+        match self.jobs.try_send(Box::new(job)) {
+            Ok(()) => true,
+            Err(TrySendError::Full(_)) | Err(TrySendError::Disconnected(_)) => false,
+        }
+    }
+
+    // Queues the job, waiting for room in the queue
+    pub fn execute<F: FnOnce() + Send + 'static>(&self, job: F) -> bool {
+        self.jobs.send(Box::new(job)).is_ok()
+    }
+}
+
+fn run(queue: &Mutex<Receiver<Job>>) {
+    loop {
+        let next = queue.lock().unwrap().recv();
+        match next {
+            // A job that panics doesn't take its worker down with it
+            Ok(job) => {
+                let _ = panic::catch_unwind(AssertUnwindSafe(job));
+            }
+            Err(_) => break,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc::channel;
+    use std::time::Duration;
+
+    #[test]
+    fn test_drops_jobs_past_the_queue_size() {
+        let pool = WorkerPool::new(1, 2).unwrap();
+        let (release, blocked) = channel::<()>();
+        let (started, wait_started) = channel();
+        assert!(pool.try_execute(move || {
+            started.send(()).unwrap();
+            let _ = blocked.recv();
+        }));
+        wait_started.recv().unwrap();
+
+        // The only worker is busy, so two jobs fit in the queue and the third one is dropped
+        let (done, finished) = channel();
+        for i in 0..2 {
+            let done = done.clone();
+            assert!(pool.try_execute(move || done.send(i).unwrap()));
+        }
+        assert!(!pool.try_execute(|| unreachable!()));
+
+        release.send(()).unwrap();
+        assert_eq!(finished.recv_timeout(Duration::from_secs(5)), Ok(0));
+        assert_eq!(finished.recv_timeout(Duration::from_secs(5)), Ok(1));
+        assert!(pool.try_execute(move || done.send(2).unwrap()));
+        assert_eq!(finished.recv_timeout(Duration::from_secs(5)), Ok(2));
+    }
+
+    #[test]
+    fn test_requires_a_worker() {
+        assert!(WorkerPool::new(0, 1).is_err());
+    }
+}